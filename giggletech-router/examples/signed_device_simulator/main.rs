@@ -0,0 +1,50 @@
+//! Reference verifier for signed device commands.
+//!
+//! Listens like a GiggleTech device and checks every incoming message the way the firmware should
+//! when an `hmac_key` is configured: unsigned, forged and replayed packets are rejected.
+//!
+//! ```text
+//! cargo run --example signed_device_simulator -- <shared key> [listen address]
+//! ```
+//!
+//! The listen address defaults to `0.0.0.0:8888`. Point a device entry in `config.yml` at the
+//! machine running the simulator with the same `hmac_key` to try it out. The checks themselves are
+//! in `verifier.rs`, which the router's tests also run against.
+
+use async_osc::{prelude::*, OscSocket};
+use async_std::stream::StreamExt;
+
+mod verifier;
+
+use verifier::Verifier;
+
+#[async_std::main]
+async fn main() -> async_osc::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let key = match args.next() {
+        Some(key) => key,
+        None => {
+            eprintln!("Usage: signed_device_simulator <shared key> [listen address]");
+            std::process::exit(1);
+        }
+    };
+    let address = args.next().unwrap_or_else(|| "0.0.0.0:8888".to_string());
+
+    let mut verifier = Verifier::new(&key);
+    let mut socket = OscSocket::bind(&address).await?;
+    println!("Simulated device listening on {}", address);
+
+    while let Some(packet) = socket.next().await {
+        let (packet, peer) = packet?;
+        let message = match packet.into_message() {
+            Some(message) => message,
+            None => continue,
+        };
+        match verifier.verify(&message) {
+            Ok(args) => println!("ACCEPT {} {} {:?}", peer, message.addr, args),
+            Err(rejection) => println!("REJECT {} {} {}", peer, message.addr, rejection),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! The checks a device with an `hmac_key` runs on every incoming message.
+
+use async_osc::{OscMessage, OscPacket, OscType};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub enum Rejection {
+    Unsigned,
+    BadSignature,
+    Replayed { counter: i64, last: i64 },
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Unsigned => write!(f, "unsigned"),
+            Rejection::BadSignature => write!(f, "bad signature"),
+            Rejection::Replayed { counter, last } => write!(f, "replayed (counter {} <= {})", counter, last),
+        }
+    }
+}
+
+pub struct Verifier {
+    key: Vec<u8>,
+    // The router sends from a new port for every message, so the counter is tracked per key
+    last_counter: Option<i64>,
+}
+
+impl Verifier {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.as_bytes().to_vec(),
+            last_counter: None,
+        }
+    }
+
+    // Returns the original arguments if the message is signed correctly and not a replay
+    pub fn verify(&mut self, message: &OscMessage) -> Result<Vec<OscType>, Rejection> {
+        let (signature, counter) = match message.args.as_slice() {
+            [.., OscType::Long(counter), OscType::Blob(signature)] => (signature, *counter),
+            _ => return Err(Rejection::Unsigned),
+        };
+
+        // The signature covers the message without the blob
+        let signed = OscMessage {
+            addr: message.addr.clone(),
+            args: message.args[..message.args.len() - 1].to_vec(),
+        };
+        let encoded = rosc::encoder::encode(&OscPacket::Message(signed))
+            .map_err(|_| Rejection::BadSignature)?;
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(&encoded);
+        mac.verify_slice(signature).map_err(|_| Rejection::BadSignature)?;
+
+        if let Some(last) = self.last_counter {
+            if counter <= last {
+                return Err(Rejection::Replayed { counter, last });
+            }
+        }
+        self.last_counter = Some(counter);
+
+        Ok(message.args[..message.args.len() - 2].to_vec())
+    }
+}
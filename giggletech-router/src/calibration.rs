@@ -0,0 +1,335 @@
+/*
+    calibration.rs - Interactive Motor Calibration
+
+    Finding `min_speed`, `start_tx` and `max_speed` for a motor used to be trial and error in
+    config.yml. The calibration wizard steps a chosen device through output levels, asks the user
+    to press Enter when the motor starts, stops and becomes uncomfortable, and writes the results
+    back into that device's entry.
+
+    **Steps:**
+
+    1. **Start**: From standstill the output rises until the motor starts. This level, plus one step
+       of margin, is the start kick (`start_tx`).
+    2. **Stop**: After a kick the output falls until the motor stops. The last level that still ran
+       is the stall threshold, saved as `min_speed` and `stall_tx` (used by pulse mode).
+    3. **Uncomfortable**: The output rises from the stall threshold until the user finds it
+       uncomfortable. The level before is the comfortable maximum (`max_speed`). Without an answer
+       the full motor range is comfortable.
+
+    **Key Features:**
+
+    1. **Keep-Alive**:
+       - The current level is resent every `KEEPALIVE` while waiting for an answer.
+
+    2. **Write-Back (`set_device_keys`)**:
+       - Only the lines of the calibrated keys in the device's entry are changed or added, so
+         comments and the rest of config.yml stay as they were. The previous file is kept as
+         config.yml.bak.
+
+    3. **Signing**:
+       - Levels are signed with the device's `hmac_key` like the router's own messages.
+
+    **Usage**:
+    - `async-osc calibrate <device ip>`. Type `q` and Enter at any time to abort without saving.
+*/
+
+use async_std::channel::{self, Receiver};
+use async_std::future;
+use async_std::task;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::config::{self, DeviceConfig};
+use crate::config_reload;
+use crate::data_processing;
+use crate::giggletech_osc;
+use crate::paths;
+
+// Motor Tx change per step
+const STEP: i32 = 3;
+// Time at each level
+const STEP_TIME: Duration = Duration::from_millis(1000);
+// Resend interval while waiting at a level
+const KEEPALIVE: Duration = Duration::from_millis(250);
+// Kick before stepping down to the stall threshold
+const KICK_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Calibration {
+    start_tx: i32,
+    stall_tx: i32,
+    comfortable_tx: i32
+}
+
+enum Answer {
+    Pressed,
+    Quit
+}
+
+// Lines typed by the user, read on a separate thread so the motor keeps being driven
+fn spawn_input() -> Receiver<String> {
+    let (sender, receiver) = channel::unbounded();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        while std::io::stdin().read_line(&mut line).map(|read| read > 0).unwrap_or(false) {
+            if task::block_on(sender.send(line.trim().to_lowercase())).is_err() {
+                return;
+            }
+            line.clear();
+        }
+    });
+    receiver
+}
+
+// Hold a level for one step, returns the user's answer if there was one
+async fn hold_level(device: &DeviceConfig, tx: i32, input: &Receiver<String>) -> Result<Option<Answer>, String> {
+    let until = Instant::now() + STEP_TIME;
+    loop {
+        giggletech_osc::send_data(&device.device_uri, tx as f32, device.output_format).await.map_err(|e| format!("Failed to send to {}: {}", device.device_uri, e))?;
+        let wait = until.saturating_duration_since(Instant::now()).min(KEEPALIVE);
+        if wait.is_zero() {
+            return Ok(None);
+        }
+        match future::timeout(wait, input.recv()).await {
+            Ok(Ok(line)) if line == "q" => return Ok(Some(Answer::Quit)),
+            Ok(Ok(_)) => return Ok(Some(Answer::Pressed)),
+            Ok(Err(_)) => return Err("Input closed".to_string()),
+            Err(_) => {}
+        }
+    }
+}
+
+// Step through the levels until the user presses Enter, returns the level at that moment
+async fn step_until_answer(device: &DeviceConfig, levels: impl Iterator<Item = i32>, input: &Receiver<String>) -> Result<Option<i32>, String> {
+    for tx in levels {
+        print!("\r  Motor Tx: {:>3} ", tx);
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        match hold_level(device, tx, input).await? {
+            Some(Answer::Pressed) => return Ok(Some(tx)),
+            Some(Answer::Quit) => return Err("Calibration aborted".to_string()),
+            None => {}
+        }
+    }
+    Ok(None)
+}
+
+fn drain(input: &Receiver<String>) {
+    while input.try_recv().is_ok() {}
+}
+
+async fn wait_for_enter(input: &Receiver<String>) -> Result<(), String> {
+    drain(input);
+    match input.recv().await {
+        Ok(line) if line == "q" => Err("Calibration aborted".to_string()),
+        Ok(_) => Ok(()),
+        Err(_) => Err("Input closed".to_string()),
+    }
+}
+
+async fn measure(device: &DeviceConfig, input: &Receiver<String>) -> Result<Calibration, String> {
+    let full_tx = data_processing::speed_tx(1.0, device).floor() as i32;
+
+    println!("\nStep 1/3: The motor will slowly speed up from standstill.");
+    println!("Press Enter as soon as the motor STARTS spinning. Press Enter to begin.");
+    wait_for_enter(input).await?;
+    let start = step_until_answer(device, (0..=full_tx).step_by(STEP as usize), input).await?
+        .ok_or_else(|| "The motor never started".to_string())?;
+    let start_tx = (start + STEP).min(full_tx);
+    giggletech_osc::send_data(&device.device_uri, 0.0, device.output_format).await.map_err(|e| e.to_string())?;
+
+    println!("\n\nStep 2/3: The motor will start and slowly slow down.");
+    println!("Press Enter as soon as the motor STOPS spinning. Press Enter to begin.");
+    wait_for_enter(input).await?;
+    giggletech_osc::send_data(&device.device_uri, start_tx as f32, device.output_format).await.map_err(|e| e.to_string())?;
+    task::sleep(KICK_TIME).await;
+    let stop = step_until_answer(device, (0..=start).rev().step_by(STEP as usize), input).await?.unwrap_or(0);
+    let stall_tx = (stop + STEP).min(full_tx);
+    giggletech_osc::send_data(&device.device_uri, 0.0, device.output_format).await.map_err(|e| e.to_string())?;
+
+    println!("\n\nStep 3/3: The motor will slowly speed up to full power.");
+    println!("Press Enter as soon as it becomes UNCOMFORTABLE. Press Enter to begin.");
+    wait_for_enter(input).await?;
+    giggletech_osc::send_data(&device.device_uri, start_tx as f32, device.output_format).await.map_err(|e| e.to_string())?;
+    task::sleep(KICK_TIME).await;
+    let comfortable_tx = match step_until_answer(device, (stall_tx..=full_tx).step_by(STEP as usize), input).await? {
+        Some(uncomfortable) => (uncomfortable - STEP).max(stall_tx),
+        None => full_tx,
+    };
+    giggletech_osc::send_data(&device.device_uri, 0.0, device.output_format).await.map_err(|e| e.to_string())?;
+
+    Ok(Calibration { start_tx, stall_tx, comfortable_tx })
+}
+
+// Settings written to the device's entry, speeds in percent
+fn settings(calibration: &Calibration, device: &DeviceConfig) -> Vec<(&'static str, String)> {
+    let min_speed = (data_processing::tx_speed(calibration.stall_tx as f32, device) * 100.0).ceil();
+    let max_speed = (data_processing::tx_speed(calibration.comfortable_tx as f32, device) * 100.0).floor().max(min_speed);
+    vec![
+        ("min_speed", format!("{:.0}", min_speed)),
+        ("max_speed", format!("{:.0}", max_speed)),
+        ("start_tx", calibration.start_tx.to_string()),
+        ("stall_tx", calibration.stall_tx.to_string()),
+    ]
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn is_content(line: &str) -> bool {
+    let trimmed = line.trim();
+    !trimmed.is_empty() && !trimmed.starts_with('#')
+}
+
+// Set keys in the `devices` entry with the given ip, keeping everything else of the file
+fn set_device_keys(config_text: &str, ip: &str, settings: &[(&str, String)]) -> Result<String, String> {
+    let mut lines = config_text.lines().map(|line| line.to_string()).collect::<Vec<_>>();
+    let devices = lines.iter().position(|line| line.trim_end() == "devices:")
+        .ok_or_else(|| "config.yml has no devices section".to_string())?;
+
+    // Entry start: a list item of the devices section with this ip
+    let matches_ip = |line: &str| {
+        let item = line.trim_start().trim_start_matches('-').trim_start();
+        item.strip_prefix("ip:").map(|value| value.split('#').next().unwrap_or("").trim().trim_matches(|c| c == '"' || c == '\'') == ip).unwrap_or(false)
+    };
+    let mut entry_start = None;
+    let mut ip_line = None;
+    for (i, line) in lines.iter().enumerate().skip(devices + 1) {
+        if is_content(line) && indent_of(line) == 0 {
+            break;
+        }
+        if line.trim_start().starts_with('-') {
+            entry_start = Some(i);
+        }
+        if is_content(line) && matches_ip(line) {
+            ip_line = Some(i);
+            break;
+        }
+    }
+    let (entry_start, ip_line) = match (entry_start, ip_line) {
+        (Some(entry_start), Some(ip_line)) => (entry_start, ip_line),
+        _ => return Err(format!("No device with ip {} in config.yml", ip)),
+    };
+    let item_indent = indent_of(&lines[entry_start]);
+    let key_indent = if ip_line == entry_start {
+        let item = &lines[entry_start];
+        item.len() - item.trim_start().trim_start_matches('-').trim_start().len()
+    } else {
+        indent_of(&lines[ip_line])
+    };
+    let mut entry_end = lines.iter().enumerate().skip(entry_start + 1)
+        .find(|(_, line)| is_content(line) && (indent_of(line) < key_indent || (indent_of(line) == item_indent && line.trim_start().starts_with('-'))))
+        .map(|(i, _)| i)
+        .unwrap_or(lines.len());
+
+    let mut insert_at = ip_line + 1;
+    for (key, value) in settings {
+        let prefix = format!("{}:", key);
+        let existing = (entry_start..entry_end).find(|i| {
+            let line = &lines[*i];
+            let content = if *i == entry_start { line.trim_start().trim_start_matches('-').trim_start() } else { line.trim_start() };
+            content.starts_with(&prefix) && (*i == entry_start || indent_of(line) == key_indent)
+        });
+        match existing {
+            Some(i) => {
+                let line = &lines[i];
+                let at = line.find(&prefix).unwrap_or(0);
+                lines[i] = format!("{}{} {}", &line[..at], prefix, value);
+            }
+            None => {
+                lines.insert(insert_at, format!("{}{} {}", " ".repeat(key_indent), prefix, value));
+                insert_at += 1;
+                entry_end += 1;
+            }
+        }
+    }
+
+    let mut result = lines.join("\n");
+    if config_text.ends_with('\n') {
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+fn write_back(device: &DeviceConfig, settings: &[(&str, String)]) -> Result<(), String> {
+    let config_path = paths::config_path();
+    let config_text = fs::read_to_string(&config_path).map_err(|e| format!("Failed to read {}: {}", config_path.display(), e))?;
+    let updated = set_device_keys(&config_text, &device.device_uri, settings)?;
+    let mut backup = config_path.clone().into_os_string();
+    backup.push(".bak");
+    fs::write(&backup, &config_text).map_err(|e| format!("Failed to back up {}: {}", config_path.display(), e))?;
+    fs::write(&config_path, updated).map_err(|e| format!("Failed to write {}: {}", config_path.display(), e))
+}
+
+pub(crate) async fn run(ip: &str) -> Result<(), String> {
+    let (_, devices) = config::check_config()?;
+    let device = devices.into_iter()
+        .find(|device| *device.device_uri == ip)
+        .ok_or_else(|| format!("No device with ip {} in config.yml (groups are calibrated per member)", ip))?;
+    // A device with an hmac_key drops unsigned messages
+    config_reload::register_keys(&device);
+
+    println!("\nCalibrating {}. Type q and Enter at any time to abort.", device.device_uri);
+    let input = spawn_input();
+    let result = measure(&device, &input).await;
+    // Never leave the motor running
+    let _ = giggletech_osc::send_data(&device.device_uri, 0.0, device.output_format).await;
+    let calibration = result?;
+
+    let settings = settings(&calibration, &device);
+    println!("\n\nCalibration for {}:", device.device_uri);
+    println!("  Start kick: Tx {}", calibration.start_tx);
+    println!("  Stall threshold: Tx {}", calibration.stall_tx);
+    println!("  Comfortable max: Tx {}", calibration.comfortable_tx);
+    for (key, value) in settings.iter() {
+        println!("  {}: {}", key, value);
+    }
+
+    println!("\nWrite these settings to config.yml? [y/N]");
+    drain(&input);
+    match input.recv().await {
+        Ok(answer) if answer == "y" || answer == "yes" => {
+            write_back(&device, &settings)?;
+            println!("Saved to config.yml (previous version in config.yml.bak).");
+        }
+        _ => println!("Not saved."),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "devices:
+  - ip: 192.168.1.10
+    proximity_parameter: a
+    # tuned by hand
+    min_speed: 5
+  - ip: \"192.168.1.11\"
+    proximity_parameter: b
+
+setup:
+  port_rx: 9001
+";
+
+    fn settings() -> Vec<(&'static str, String)> {
+        vec![("min_speed", "12".to_string()), ("start_tx", "30".to_string())]
+    }
+
+    #[test]
+    fn replaces_existing_and_adds_missing_keys() {
+        let updated = set_device_keys(CONFIG, "192.168.1.10", &settings()).unwrap();
+        assert!(updated.contains("  - ip: 192.168.1.10\n    start_tx: 30\n    proximity_parameter: a\n    # tuned by hand\n    min_speed: 12\n"));
+        // The other device is untouched
+        assert!(updated.contains("  - ip: \"192.168.1.11\"\n    proximity_parameter: b\n\nsetup:"));
+    }
+
+    #[test]
+    fn only_touches_the_matching_entry() {
+        let updated = set_device_keys(CONFIG, "192.168.1.11", &settings()).unwrap();
+        assert!(updated.contains("    # tuned by hand\n    min_speed: 5\n"));
+        assert!(updated.contains("  - ip: \"192.168.1.11\"\n    min_speed: 12\n    start_tx: 30\n    proximity_parameter: b\n"));
+        assert!(set_device_keys(CONFIG, "192.168.1.12", &settings()).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+/*
+    cli.rs - Command-Line Interface
+
+    Subcommands and options of the router. Without a subcommand the router runs, so a double-clicked
+    executable behaves like before.
+
+    **Commands:**
+
+    - `run`: Run the router (default).
+    - `monitor`: Run the router with the live dashboard (see `dashboard`).
+    - `validate`: Check config.yml and exit, with exit code 1 if it is invalid.
+    - `devices`: Print the configured devices and groups.
+    - `test-device <ip>`: Ping a configured device and run its motor briefly.
+    - `stop-all`: Send a stop to every configured device.
+    - `calibrate <ip>`: Interactive motor calibration (see `calibration`).
+    - `history`: Summary or export of past sessions (see `session_stats`).
+
+    **Key Features:**
+
+    1. **Locations (`Cli::locations`)**:
+       - `--config <file>` and `--log-dir <folder>` work with every command. Without them the
+         default locations of `paths` are used.
+
+    2. **One-Shot Commands (`Command::runs_router`)**:
+       - Commands other than `run`, `monitor` and `calibrate` print their result to the console and
+         don't write a log file.
+*/
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::paths::Locations;
+
+#[derive(Debug, Parser)]
+#[command(about = "GiggleTech OSC Router: drives haptic devices from VRChat avatar parameters")]
+pub(crate) struct Cli {
+    /// Config file [default: ./config.yml, on Linux ~/.config/giggletech/config.yml if there is none]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Folder of the log files, takes precedence over log_dir in config.yml
+    #[arg(long, global = true, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Same as the monitor command
+    #[arg(long, global = true, hide = true)]
+    pub dashboard: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+pub(crate) enum Command {
+    /// Run the router (default)
+    Run,
+    /// Run the router with the live dashboard
+    Monitor,
+    /// Check the config file and exit
+    Validate,
+    /// Print the configured devices and groups
+    Devices,
+    /// Ping a configured device and run its motor briefly
+    TestDevice {
+        /// Device ip (a device or a group member)
+        ip: String,
+        /// Motor level in % of the full range
+        #[arg(long, default_value_t = 30.0)]
+        level: f32,
+        /// How long the motor runs
+        #[arg(long, default_value_t = 1.0)]
+        seconds: f32
+    },
+    /// Send a stop to every configured device
+    StopAll,
+    /// Calibrate the motor of a device interactively
+    Calibrate {
+        /// Device ip
+        ip: String
+    },
+    /// Summarise or export past sessions
+    History {
+        /// Export all sessions to a CSV file
+        #[arg(long, value_name = "FILE")]
+        csv: Option<PathBuf>,
+        /// Export all sessions to a JSON file
+        #[arg(long, value_name = "FILE")]
+        json: Option<PathBuf>
+    }
+}
+
+impl Command {
+    // Whether the command writes the log file like the router
+    pub fn runs_router(&self) -> bool {
+        matches!(self, Command::Run | Command::Monitor | Command::Calibrate { .. })
+    }
+}
+
+impl Cli {
+    // The command to run, `--dashboard` turns `run` into `monitor`
+    pub fn command(&self) -> Command {
+        match &self.command {
+            None | Some(Command::Run) if self.dashboard => Command::Monitor,
+            None => Command::Run,
+            Some(command) => command.clone(),
+        }
+    }
+
+    pub fn locations(&self) -> Locations {
+        Locations::find(self.config.clone(), self.log_dir.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("async-osc").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn no_command_runs_the_router() {
+        assert_eq!(parse(&[]).command(), Command::Run);
+        assert_eq!(parse(&["--dashboard"]).command(), Command::Monitor);
+    }
+
+    #[test]
+    fn options_work_before_and_after_the_command() {
+        let cli = parse(&["--config", "/etc/giggletech.yml", "test-device", "192.168.1.69", "--level", "50", "--log-dir", "/tmp/logs"]);
+        assert_eq!(cli.config, Some(PathBuf::from("/etc/giggletech.yml")));
+        assert_eq!(cli.log_dir, Some(PathBuf::from("/tmp/logs")));
+        assert_eq!(cli.command(), Command::TestDevice { ip: "192.168.1.69".to_string(), level: 50.0, seconds: 1.0 });
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        assert!(Cli::try_parse_from(["async-osc", "start"]).is_err());
+        assert!(Cli::try_parse_from(["async-osc", "calibrate"]).is_err());
+    }
+}
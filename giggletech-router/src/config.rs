@@ -9,7 +9,11 @@
     **Key Features:**
     
     1. **Loading Configuration (`load_config`)**:
-       - Reads the `config.yml` file and parses it into a structure using YAML.
+       - Reads the `config.yml` file into the typed model in `config/model.rs`, which also declares
+         every default value. The file is the one picked by `paths` (`--config`, the current folder or
+         the XDG config folder), and `pattern_dir` and `script` are relative to its folder.
+       - `check_config` reads it without resolving an OSCQuery port, for `validate` and the other
+         one-shot commands. `device_map` lists every device for the startup log and `devices`.
        - Extracts global and device-specific settings.
        - Displays a banner with device information and listens for OSC messages on a defined port.
 
@@ -20,12 +24,83 @@
          - `port_rx`: The OSC port (either a fixed value or dynamically assigned via OSCQuery).
          - `default_min_speed` & `default_max_speed`: Speed limits used for device control.
          - `timeout`, `velocity control`, and `proximity settings`.
+       - An invalid `default_*` setting is an error, the same as the setting on a device.
 
     3. **Device-Specific Configuration (`DeviceConfig`)**:
        - Each device can have custom parameters, but if not specified, they inherit from global settings.
        - The function `parse_device_config` processes each device's configuration, allowing custom IP addresses, 
          speed settings, and proximity parameters for each individual device.
 
+    4. **Combined Inputs**:
+       - `proximity_parameter` may be a list of parameters, merged with the `combine` mode
+         (`max`, `sum`, `weighted` with `weights`, or `latest`) before processing.
+
+    5. **Response Curves**:
+       - `curve`, `curve_strength` and `curve_table` shape proximity before it is mapped to motor speed,
+         with `default_curve*` in `setup`. `curve_parameter` switches the curve at runtime.
+
+    6. **Input Filters**:
+       - `filter` (`none`, `ema`, `median` or `one_euro`) and its parameters smooth proximity before
+         processing, with `default_filter*` in `setup`.
+
+    7. **Signed Commands**:
+       - A device (or group member) with an `hmac_key` receives HMAC-signed OSC messages, see `osc_auth`.
+
+    8. **Device Groups (`groups`)**:
+       - A group drives several devices from a single proximity parameter. The group is processed once
+         like any other device and the result is fanned out to its members, each with its own gain and delay.
+       - Groups take the same settings as devices, but list `members` instead of a single `ip`.
+
+    9. **Haptic Patterns (`patterns`)**:
+       - Each entry plays a built-in or file pattern when its `parameter` turns on, mixed with or
+         overriding live output (`mode`). Pattern files are looked up in `pattern_dir` from `setup`.
+
+    10. **Control Modes (`control_mode`)**:
+       - `proximity`, `velocity`, `hybrid` or `script`, with `default_control_mode` in `setup`. Without it
+         the mode follows `script` and `use_velocity_control`. Hybrid blends a proximity baseline with a
+         velocity boost using `proximity_weight` and `velocity_weight`.
+
+    11. **Scripted Mappings (`script`)**:
+       - A device with a `script` file is driven by that Rhai script instead of the built-in proximity
+         or velocity mapping, see `scripting`. The selected mapping is the device's `ControlMode`.
+
+    12. **Contact Gate**:
+       - `dead_zone`, `hysteresis`, `min_on_time` and `min_off_time` decide when a contact starts and
+         stops, with `default_*` in `setup`, see `contact_gate`.
+
+    13. **Motor Governor**:
+       - `thermal_time_constant`, `max_heat`, `max_runtime`, `rest_time`, `duty_cycle` and `duty_window`
+         protect each motor (every member of a group) in every mapping mode, with `default_*` in `setup`,
+         see `governor`.
+
+    14. **Envelopes**:
+       - `attack`, `decay`, `sustain`, `release` and `max_slew_rate` shape the output over time, with
+         `default_*` in `setup`, see `envelope`.
+
+    15. **Pulse Mode**:
+       - With `pulse_mode`, values below `stall_tx` are rendered as bursts at `pulse_tx` every
+         `pulse_period` ms, see `pulse`.
+
+    16. **Gestures (`gestures`)**:
+       - Maps `tap`, `hold`, `stroke` and `pats` to the pattern played when the gesture is recognised,
+         see `gestures`.
+
+    17. **Spatial Layout (`layout`)**:
+       - Group members with a `position` are driven by their distance to the contact, taken from
+         `position_parameters` or from directional proximity parameters, see `spatial`.
+
+    18. **Output Format (`output_format`)**:
+       - `int8` (0-255, default), `int16` (0-65535) or `float` (0.0-1.0) encoding of motor values, per
+         device or group member, with `default_output_format` in `setup`.
+
+    19. **Logging (`log_level`, `log_filters`, ...)**:
+       - Levels, per-module filters, format, location and rotation of the log in `setup` (see `logging`).
+
+    20. **Diagnostics (`read_config`)**:
+       - Every problem in the file is reported at once with its line and column (see `diagnostics`):
+         unknown keys, wrong values, out-of-range settings, `inner_proximity` not above
+         `outer_proximity`, and duplicate ips or group names. Warnings don't stop the file from being used.
+
     **Dynamic Port Management with OSCQuery**:
     - If the configuration specifies `"OSCQuery"` for `port_rx`, the module uses the `oscq_giggletech` helper 
       to dynamically retrieve the UDP port from the OSCQuery service. If not, a static port number from the config is used.
@@ -53,33 +128,38 @@
 //  #default_speed_scale: 100
 
 use std::{net::IpAddr};
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::path::Path;
+use std::collections::HashMap;
 use std::sync::Arc;
-use yaml_rust::{YamlLoader, Yaml};
-use yaml_rust::yaml::Hash;
+use std::time::Duration;
+use log::{info, trace, warn};
 mod oscq_giggletech;
 
-use std::fs::OpenOptions;
-use std::io::Write;
-use chrono::Local; // For timestamps
 
 
-mod yaml_validator;
+mod model;
+mod diagnostics;
 
-use yaml_validator::{validate_yaml, Config};
+use crate::input_combiner::CombineMode;
+use crate::response_curve::{CurveKind, ResponseCurve};
+use crate::input_filter::InputFilter;
+use crate::patterns::{self, Pattern, PatternMode, PatternTrigger};
+use crate::scripting;
+use crate::contact_gate::GateParams;
+use crate::governor::GovernorParams;
+use crate::envelope::EnvelopeParams;
+use crate::pulse::PulseParams;
+use crate::gestures::{Gesture, GestureResponses};
+use crate::spatial::{PanLayout, PanSource};
+use crate::giggletech_osc::OutputFormat;
+use crate::logging::{self, LogSettings, Rotation};
+use crate::paths;
 
+use diagnostics::{ConfigPath, Report};
+use model::{defaults, ConfigFile, CurveSettings, DeviceEntry, DeviceOptions, EnvelopeSettings, FilterSettings, GateSettings,
+    GovernorSettings, GroupEntry, LayoutEntry, MotionSettings, Settings, Setup, SetupOptions, SpeedSettings, Text};
 
-fn log_to_file(message: &str) {
-    let now = Local::now();
-    let timestamp = now.format("%Y-%m-%d %H:%M:%S").to_string(); // Add a timestamp
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("giggletech_log.txt")
-        .unwrap();
-    writeln!(file, "[{}] {}", timestamp, message).unwrap();
-}
 
 // Banner
 fn banner_txt(){
@@ -98,309 +178,1067 @@ fn banner_txt(){
                                                                                 
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) struct DeviceConfig {
     pub device_uri: Arc<String>,
     pub min_speed: f32,
     pub max_speed: f32,
     pub start_tx: i32,
     pub speed_scale: f32,
-    pub proximity_parameter: Arc<String>,
+    pub proximity_parameters: Arc<Vec<Arc<String>>>,
+    pub combine_mode: CombineMode,
+    pub input_weights: Arc<Vec<f32>>,
     pub max_speed_parameter: Arc<String>,
-    pub use_velocity_control: bool,
+    pub control_mode: ControlMode,
+    pub proximity_weight: f32,
+    pub velocity_weight: f32,
+    pub script: Option<Arc<String>>,
     pub outer_proximity: f32,
     pub inner_proximity: f32,
-    pub velocity_scalar: f32
+    pub velocity_scalar: f32,
+    pub respond_to_retreat: bool,
+    pub velocity_smoothing: f32,
+    pub velocity_decay: f32,
+    pub led: Option<LedConfig>,
+    pub group_members: Arc<Vec<GroupMember>>,
+    pub hmac_key: Option<Arc<String>>,
+    pub output_format: OutputFormat,
+    pub curve: ResponseCurve,
+    pub curve_parameter: Option<Arc<String>>,
+    pub input_filter: InputFilter,
+    pub pattern_triggers: Arc<Vec<PatternTrigger>>,
+    pub gate: GateParams,
+    pub governor: GovernorParams,
+    pub envelope: EnvelopeParams,
+    pub pulse: PulseParams,
+    pub gestures: GestureResponses,
+    pub layout: Option<PanLayout>
+}
+
+impl DeviceConfig {
+    // Short name of the input parameter(s) for console output
+    pub fn parameter_label(&self) -> String {
+        self.proximity_parameters.iter()
+            .map(|param| param.trim_start_matches("/avatar/parameters/"))
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+
+    // Devices driven by this entry: the group members, or the device itself
+    pub fn targets(&self) -> Vec<GroupMember> {
+        if self.group_members.is_empty() {
+            vec![GroupMember {
+                ip: self.device_uri.clone(),
+                gain: 1.0,
+                delay: Duration::ZERO,
+                hmac_key: self.hmac_key.clone(),
+                output_format: self.output_format,
+                position: None
+            }]
+        } else {
+            self.group_members.as_ref().clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GroupMember {
+    pub ip: Arc<String>,
+    pub gain: f32,
+    pub delay: Duration,
+    pub hmac_key: Option<Arc<String>>,
+    pub output_format: OutputFormat,
+    pub position: Option<[f32; 2]>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum LedSource {
+    Motor,
+    Parameter(Arc<String>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LedConfig {
+    pub source: LedSource,
+    pub color_parameter: Option<Arc<String>>,
+    pub min_brightness: f32,
+    pub max_brightness: f32,
+    pub gamma: f32,
+    pub timeout: u64
+}
+
+// How proximity samples are turned into motor values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ControlMode {
+    Proximity,
+    Velocity,
+    Hybrid,
+    Script
+}
+
+impl ControlMode {
+    pub fn parse(mode: &str) -> Option<ControlMode> {
+        match mode.to_lowercase().as_str() {
+            "proximity" => Some(ControlMode::Proximity),
+            "velocity" => Some(ControlMode::Velocity),
+            "hybrid" => Some(ControlMode::Hybrid),
+            "script" => Some(ControlMode::Script),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct GlobalConfig {
     pub port_rx: Arc<String>,
-    pub default_min_speed: f32,
-    pub default_max_speed: f32,
-    pub default_speed_scale: f32,
-    pub default_start_tx: i32,
-    pub default_max_speed_parameter: Arc<String>,
+    // `port_rx` as written in config.yml, e.g. "OSCQuery"
+    pub port_rx_setting: Arc<String>,
     pub minimum_max_speed: f32,
     pub timeout: u64,
-    pub default_use_velocity_control: bool,
-    pub default_outer_proximity: f32,
-    pub default_inner_proximity: f32,
-    pub default_velocity_scalar: f32
+    pub pattern_dir: Arc<String>,
+    // The `default_*` settings of `setup` with every value set
+    pub defaults: Settings,
+    pub logging: LogSettings
 }
 
-struct YamlHashWrapper {
-    yaml_hash: Hash
-}
+// Reads and validates config.yml. `port` turns the `port_rx` setting into the port to listen on.
+fn read_config(port: impl FnOnce(&Arc<String>) -> Arc<String>) -> Result<(GlobalConfig, Vec<DeviceConfig>), String> {
+    let path = paths::config_path();
+    let config_data = match fs::read_to_string(&path) {
+        Err(why) => return Err(format!("Failed to open {}: {}", path.display(), why)),
+        Ok(data) => data
+    };
 
-impl YamlHashWrapper {
-    fn has_key(&self, key: &str) -> bool {
-        self.yaml_hash.contains_key(&Yaml::String(key.to_string()))
+    // Every problem is reported before giving up
+    let (config, mut report) = diagnostics::check(&config_data, &path.display().to_string());
+    let parsed = config.map(|config| parse_config(&config, &mut report));
+    report.sort();
+    for warning in report.warnings() {
+        warn!("{}", warning);
     }
+    let (mut global_config, device_configs) = match parsed {
+        Some(parsed) if !report.has_errors() => parsed,
+        _ => return Err(report.to_string()),
+    };
+    info!("Configuration file is valid.");
 
-    fn get_i64(&self, key: &str) -> Option<i64> {
-        self.yaml_hash.get(&Yaml::String(key.to_string()))?.as_i64()
-    }
+    global_config.port_rx = port(&global_config.port_rx_setting);
+    Ok((global_config, device_configs))
+}
 
-    fn get_f64(&self, key: &str) -> Option<f64> {
-        let value = self.yaml_hash.get(&Yaml::String(key.to_string()));
-        value.map(|yaml| {
-            yaml.as_f64()
-                .or(yaml.as_i64().map(|x| x as f64))
-        }).flatten()
-    }
+fn parse_config(config: &ConfigFile, report: &mut Report) -> (GlobalConfig, Vec<DeviceConfig>) {
+    let global_config = parse_global_config(&config.setup, report);
+    let root = ConfigPath::default();
 
-    fn get_str(&self, key: &str) -> Option<String> {
-        let value = self.yaml_hash.get(&Yaml::String(key.to_string()));
-        value.map(|yaml| {
-            yaml.as_str().map(|x| x.to_string())
-                .or(yaml.as_bool().map(|x| x.to_string()))
-                .or(yaml.as_i64().map(|x| x.to_string()))
-                .or(yaml.as_f64().map(|x| x.to_string()))
-        }).flatten()
+    let mut device_configs = Vec::new();
+    for (i, device) in config.devices.iter().enumerate() {
+        let path = root.key("devices").index(i);
+        let problems = settings_problems(&device.options.settings.or(&global_config.defaults), &path, report);
+        check_weights_use(&device.options, &path, report);
+        // A missing key is already reported
+        if !report.is_complete(&path) {
+            continue;
+        }
+        match parse_device_config(device, &global_config) {
+            Ok(device_config) => device_configs.push(device_config),
+            Err(errors) => report_new(report, &path, errors, &problems),
+        }
     }
 
-    fn get_bool(&self, key: &str) -> Option<bool> {
-        self.yaml_hash.get(&Yaml::String(key.to_string()))?.as_bool()
+    // Device groups are optional
+    for (i, group) in config.groups.iter().enumerate() {
+        let path = root.key("groups").index(i);
+        let problems = settings_problems(&group.options.settings.or(&global_config.defaults), &path, report);
+        check_weights_use(&group.options, &path, report);
+        // A missing key is already reported
+        if !report.is_complete(&path) {
+            continue;
+        }
+        match parse_group_config(group, &global_config) {
+            Ok(group_config) => device_configs.push(group_config),
+            Err(errors) => report_new(report, &path, errors, &problems),
+        }
     }
-}
-
-
-
 
+    check_duplicates(config, report);
+    (global_config, device_configs)
+}
 
+// Errors of an entry that `settings_problems` has not reported yet
+fn report_new(report: &mut Report, entry: &ConfigPath, errors: Vec<String>, reported: &[String]) {
+    for e in errors.into_iter().filter(|e| !reported.contains(e)) {
+        report.error_in(entry, e);
+    }
+}
 
+// Every error of an entry, so one run reports all of them instead of the first one
+#[derive(Debug, Default)]
+struct Problems(Vec<String>);
 
+impl Problems {
+    fn push(&mut self, problem: String) {
+        self.0.push(problem);
+    }
 
+    // The value of a setting, or None once its error is recorded
+    fn check<T>(&mut self, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.0.push(e);
+                None
+            }
+        }
+    }
 
-pub(crate) fn load_config() -> Result<(GlobalConfig, Vec<DeviceConfig>), String> {
-    let mut config_file = match File::open("./config.yml") {
-        Err(why) => return Err(format!("Failed to open config.yml: {}", why)),
-        Ok(f) => f
-    };
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
 
-    // Call validate_yaml function
-    match validate_yaml("./config.yml") {
-        Ok(_) => println!("Configuration file is valid."),
-        Err(e) => return Err(format!("Configuration File Error: {}", e)),
-    };
+fn errors<T>(result: Result<T, String>) -> Vec<String> {
+    result.err().into_iter().collect()
+}
 
-    let mut config_data = String::new();
-    match config_file.read_to_string(&mut config_data) {
-        Err(why) => return Err(format!("Failed to read config.yml: {}", why)),
-        Ok(_) => {}
+// Reports every speed and motion problem of an entry, also of one with a missing key that is not parsed
+fn settings_problems(settings: &Settings, entry: &ConfigPath, report: &mut Report) -> Vec<String> {
+    check_speed_order(&settings.speed, entry, report);
+    let problems = [speed_problems(&settings.speed), motion_problems(&settings.motion)].concat();
+    for problem in problems.iter() {
+        report.error_in(entry, problem.clone());
     }
+    problems
+}
 
-    let config = match YamlLoader::load_from_str(&config_data) {
-        Err(why) => return Err(format!("Failed to parse YAML: {}", why)),
-        Ok(yaml_data) => yaml_data
-    };
-    
-    if config.len() != 1 {
-        return Err("Only 1 element should be in the yaml file".to_string());
+// Weights only count in the weighted combine mode
+fn check_weights_use(options: &DeviceOptions, entry: &ConfigPath, report: &mut Report) {
+    let weighted = options.combine.as_deref().and_then(|mode| CombineMode::parse(mode)) == Some(CombineMode::WeightedAverage);
+    if !options.weights.is_empty() && !weighted {
+        report.warning_in(entry, "weights are only used with combine: weighted and are ignored");
     }
-    
-    let map = match config.first().unwrap().as_hash() {
-        Some(hash) => hash,
-        None => return Err("Expected config to be a map at the top level".to_string()),
-    };
-    
-    let setup = match map.get(&Yaml::String("setup".to_string())) {
-        Some(setup_yaml) => match setup_yaml.as_hash() {
-            Some(setup_hash) => setup_hash,
-            None => return Err("Setup section must be a map".to_string()),
-        },
-        None => return Err("Missing setup section".to_string()),
-    };
-    
-    let setup = YamlHashWrapper {yaml_hash: setup.clone()};
-    let global_config = parse_global_config(setup);
+}
 
-    let devices = match map.get(&Yaml::String("devices".to_string())) {
-        Some(devices_yaml) => match devices_yaml.as_vec() {
-            Some(devices_vec) => devices_vec,
-            None => return Err("Devices section must be a list".to_string()),
-        },
-        None => return Err("Missing devices section".to_string()),
-    };
-    
-    let mut device_configs = Vec::new();
-    for (i, dev) in devices.iter().enumerate() {
-        let device_hash = match dev.as_hash() {
-            Some(hash) => hash,
-            None => return Err(format!("Device {} is not a valid map", i + 1)),
-        };
-        let device_data = YamlHashWrapper {yaml_hash: device_hash.clone()};
-        match parse_device_config(device_data, &global_config) {
-            Ok(device_config) => device_configs.push(device_config),
-            Err(e) => return Err(format!("Error parsing device {}: {}", i + 1, e)),
+// Devices and groups are told apart by their address, and two entries driving one motor fight over it.
+// Every entry is checked, also one with other errors, only a missing address is left out.
+fn check_duplicates(config: &ConfigFile, report: &mut Report) {
+    let root = ConfigPath::default();
+    let devices = config.devices.iter().enumerate()
+        .map(|(i, device)| (root.key("devices").index(i), "ip", device.ip.to_string(), Vec::new()));
+    let groups = config.groups.iter().enumerate()
+        .map(|(i, group)| (root.key("groups").index(i), "name", format!("group:{}", group.name), group.members.iter().map(|member| member.ip.to_string()).collect()));
+    let entries = devices.chain(groups)
+        .filter(|(path, key, _, _)| report.is_complete(&path.key(key)))
+        .collect::<Vec<(ConfigPath, &str, String, Vec<String>)>>();
+
+    for (i, (path, key, address, members)) in entries.iter().enumerate() {
+        if let Some((first, ..)) = entries[..i].iter().find(|(_, _, other, _)| other == address) {
+            report.error_at(&path.key(key), format!("Duplicate {} {}, already used by {}", key, address, first));
+        }
+        for (j, member) in members.iter().enumerate() {
+            let member_path = path.key("members").index(j);
+            if !report.is_complete(&member_path.key("ip")) {
+                continue;
+            }
+            if members[..j].contains(member) {
+                report.error_at(&member_path.key("ip"), format!("Duplicate member {} in {}", member, path));
+            } else if let Some((other, ..)) = entries.iter().find(|(_, _, other, _)| other == member) {
+                report.warning_in(&member_path, format!("ip {} is also {}, both send to the same motor", member, other));
+            }
         }
     }
+}
+
+// Reads config.yml again while the router runs, keeping the port it listens on
+pub(crate) fn reload_config(running: &GlobalConfig) -> Result<(GlobalConfig, Vec<DeviceConfig>), String> {
+    read_config(|_| running.port_rx.clone())
+}
+
+// Reads config.yml without resolving an OSCQuery port, for commands that don't listen for OSC
+pub(crate) fn check_config() -> Result<(GlobalConfig, Vec<DeviceConfig>), String> {
+    read_config(|port_rx_setting| port_rx_setting.clone())
+}
+
+pub(crate) fn load_config() -> Result<(GlobalConfig, Vec<DeviceConfig>), String> {
+    let (global_config, device_configs) = read_config(|port_rx_setting| resolve_port_rx(port_rx_setting))?;
 
     println!("\n");
     banner_txt();
     println!("\n");
-    println!(" Device Maps");
-    println!("");
-    for (i, device) in device_configs.iter().enumerate() {
-        println!("  Device {i}");
-        println!("   {} => {}", device.proximity_parameter.trim_start_matches("/avatar/parameters/"), device.device_uri);
-        println!("   Vibration Configuration");
-        println!("    Startup TX Speed: {:.0}%", device.start_tx);
-        println!("    Min Speed: {:.0}%", device.min_speed * 100.0);
-        println!("    Max Speed: {:.0}%", device.max_speed * 100.0);
-        println!("    Scale Factor: {:.0}%", device.speed_scale * 100.0);
-        println!("    Advanced Mode: {}", device.use_velocity_control);
-        println!("");
+    info!("Device Maps");
+    for line in device_map(&device_configs) {
+        info!("{}", line);
     }
 
-    println!("\n Listening for OSC on port: {}", global_config.port_rx);
-    println!(" Timeout: {}s", global_config.timeout);
-    println!("\nWaiting for pats...");
+    info!(" Listening for OSC on port: {}", global_config.port_rx);
+    info!(" Timeout: {}s", global_config.timeout);
+    info!("Waiting for pats...");
 
     Ok((global_config, device_configs))
 }
 
+// Parameters, members and settings of every device, one line each
+pub(crate) fn device_map(device_configs: &[DeviceConfig]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (i, device) in device_configs.iter().enumerate() {
+        lines.push(format!("  Device {i}"));
+        lines.push(format!("   {} => {}", device.parameter_label(), device.device_uri));
+        if device.proximity_parameters.len() > 1 {
+            lines.push(format!("    Combine: {:?}", device.combine_mode));
+        }
+        for member in device.group_members.iter() {
+            lines.push(format!("    Member {} (Gain: {:.0}%, Delay: {}ms)", member.ip, member.gain * 100.0, member.delay.as_millis()));
+        }
+        match device.layout.as_ref().map(|layout| &layout.source) {
+            Some(PanSource::Position(..)) => lines.push("    Spatial Panning: contact position".to_string()),
+            Some(PanSource::Directions(_)) => lines.push("    Spatial Panning: directional proximity".to_string()),
+            None => {}
+        }
+        if device.targets().iter().any(|target| target.hmac_key.is_some()) {
+            lines.push("    Signed Commands: Enabled".to_string());
+        }
+        for format in [OutputFormat::Int16, OutputFormat::Float] {
+            if device.targets().iter().any(|target| target.output_format == format) {
+                lines.push(format!("    Output Format: {:?}", format));
+            }
+        }
+        lines.push("   Vibration Configuration".to_string());
+        lines.push(format!("    Startup TX Speed: {:.0}%", device.start_tx));
+        lines.push(format!("    Min Speed: {:.0}%", device.min_speed * 100.0));
+        lines.push(format!("    Max Speed: {:.0}%", device.max_speed * 100.0));
+        lines.push(format!("    Scale Factor: {:.0}%", device.speed_scale * 100.0));
+        lines.push(format!("    Control Mode: {:?}", device.control_mode));
+        if device.control_mode == ControlMode::Hybrid {
+            lines.push(format!("    Hybrid Weights: Proximity {:.0}%, Velocity {:.0}%", device.proximity_weight * 100.0, device.velocity_weight * 100.0));
+        }
+        if device.pulse.enabled {
+            lines.push(format!("    Pulse Mode: below Tx {} (bursts at Tx {})", device.pulse.stall_tx, device.pulse.pulse_tx));
+        }
+        if device.governor.enabled() {
+            lines.push("    Motor Governor: Enabled".to_string());
+        }
+        if let Some(script) = &device.script {
+            lines.push(format!("    Script: {}", script));
+        }
+        if device.curve.kind != CurveKind::Linear {
+            lines.push(format!("    Response Curve: {:?}", device.curve.kind));
+        }
+        if device.input_filter != InputFilter::None {
+            lines.push(format!("    Input Filter: {:?}", device.input_filter));
+        }
+        for trigger in device.pattern_triggers.iter() {
+            lines.push(format!("    Pattern: {} => {} ({:?})", trigger.parameter.trim_start_matches("/avatar/parameters/"), trigger.pattern.name, trigger.mode));
+        }
+        for (gesture, pattern) in Gesture::ALL.iter().filter_map(|gesture| device.gestures.get(gesture).map(|pattern| (gesture, pattern))) {
+            lines.push(format!("    Gesture: {} => {}", gesture.name(), pattern.as_ref().map(|pattern| pattern.name.as_str()).unwrap_or("none")));
+        }
+        if let Some(led) = &device.led {
+            match &led.source {
+                LedSource::Motor => lines.push("    LED: follows motor".to_string()),
+                LedSource::Parameter(param) => lines.push(format!("    LED: {}", param.trim_start_matches("/avatar/parameters/"))),
+            }
+        }
+    }
+
+    lines
+}
+
+
+// Problems with the options of `setup` are warnings, problems with the `default_*` settings errors
+fn parse_global_config(setup: &Setup, report: &mut Report) -> GlobalConfig {
+    let options = &setup.options;
+    let path = ConfigPath::default().key("setup");
+
+    // Retrieve the value of `port_rx` with fallback
+    let port_rx_setting = Arc::new(options.port_rx.as_ref().map(|port| port.to_string()).unwrap_or_else(|| {
+        report.warning_in(&path, format!("port_rx not found in config, using default port {}", defaults::PORT_RX));
+        defaults::PORT_RX.to_string()
+    }));
+    if *port_rx_setting != "OSCQuery" && port_rx_setting.parse::<u16>().is_err() {
+        report.warning_in(&path, format!("Invalid port_rx '{}' (expected a port number or OSCQuery), using default port {}", port_rx_setting, defaults::PORT_RX));
+    }
 
+    let timeout = options.timeout.unwrap_or(defaults::TIMEOUT);
+    let pattern_dir = Arc::new(paths::resolve(options.pattern_dir.as_ref().map(|dir| dir.as_str()).unwrap_or(defaults::PATTERN_DIR)));
 
-fn parse_global_config(setup: YamlHashWrapper) -> GlobalConfig {
-    // Retrieve the value of `port_rx` from the YAML file with fallback
-    let port_rx_str = setup.get_str("port_rx").unwrap_or_else(|| {
-        println!("Warning: port_rx not found in config, using default port 9001");
-        "9001".to_string()
+    // Device defaults: `default_*` settings first, then the built-in defaults
+    let builtin = Settings::builtin();
+    let mut device_defaults = setup.defaults.or(&builtin);
+    check_default(report, &path, speed_problems(&device_defaults.speed), &mut device_defaults.speed, &builtin.speed);
+    check_default(report, &path, motion_problems(&device_defaults.motion), &mut device_defaults.motion, &builtin.motion);
+    check_default(report, &path, errors(parse_curve(&device_defaults.curve)), &mut device_defaults.curve, &builtin.curve);
+    check_default(report, &path, errors(parse_input_filter(&device_defaults.filter)), &mut device_defaults.filter, &builtin.filter);
+    check_default(report, &path, errors(parse_gate(&device_defaults.gate)), &mut device_defaults.gate, &builtin.gate);
+    check_default(report, &path, errors(parse_governor(&device_defaults.governor)), &mut device_defaults.governor, &builtin.governor);
+    check_default(report, &path, errors(parse_envelope(&device_defaults.envelope)), &mut device_defaults.envelope, &builtin.envelope);
+    check_default(report, &path, errors(parse_output_format(device_defaults.output_format.as_ref())), &mut device_defaults.output_format, &builtin.output_format);
+    if let Some(mode) = &device_defaults.control.control_mode {
+        if !matches!(ControlMode::parse(mode), Some(control_mode) if control_mode != ControlMode::Script) {
+            report.error_in(&path, format!("Invalid default_control_mode '{}' (expected proximity, velocity or hybrid)", mode));
+            device_defaults.control.control_mode = None;
+        }
+    }
+
+    let logging = parse_logging(options).unwrap_or_else(|e| {
+        report.warning_in(&path, format!("{}, using the default log settings", e));
+        LogSettings::default()
     });
 
+    GlobalConfig {
+        // Resolved once the file is known to be valid
+        port_rx: port_rx_setting.clone(),
+        port_rx_setting,
+        minimum_max_speed: defaults::MINIMUM_MAX_SPEED,
+        timeout,
+        pattern_dir,
+        defaults: device_defaults,
+        logging,
+    }
+}
+
+// Uses the port from OSCQuery or the port number from config.yml
+fn resolve_port_rx(port_rx_setting: &str) -> Arc<String> {
     // Check if `port_rx` is "OSCQuery" or a numeric port
-    let port_rx: Arc<String> = if port_rx_str == "OSCQuery" {
+    if port_rx_setting == "OSCQuery" {
         // If it's "OSCQuery", try to use the port from the OSCQuery server
-        println!("\nAttempting to use OSCQuery...");
+        info!("Attempting to use OSCQuery...");
         match std::panic::catch_unwind(|| {
             oscq_giggletech::initialize_and_get_udp_port()
         }) {
             Ok(udp_port) => {
-                println!("OSCQuery initialized successfully. UDP port: {}", udp_port);
+                info!("OSCQuery initialized successfully. UDP port: {}", udp_port);
                 Arc::new(udp_port.to_string())
             }
             Err(_) => {
-                println!("OSCQuery initialization failed. Falling back to default port 9001.");
-                Arc::new("9001".to_string())
+                warn!("OSCQuery initialization failed. Falling back to default port {}.", defaults::PORT_RX);
+                Arc::new(defaults::PORT_RX.to_string())
             }
         }
     } else {
         // Otherwise, assume it's a port number in string format, validate, and wrap it in Arc
-        match u16::from_str_radix(&port_rx_str, 10) {
-            Ok(_) => Arc::new(port_rx_str),
-            Err(_) => {
-                println!("Warning: Invalid port number '{}', using default port 9001", port_rx_str);
-                Arc::new("9001".to_string())
-            }
+        match port_rx_setting.parse::<u16>() {
+            Ok(_) => Arc::new(port_rx_setting.to_string()),
+            // Reported by `parse_global_config`
+            Err(_) => Arc::new(defaults::PORT_RX.to_string()),
         }
-    };
-
-    // Proceed with other config values with proper fallbacks
-    let default_min_speed = setup.get_f64("default_min_speed").unwrap_or(5.0) as f32 / 100.0;
-    assert!(default_min_speed >= 0.0); // Ensure min speed is valid
-
-    const MAX_SPEED_LOW_LIMIT_CONST: f32 = 0.05;
-
-    let default_max_speed = setup.get_f64("default_max_speed").unwrap_or(25.0) as f32 / 100.0;
-    let default_max_speed = default_max_speed.max(default_min_speed).max(MAX_SPEED_LOW_LIMIT_CONST);
-
-    let default_start_tx = setup.get_i64("default_start_tx").unwrap_or(20) as i32;
+    }
+}
 
-    let default_max_speed_parameter = setup
-        .get_str("default_max_speed_parameter")
-        .unwrap_or("max_speed".to_string());
-    let default_max_speed_parameter = Arc::new(format!("/avatar/parameters/{}", default_max_speed_parameter));
+// An invalid group of `default_*` settings is an error like on a device. The built-in defaults
+// stand in for it, so the devices can still be checked.
+fn check_default<T: Clone>(report: &mut Report, setup: &ConfigPath, problems: Vec<String>, settings: &mut T, builtin: &T) {
+    if problems.is_empty() {
+        return;
+    }
+    for problem in problems {
+        report.error_in(setup, problem);
+    }
+    *settings = builtin.clone();
+}
 
-    let default_speed_scale = (setup.get_f64("default_speed_scale").unwrap_or(100.0) as f32) / 100.0;
+fn parse_ip(ip: &Text) -> Result<Arc<String>, String> {
+    match ip.parse::<IpAddr>() {
+        Ok(_) => Ok(Arc::new(ip.to_string())),
+        Err(_) => Err(format!("Invalid IP address format: {}", ip)),
+    }
+}
 
-    let timeout = setup.get_i64("timeout").unwrap_or(5) as u64;
+fn parse_device_config(device: &DeviceEntry, global_config: &GlobalConfig) -> Result<DeviceConfig, Vec<String>> {
+    let mut problems = Problems::default();
+    let ip = problems.check(parse_ip(&device.ip));
+    let settings = parse_device_settings(&device.options, global_config, Arc::new(device.ip.to_string()), Vec::new());
+    match (ip, settings) {
+        (Some(ip), Ok(device_config)) => Ok(DeviceConfig { device_uri: ip, ..device_config }),
+        (_, settings) => Err([problems.0, settings.err().unwrap_or_default()].concat()),
+    }
+}
 
-    let default_use_velocity_control = setup
-        .get_bool("default_use_velocity_control")
-        .or_else(|| {
-            setup
-                .get_str("default_use_velocity_control")
-                .map(|s| s.to_lowercase() == "true")
-        })
-        .unwrap_or(false); // Default to `false` if the key is missing or invalid
+fn parse_group_config(group: &GroupEntry, global_config: &GlobalConfig) -> Result<DeviceConfig, Vec<String>> {
+    let name = &group.name;
+    let mut problems = Problems::default();
+    if group.members.is_empty() {
+        problems.push(format!("Group '{}' must have a non-empty 'members' list", name));
+    }
 
-    let default_outer_proximity = setup.get_f64("default_outer_proximity").unwrap_or(0.0) as f32;
-    let default_inner_proximity = setup.get_f64("default_inner_proximity").unwrap_or(0.7) as f32;
-    let default_velocity_scalar = setup.get_f64("default_velocity_scalar").unwrap_or(20.0) as f32;
+    let mut members = Vec::new();
+    for member in group.members.iter() {
+        let ip = match problems.check(parse_ip(&member.ip)) {
+            Some(ip) => ip,
+            None => continue,
+        };
+        let gain = member.gain.unwrap_or(defaults::MEMBER_GAIN);
+        // A gain above 1 would bypass MOTOR_SPEED_SCALE
+        if !(0.0..=1.0).contains(&gain) {
+            problems.push(format!("Gain for member {} of group '{}' must be between 0 and 1", ip, name));
+        }
+        let delay_ms = member.delay_ms.unwrap_or(0);
+        if delay_ms < 0 {
+            problems.push(format!("Delay for member {} of group '{}' cannot be negative", ip, name));
+        }
+        let position = match &member.position {
+            Some(position) => match problems.check(parse_position(position).ok_or_else(|| format!("Position of member {} of group '{}' must be [x] or [x, y]", ip, name))) {
+                Some(position) => Some(position),
+                None => continue,
+            },
+            None => None,
+        };
+        let output_format = member.output_format.as_ref().or(global_config.defaults.output_format.as_ref());
+        let hmac_key = problems.check(parse_hmac_key(member.hmac_key.as_ref()));
+        let output_format = problems.check(parse_output_format(output_format));
+        if let (Some(hmac_key), Some(output_format)) = (hmac_key, output_format) {
+            members.push(GroupMember {
+                ip,
+                gain,
+                delay: Duration::from_millis(delay_ms.max(0) as u64),
+                hmac_key,
+                output_format,
+                position
+            });
+        }
+    }
 
-    // Return the GlobalConfig struct with the updated port_rx
-    GlobalConfig {
-        port_rx,
-        default_min_speed,
-        default_max_speed,
-        default_max_speed_parameter,
-        default_start_tx,
-        minimum_max_speed: MAX_SPEED_LOW_LIMIT_CONST,
-        default_speed_scale,
-        timeout,
-        default_use_velocity_control,
-        default_outer_proximity,
-        default_inner_proximity,
-        default_velocity_scalar,
+    let layout = match &group.layout {
+        Some(layout) if members.len() == group.members.len() => problems.check(parse_layout(layout, &members).map_err(|e| format!("Layout of group '{}': {}", name, e))),
+        _ => None,
+    };
+    let mut group_config = match parse_device_settings(&group.options, global_config, Arc::new(format!("group:{}", name)), members) {
+        Ok(group_config) if problems.is_empty() => group_config,
+        settings => return Err([problems.0, settings.err().unwrap_or_default()].concat()),
+    };
+    if let Some(PanLayout { source: PanSource::Directions(directions), .. }) = &layout {
+        if directions.len() != group_config.proximity_parameters.len() {
+            return Err(vec![format!("Layout of group '{}' needs one direction per proximity_parameter", name)]);
+        }
     }
+    group_config.layout = layout;
+    Ok(group_config)
 }
 
+// Accepts [x] or [x, y]
+fn parse_position(values: &[f32]) -> Option<[f32; 2]> {
+    match values {
+        [x] => Some([*x, 0.0]),
+        [x, y] => Some([*x, *y]),
+        _ => None,
+    }
+}
 
-fn parse_device_config(device_data: YamlHashWrapper, global_config: &GlobalConfig) -> Result<DeviceConfig, String> {
-    let ip = match device_data.get_str("ip") {
-        Some(ip_str) => {
-            match ip_str.parse::<IpAddr>() {
-                Ok(_) => Arc::new(ip_str),
-                Err(_) => {
-                    return Err(format!("Invalid IP address format: {}", ip_str));
-                }
+fn parse_layout(layout: &LayoutEntry, members: &[GroupMember]) -> Result<PanLayout, String> {
+    if members.iter().any(|member| member.position.is_none()) {
+        return Err("every member needs a position".to_string());
+    }
+    let spread = layout.spread.unwrap_or(defaults::LAYOUT_SPREAD);
+    if spread <= 0.0 {
+        return Err("spread must be greater than 0".to_string());
+    }
+    let source = match (&layout.position_parameters, &layout.directions) {
+        (Some(_), Some(_)) => return Err("use either position_parameters or directions".to_string()),
+        (Some(parameters), None) => {
+            let mut parameters = parameters.to_vec().into_iter().map(|param| Arc::new(format!("/avatar/parameters/{}", param)));
+            match (parameters.next(), parameters.next(), parameters.next()) {
+                (Some(x), y, None) => PanSource::Position(x, y),
+                _ => return Err("position_parameters must be one or two parameters (x, y)".to_string()),
             }
         }
-        None => {
-            return Err("Missing 'ip' field in device configuration".to_string());
+        (None, Some(directions)) => {
+            match directions.iter().map(|direction| parse_position(direction)).collect::<Option<Vec<_>>>() {
+                Some(directions) => PanSource::Directions(Arc::new(directions)),
+                None => return Err("directions must be a list of [x] or [x, y] positions".to_string()),
+            }
         }
+        (None, None) => return Err("needs position_parameters or directions".to_string()),
     };
+    Ok(PanLayout { spread, source })
+}
 
-    let proximity_parameter = match device_data.get_str("proximity_parameter") {
-        Some(param) => Arc::new(format!("/avatar/parameters/{}", param)),
-        None => {
-            return Err("Missing 'proximity_parameter' field in device configuration".to_string());
-        }
+// Settings shared by single devices and device groups, with the error of every setting that is wrong
+fn parse_device_settings(options: &DeviceOptions, global_config: &GlobalConfig, ip: Arc<String>, group_members: Vec<GroupMember>) -> Result<DeviceConfig, Vec<String>> {
+    let mut problems = Problems::default();
+    let proximity_parameters = options.proximity_parameter.to_vec().iter()
+        .map(|param| Arc::new(format!("/avatar/parameters/{}", param)))
+        .collect::<Vec<_>>();
+    if proximity_parameters.is_empty() {
+        problems.push("Missing 'proximity_parameter' field in device configuration".to_string());
+    }
+
+    let combine_mode = match &options.combine {
+        Some(mode) => problems.check(CombineMode::parse(mode).ok_or_else(|| format!("Invalid combine mode '{}' (expected max, sum, weighted or latest)", mode))),
+        None => Some(CombineMode::Max),
     };
+    let input_weights = options.weights.clone();
+    if input_weights.iter().any(|x| *x < 0.0) {
+        problems.push("Weights cannot be negative".to_string());
+    }
+    if !input_weights.is_empty() && input_weights.len() != proximity_parameters.len() {
+        problems.push(format!("weights must have one value per proximity parameter, found {} for {}", input_weights.len(), proximity_parameters.len()));
+    }
+
+    // Settings the device leaves out come from `setup`, where every value is set
+    let settings = options.settings.or(&global_config.defaults);
+
+    problems.0.extend(speed_problems(&settings.speed));
+    let speed = &settings.speed;
+    let min_speed = speed.min_speed.unwrap_or_default() / 100.0;
+    let max_speed = (speed.max_speed.unwrap_or_default() / 100.0).max(min_speed).max(global_config.minimum_max_speed);
+    let start_tx = speed.start_tx.unwrap_or_default();
+    let speed_scale = speed.speed_scale.unwrap_or_default() / 100.0;
+    let max_speed_parameter = Arc::new(format!("/avatar/parameters/{}", speed.max_speed_parameter.clone().unwrap_or_default()));
+
+    problems.0.extend(motion_problems(&settings.motion));
+    let motion = &settings.motion;
+    let proximity_weight = motion.proximity_weight.unwrap_or_default();
+    let velocity_weight = motion.velocity_weight.unwrap_or_default();
+    let outer_proximity = motion.outer_proximity.unwrap_or_default();
+    let inner_proximity = motion.inner_proximity.unwrap_or_default();
+    let velocity_scalar = motion.velocity_scalar.unwrap_or_default();
+    let respond_to_retreat = motion.respond_to_retreat.unwrap_or_default();
+    let velocity_smoothing = motion.velocity_smoothing.unwrap_or_default();
+    let velocity_decay = motion.velocity_decay.unwrap_or_default();
 
-    let min_speed = device_data.get_f64("min_speed").map(|x| x as f32 / 100.0).unwrap_or(global_config.default_min_speed);
-    if min_speed < 0.0 {
-        return Err("Min speed cannot be negative".to_string());
+    let led = problems.check(parse_led_config(options, global_config));
+    let hmac_key = problems.check(parse_hmac_key(options.hmac_key.as_ref()));
+    let output_format = problems.check(parse_output_format(settings.output_format.as_ref()));
+    let curve = problems.check(parse_curve(&settings.curve));
+    let curve_parameter = options.curve_parameter.as_ref().map(|x| Arc::new(format!("/avatar/parameters/{}", x)));
+    let input_filter = problems.check(parse_input_filter(&settings.filter));
+    let pattern_triggers = problems.check(parse_pattern_triggers(options, global_config));
+    let gate = problems.check(parse_gate(&settings.gate));
+    let governor = problems.check(parse_governor(&settings.governor));
+    let envelope = problems.check(parse_envelope(&settings.envelope));
+    let pulse = problems.check(parse_pulse(options));
+    let gestures = problems.check(parse_gestures(options, global_config));
+    let script = options.script.as_ref().map(|x| Arc::new(paths::resolve(x)));
+    if let Some(script) = &script {
+        problems.check(scripting::load_script(script));
+    }
+    // An explicit control_mode takes precedence over a script and use_velocity_control
+    let device_control = &options.settings.control;
+    let control_mode = problems.check(match &device_control.control_mode {
+        Some(mode) => match ControlMode::parse(mode) {
+            Some(ControlMode::Script) if script.is_none() => Err("control_mode script needs a 'script' file".to_string()),
+            Some(control_mode) => Ok(control_mode),
+            None => Err(format!("Invalid control_mode '{}' (expected proximity, velocity, hybrid or script)", mode)),
+        },
+        None => Ok(match (&script, device_control.use_velocity_control, settings.control.control_mode.as_ref().and_then(|mode| ControlMode::parse(mode))) {
+            (Some(_), _, _) => ControlMode::Script,
+            (None, None, Some(default_mode)) => default_mode,
+            (None, _, _) if settings.control.use_velocity_control.unwrap_or_default() => ControlMode::Velocity,
+            _ => ControlMode::Proximity,
+        }),
+    });
+
+    let (Some(combine_mode), Some(led), Some(hmac_key), Some(output_format), Some(curve), Some(input_filter), Some(pattern_triggers), Some(gate), Some(governor), Some(envelope), Some(pulse), Some(gestures), Some(control_mode)) =
+        (combine_mode, led, hmac_key, output_format, curve, input_filter, pattern_triggers, gate, governor, envelope, pulse, gestures, control_mode) else {
+        return Err(problems.0);
+    };
+    if !problems.is_empty() {
+        return Err(problems.0);
     }
-    
-    let max_speed = device_data.get_f64("max_speed").map(|x| (x as f32 / 100.0).max(min_speed).max(global_config.minimum_max_speed)).unwrap_or(global_config.default_max_speed);
-    let start_tx = device_data.get_i64("start_tx").map(|x| x as i32).unwrap_or(global_config.default_start_tx);
-    let speed_scale = device_data.get_f64("speed_scale").map(|x| x as f32 / 100.0).unwrap_or(global_config.default_speed_scale);
-    let max_speed_parameter = device_data.get_str("max_speed_parameter").map(|x| Arc::new(format!("/avatar/parameters/{}", x))).unwrap_or(global_config.default_max_speed_parameter.clone());
-    let use_velocity_control = device_data.get_bool("use_velocity_control").unwrap_or(global_config.default_use_velocity_control);
-    let outer_proximity = device_data.get_f64("outer_proximity").map(|x| x as f32).unwrap_or(global_config.default_outer_proximity);
-    let inner_proximity = device_data.get_f64("inner_proximity").map(|x| x as f32).unwrap_or(global_config.default_inner_proximity);
-    let velocity_scalar = device_data.get_f64("velocity_scalar").map(|x| x as f32).unwrap_or(global_config.default_velocity_scalar);
 
     // Log device settings
-    log_to_file(&format!(
-        "Device IP: {}\nMin Speed: {:.0}%\nMax Speed: {:.0}%\nSpeed Scale: {:.0}%\nProximity Parameter: {}\nVelocity Control: {}\nOuter Proximity: {:.2}\nInner Proximity: {:.2}\n",
-        ip, min_speed * 100.0, max_speed * 100.0, speed_scale * 100.0, proximity_parameter, use_velocity_control, outer_proximity, inner_proximity
-    ));
+    trace!(
+        "Device IP: {}\nMin Speed: {:.0}%\nMax Speed: {:.0}%\nSpeed Scale: {:.0}%\nProximity Parameter: {}\nControl Mode: {:?}\nOuter Proximity: {:.2}\nInner Proximity: {:.2}\n",
+        ip, min_speed * 100.0, max_speed * 100.0, speed_scale * 100.0, proximity_parameters.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(", "), control_mode, outer_proximity, inner_proximity
+    );
 
     Ok(DeviceConfig {
         device_uri: ip,
-        proximity_parameter,
+        proximity_parameters: Arc::new(proximity_parameters),
+        combine_mode,
+        input_weights: Arc::new(input_weights),
         min_speed,
         max_speed,
         start_tx,
         speed_scale,
         max_speed_parameter,
-        use_velocity_control,
+        control_mode,
+        proximity_weight,
+        velocity_weight,
+        script,
         outer_proximity,
         inner_proximity,
-        velocity_scalar
+        velocity_scalar,
+        respond_to_retreat,
+        velocity_smoothing,
+        velocity_decay,
+        led,
+        group_members: Arc::new(group_members),
+        hmac_key,
+        output_format,
+        curve,
+        curve_parameter,
+        input_filter,
+        pattern_triggers: Arc::new(pattern_triggers),
+        gate,
+        governor,
+        envelope,
+        pulse,
+        gestures: Arc::new(gestures),
+        layout: None
     })
 }
+
+// Every problem with the speeds
+fn speed_problems(speed: &SpeedSettings) -> Vec<String> {
+    [("min_speed", speed.min_speed), ("max_speed", speed.max_speed), ("speed_scale", speed.speed_scale)].iter()
+        .filter(|(_, value)| value.unwrap_or_default() < 0.0)
+        .map(|(key, _)| format!("{} cannot be negative", key))
+        .collect()
+}
+
+// A max speed below the min speed is raised to it
+fn check_speed_order(speed: &SpeedSettings, entry: &ConfigPath, report: &mut Report) {
+    if let (Some(min_speed), Some(max_speed)) = (speed.min_speed, speed.max_speed) {
+        if max_speed < min_speed {
+            report.warning_in(entry, format!("max_speed {} is below min_speed {}, using {} as max_speed", max_speed, min_speed, min_speed));
+        }
+    }
+}
+
+// Every problem with the motion settings
+fn motion_problems(motion: &MotionSettings) -> Vec<String> {
+    let mut problems = Vec::new();
+    let values = [
+        ("velocity_smoothing", motion.velocity_smoothing),
+        ("velocity_decay", motion.velocity_decay),
+        ("proximity_weight", motion.proximity_weight),
+        ("velocity_weight", motion.velocity_weight)
+    ];
+    for (key, _) in values.iter().filter(|(_, value)| value.unwrap_or_default() < 0.0) {
+        problems.push(format!("{} cannot be negative", key));
+    }
+    let outer_proximity = motion.outer_proximity.unwrap_or_default();
+    let inner_proximity = motion.inner_proximity.unwrap_or_default();
+    for (key, value) in [("outer_proximity", outer_proximity), ("inner_proximity", inner_proximity)] {
+        if !(0.0..=1.0).contains(&value) {
+            problems.push(format!("{} must be between 0 and 1", key));
+        }
+    }
+    // Movement only counts between the two, so there has to be room between them
+    if inner_proximity <= outer_proximity {
+        problems.push(format!("inner_proximity ({}) must be greater than outer_proximity ({})", inner_proximity, outer_proximity));
+    }
+    problems
+}
+
+// Reads the `gestures` map of gesture names to response patterns
+fn parse_gestures(options: &DeviceOptions, global_config: &GlobalConfig) -> Result<HashMap<Gesture, Option<Arc<Pattern>>>, String> {
+    let mut gestures = HashMap::new();
+    for (name, pattern) in options.gestures.iter() {
+        let gesture = match Gesture::parse(name) {
+            Some(gesture) => gesture,
+            None => return Err(format!("Invalid gesture '{}' (expected tap, hold, stroke or pats)", name)),
+        };
+        let pattern = match pattern.as_str() {
+            "none" => None,
+            pattern => Some(Arc::new(patterns::load_pattern(pattern, Path::new(global_config.pattern_dir.as_str()))?)),
+        };
+        gestures.insert(gesture, pattern);
+    }
+    Ok(gestures)
+}
+
+fn parse_logging(options: &SetupOptions) -> Result<LogSettings, String> {
+    let defaults = LogSettings::default();
+    let level = |key: &str, value: Option<&Text>, fallback| match value {
+        Some(name) => logging::parse_level(name).ok_or(format!("Invalid {} '{}' (expected error, warn, info, debug, trace or off)", key, name)),
+        None => Ok(fallback),
+    };
+    let level_value = level("log_level", options.log_level.as_ref(), defaults.level)?;
+    let console_level = level("console_log_level", options.console_log_level.as_ref(), defaults.console_level)?;
+
+    let mut filters = Vec::new();
+    for (module, module_level) in options.log_filters.iter().flatten() {
+        let module_level = logging::parse_level(module_level).ok_or(format!("Invalid log level for module '{}'", module))?;
+        filters.push((module.to_string(), module_level));
+    }
+
+    let json = match options.log_format.as_ref().map(|format| format.as_str()) {
+        Some("json") => true,
+        Some("text") | None => false,
+        Some(format) => return Err(format!("Invalid log_format '{}' (expected text or json)", format)),
+    };
+    let rotation = match &options.log_rotation {
+        Some(name) => Rotation::parse(name).ok_or(format!("Invalid log_rotation '{}' (expected daily, hourly or never)", name))?,
+        None => defaults.rotation,
+    };
+    let max_size = options.log_max_size.map(|mb| (mb.max(0.0) * 1024.0 * 1024.0) as u64).unwrap_or(defaults.max_size);
+    let keep = options.log_keep.map(|keep| keep.max(0) as usize).unwrap_or(defaults.keep);
+    let dir = paths::log_dir(options.log_dir.as_ref().map(|dir| dir.as_str()));
+
+    Ok(LogSettings { level: level_value, console_level, filters, json, dir, rotation, max_size, keep })
+}
+
+fn parse_pulse(options: &DeviceOptions) -> Result<PulseParams, String> {
+    if !options.pulse_mode {
+        return Ok(PulseParams::disabled());
+    }
+    let stall_tx = match options.stall_tx {
+        Some(stall_tx) if (1..=255).contains(&stall_tx) => stall_tx as i32,
+        Some(_) => return Err("stall_tx must be between 1 and 255".to_string()),
+        None => return Err("pulse_mode needs the device's stall_tx".to_string()),
+    };
+    let pulse_tx = options.pulse_tx.map(|x| x as i32).unwrap_or(stall_tx);
+    if pulse_tx < stall_tx || pulse_tx > 255 {
+        return Err("pulse_tx must be between stall_tx and 255".to_string());
+    }
+    let period = match options.pulse_period {
+        Some(ms) if ms <= 0 => return Err("pulse_period must be greater than 0".to_string()),
+        Some(ms) => Duration::from_millis(ms as u64),
+        None => Duration::from_millis(defaults::PULSE_PERIOD_MS),
+    };
+    Ok(PulseParams { enabled: true, stall_tx, pulse_tx, period })
+}
+
+// Reads `attack`, `decay`, `sustain`, `release` and `max_slew_rate`
+fn parse_envelope(envelope: &EnvelopeSettings) -> Result<EnvelopeParams, String> {
+    let millis = |key: &str, ms: Option<f32>| match ms.unwrap_or_default() {
+        ms if ms < 0.0 => Err(format!("{} cannot be negative", key)),
+        ms => Ok(Duration::from_secs_f32(ms / 1000.0)),
+    };
+    let params = EnvelopeParams {
+        attack: millis("attack", envelope.attack)?,
+        decay: millis("decay", envelope.decay)?,
+        sustain: envelope.sustain.unwrap_or_default() / 100.0,
+        release: millis("release", envelope.release)?,
+        max_slew_rate: envelope.max_slew_rate.unwrap_or_default() / 100.0
+    };
+    if !(0.0..=1.0).contains(&params.sustain) {
+        return Err("sustain must be between 0 and 100".to_string());
+    }
+    if params.max_slew_rate < 0.0 {
+        return Err("max_slew_rate cannot be negative".to_string());
+    }
+    Ok(params)
+}
+
+// Reads the motor governor settings, percentages are stored as fractions
+fn parse_governor(governor: &GovernorSettings) -> Result<GovernorParams, String> {
+    let seconds = |key: &str, secs: Option<f32>| match secs.unwrap_or_default() {
+        secs if secs < 0.0 => Err(format!("{} cannot be negative", key)),
+        secs => Ok(Duration::from_secs_f32(secs)),
+    };
+    let params = GovernorParams {
+        thermal_time_constant: governor.thermal_time_constant.unwrap_or_default(),
+        max_heat: governor.max_heat.unwrap_or_default() / 100.0,
+        max_runtime: seconds("max_runtime", governor.max_runtime)?,
+        rest_time: seconds("rest_time", governor.rest_time)?,
+        duty_cycle: governor.duty_cycle.unwrap_or_default() / 100.0,
+        duty_window: governor.duty_window.unwrap_or_default()
+    };
+    if params.thermal_time_constant <= 0.0 || params.duty_window <= 0.0 {
+        return Err("thermal_time_constant and duty_window must be greater than 0".to_string());
+    }
+    let percentage = 0.0..=1.0;
+    if params.max_heat <= 0.0 || params.duty_cycle <= 0.0 || !percentage.contains(&params.max_heat) || !percentage.contains(&params.duty_cycle) {
+        return Err("max_heat and duty_cycle must be between 0 and 100".to_string());
+    }
+    Ok(params)
+}
+
+// Reads `dead_zone`, `hysteresis`, `min_on_time` and `min_off_time`
+fn parse_gate(gate: &GateSettings) -> Result<GateParams, String> {
+    let dead_zone = gate.dead_zone.unwrap_or_default();
+    let hysteresis = gate.hysteresis.unwrap_or_default();
+    if !(0.0..1.0).contains(&dead_zone) || hysteresis < 0.0 || dead_zone + hysteresis >= 1.0 {
+        return Err("dead_zone and hysteresis must be at least 0, and together below 1".to_string());
+    }
+    let duration = |key: &str, ms: Option<i64>| match ms.unwrap_or_default() {
+        ms if ms < 0 => Err(format!("{} cannot be negative", key)),
+        ms => Ok(Duration::from_millis(ms as u64)),
+    };
+    Ok(GateParams {
+        dead_zone,
+        hysteresis,
+        min_on_time: duration("min_on_time", gate.min_on_time)?,
+        min_off_time: duration("min_off_time", gate.min_off_time)?
+    })
+}
+
+fn parse_pattern_triggers(options: &DeviceOptions, global_config: &GlobalConfig) -> Result<Vec<PatternTrigger>, String> {
+    let mut triggers = Vec::new();
+    for entry in options.patterns.iter() {
+        let parameter = Arc::new(format!("/avatar/parameters/{}", entry.parameter));
+        let pattern = patterns::load_pattern(&entry.pattern, Path::new(global_config.pattern_dir.as_str()))?;
+        let mode = match entry.mode.as_ref().map(|mode| mode.as_str()) {
+            Some("mix") | None => PatternMode::Mix,
+            Some("override") => PatternMode::Override,
+            Some(mode) => return Err(format!("Invalid pattern mode '{}' (expected mix or override)", mode)),
+        };
+        triggers.push(PatternTrigger { parameter, pattern: Arc::new(pattern), mode });
+    }
+    Ok(triggers)
+}
+
+// Reads `filter` and its parameters
+fn parse_input_filter(filter: &FilterSettings) -> Result<InputFilter, String> {
+    let get = |value: Option<f32>| value.unwrap_or_default();
+    let kind = filter.filter.clone().unwrap_or_default();
+    let input_filter = match kind.to_lowercase().as_str() {
+        "none" => InputFilter::None,
+        "ema" => InputFilter::Ema { alpha: get(filter.filter_alpha) },
+        "median" => InputFilter::Median { window: get(filter.filter_window) as usize },
+        "one_euro" => InputFilter::OneEuro {
+            min_cutoff: get(filter.filter_min_cutoff),
+            beta: get(filter.filter_beta),
+            d_cutoff: get(filter.filter_d_cutoff)
+        },
+        _ => return Err(format!("Invalid filter '{}' (expected none, ema, median or one_euro)", kind)),
+    };
+
+    match input_filter {
+        InputFilter::Ema { alpha } if !(alpha > 0.0 && alpha <= 1.0) => Err("filter_alpha must be between 0 and 1".to_string()),
+        InputFilter::Median { window: 0 } => Err("filter_window must be at least 1".to_string()),
+        InputFilter::OneEuro { min_cutoff, beta, d_cutoff } if min_cutoff <= 0.0 || d_cutoff <= 0.0 || beta < 0.0 => {
+            Err("filter_min_cutoff and filter_d_cutoff must be greater than 0, filter_beta cannot be negative".to_string())
+        }
+        _ => Ok(input_filter),
+    }
+}
+
+// Reads `curve`, `curve_strength` and `curve_table`
+fn parse_curve(curve: &CurveSettings) -> Result<ResponseCurve, String> {
+    let name = curve.curve.clone().unwrap_or_default();
+    let kind = match CurveKind::parse(&name) {
+        Some(kind) => kind,
+        None => return Err(format!("Invalid curve '{}' (expected linear, exponential, gamma, s_curve or table)", name)),
+    };
+
+    let strength = match curve.curve_strength {
+        Some(strength) if kind == CurveKind::Gamma && strength <= 0.0 => return Err("Gamma curve_strength must be greater than 0".to_string()),
+        Some(strength) if kind == CurveKind::SCurve && strength <= 0.0 => return Err("S-curve curve_strength must be greater than 0".to_string()),
+        strength => strength,
+    };
+
+    let mut table = Vec::new();
+    for point in curve.curve_table.iter().flatten() {
+        match point.as_slice() {
+            [x, y] if (0.0..=1.0).contains(x) && (0.0..=1.0).contains(y) => table.push((*x, *y)),
+            _ => return Err("curve_table entries must be [proximity, output] pairs between 0 and 1".to_string()),
+        }
+    }
+    table.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if kind == CurveKind::Table && table.len() < 2 {
+        return Err("A table curve needs at least 2 points in curve_table".to_string());
+    }
+
+    Ok(ResponseCurve { kind, strength, table: Arc::new(table) })
+}
+
+fn parse_output_format(format: Option<&Text>) -> Result<OutputFormat, String> {
+    let format = format.map(|format| format.as_str()).unwrap_or_default();
+    OutputFormat::parse(format).ok_or_else(|| format!("Invalid output_format '{}' (expected int8, int16 or float)", format))
+}
+
+fn parse_hmac_key(key: Option<&Text>) -> Result<Option<Arc<String>>, String> {
+    match key {
+        Some(key) if key.len() < 16 => Err("hmac_key must be at least 16 characters long".to_string()),
+        Some(key) => Ok(Some(Arc::new(key.to_string()))),
+        None => Ok(None),
+    }
+}
+
+fn parse_led_config(options: &DeviceOptions, global_config: &GlobalConfig) -> Result<Option<LedConfig>, String> {
+    // LED output is only enabled when a source is given
+    let source = match options.led_source.as_ref().map(|source| source.as_str()) {
+        Some("motor") => LedSource::Motor,
+        Some(param) => LedSource::Parameter(Arc::new(format!("/avatar/parameters/{}", param))),
+        None => return Ok(None),
+    };
+
+    let color_parameter = options.led_color_parameter.as_ref().map(|x| Arc::new(format!("/avatar/parameters/{}", x)));
+    let min_brightness = options.led_min_brightness.unwrap_or(defaults::LED_MIN_BRIGHTNESS) / 100.0;
+    let max_brightness = options.led_max_brightness.unwrap_or(defaults::LED_MAX_BRIGHTNESS) / 100.0;
+    if !(0.0..=1.0).contains(&min_brightness) || !(0.0..=1.0).contains(&max_brightness) {
+        return Err("LED brightness must be between 0 and 100".to_string());
+    }
+    let max_brightness = max_brightness.max(min_brightness);
+    let gamma = options.led_gamma.unwrap_or(defaults::LED_GAMMA);
+    if gamma <= 0.0 {
+        return Err("LED gamma must be greater than 0".to_string());
+    }
+    let timeout = options.led_timeout.unwrap_or(global_config.timeout);
+
+    Ok(Some(LedConfig {
+        source,
+        color_parameter,
+        min_brightness,
+        max_brightness,
+        gamma,
+        timeout
+    }))
+}
+
+// Devices of a config.yml that must be valid, for the tests of other modules
+#[cfg(test)]
+pub(crate) fn test_devices(text: &str) -> Vec<DeviceConfig> {
+    let (config, mut report) = diagnostics::check(text, "config.yml");
+    let devices = config.map(|config| parse_config(&config, &mut report).1);
+    assert!(!report.has_errors(), "{}", report);
+    devices.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(text: &str) -> Vec<String> {
+        let (config, mut report) = diagnostics::check(text, "config.yml");
+        parse_config(&config.unwrap(), &mut report);
+        report.errors().map(|error| error.message.clone()).collect()
+    }
+
+    #[test]
+    fn every_wrong_setting_of_an_entry_is_reported() {
+        let errors = errors("
+setup:
+  port_rx: 9001
+devices:
+  - ip: 192.168.1.10
+    proximity_parameter: a
+    min_speed: -5
+    outer_proximity: 0.8
+    inner_proximity: 0.6
+    curve: bogus
+    filter: blur
+  - ip: 192.168.1.10
+    proximity_parameter: b
+    combine: mean
+");
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        for expected in ["min_speed cannot be negative", "inner_proximity (0.6)", "Invalid curve 'bogus'", "Invalid filter 'blur'", "Duplicate ip 192.168.1.10", "Invalid combine mode 'mean'"] {
+            assert!(errors.iter().any(|error| error.starts_with(expected)), "{} missing in {:?}", expected, errors);
+        }
+    }
+
+    #[test]
+    fn every_wrong_member_is_reported() {
+        let errors = errors("
+setup:
+  port_rx: 9001
+devices: []
+groups:
+  - name: back
+    proximity_parameter: d
+    members:
+      - ip: 192.168.1.20
+        gain: 2
+      - ip: 192.168.1.20
+        delay_ms: -4
+      - ip: nowhere
+");
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+    }
+
+    #[test]
+    fn wrong_defaults_are_all_reported() {
+        let errors = errors("
+setup:
+  port_rx: 9001
+  default_min_speed: -5
+  default_speed_scale: -1
+  default_curve: bogus
+devices: []
+");
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+    }
+}
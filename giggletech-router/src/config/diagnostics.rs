@@ -0,0 +1,605 @@
+/*
+    diagnostics.rs - Problems in config.yml with Their Locations
+
+    config.yml is checked completely before it is used, so one run reports every problem instead of
+    the first one. Each problem has the line and column it is at and shows that line:
+
+        Unknown key 'max_sped' in devices[3], did you mean 'max_speed'?
+          --> config.yml:32:5
+           |
+        32 |     max_sped: 100
+           |     ^^^^^^^^
+
+    Errors stop the file from being used. Warnings are reported and the file is used anyway.
+
+    **Key Features:**
+
+    1. **Syntax (`check`)**:
+       - YAML syntax errors are reported with the parser's description, e.g. a missing `:` or a tab
+         in the indentation.
+
+    2. **Keys and Values (`check`)**:
+       - Unknown keys are warnings, with a "did you mean" suggestion for a misspelled key or a
+         missing `default_` prefix. Missing keys and values of the wrong type are errors. Values are
+         checked key by key, so every wrong value is reported.
+
+    3. **Settings (`Report::error_in`, `Report::warning_in`)**:
+       - Problems found while `config.rs` reads the settings (ranges, `inner_proximity` above
+         `outer_proximity`, duplicate ips, ...) are reported at the key they name, or at the start of
+         their device.
+
+    4. **Positions (`Node`)**:
+       - The values of config.yml are read by `serde_yaml`, their positions come from the events of
+         the `yaml_rust` parser.
+*/
+
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+use super::model::{ConfigFile, DeviceEntry, DeviceOptions, GroupEntry, LayoutEntry, MemberEntry, PatternEntry, Settings, Setup, SetupOptions, DEFAULT_PREFIX};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Severity {
+    Error,
+    Warning
+}
+
+// Line and column (both from 1) and width of a part of the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Span {
+    line: usize,
+    column: usize,
+    width: usize
+}
+
+impl Span {
+    fn at(marker: Marker, width: usize) -> Span {
+        Span { line: marker.line(), column: marker.col() + 1, width }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize)
+}
+
+// Where a value is in config.yml, e.g. `devices[2].max_speed`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ConfigPath(Vec<Segment>);
+
+impl ConfigPath {
+    pub fn key(&self, key: &str) -> ConfigPath {
+        let mut path = self.clone();
+        path.0.push(Segment::Key(key.to_string()));
+        path
+    }
+
+    pub fn index(&self, index: usize) -> ConfigPath {
+        let mut path = self.clone();
+        path.0.push(Segment::Index(index));
+        path
+    }
+}
+
+impl fmt::Display for ConfigPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if i == 0 => write!(f, "{}", key)?,
+                Segment::Key(key) => write!(f, ".{}", key)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // File name, position and the line at that position
+    location: Option<(String, Span, String)>
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some((file, span, line)) = &self.location {
+            let number = span.line.to_string();
+            let margin = " ".repeat(number.len());
+            write!(f, "\n{} --> {}:{}:{}", margin, file, span.line, span.column)?;
+            write!(f, "\n{} |\n{} | {}", margin, number, line.replace('\t', " "))?;
+            write!(f, "\n{} | {}{}", margin, " ".repeat(span.column - 1), "^".repeat(span.width.max(1)))?;
+        }
+        Ok(())
+    }
+}
+
+// Positions of the keys and values of config.yml
+#[derive(Debug)]
+enum Node {
+    Scalar(Span),
+    Sequence(Span, Vec<Node>),
+    // Key, position of the key and value
+    Mapping(Span, Vec<(String, Span, Node)>)
+}
+
+impl Node {
+    fn span(&self) -> Span {
+        match self {
+            // A mapping is shown at its first key
+            Node::Mapping(span, entries) => entries.first().map(|(_, key, _)| *key).unwrap_or(*span),
+            Node::Scalar(span) | Node::Sequence(span, _) => *span,
+        }
+    }
+
+    fn child(&self, segment: &Segment) -> Option<(Option<Span>, &Node)> {
+        match (self, segment) {
+            (Node::Mapping(_, entries), Segment::Key(key)) => entries.iter().rev().find(|(name, _, _)| name == key).map(|(_, span, node)| (Some(*span), node)),
+            (Node::Sequence(_, items), Segment::Index(index)) => items.get(*index).map(|node| (None, node)),
+            _ => None,
+        }
+    }
+}
+
+// Builds the `Node` tree from the parser events
+#[derive(Default)]
+struct TreeBuilder {
+    // Open sequences and mappings, with the key waiting for its value
+    stack: Vec<(Node, Option<(String, Span)>)>,
+    root: Option<Node>
+}
+
+impl TreeBuilder {
+    fn add(&mut self, node: Node, text: Option<String>) {
+        match self.stack.last_mut() {
+            None => self.root = Some(node),
+            Some((Node::Sequence(_, items), _)) => items.push(node),
+            Some((Node::Mapping(_, entries), pending)) => match pending.take() {
+                Some((key, span)) => entries.push((key, span, node)),
+                None => *pending = Some((text.unwrap_or_default(), node.span())),
+            },
+            Some((Node::Scalar(_), _)) => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::Scalar(text, style, _, _) => {
+                let quotes = if matches!(style, TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted) { 2 } else { 0 };
+                let width = text.chars().count() + quotes;
+                self.add(Node::Scalar(Span::at(marker, width)), Some(text));
+            }
+            Event::Alias(_) => self.add(Node::Scalar(Span::at(marker, 1)), None),
+            Event::SequenceStart(_) => self.stack.push((Node::Sequence(Span::at(marker, 1), Vec::new()), None)),
+            Event::MappingStart(_) => self.stack.push((Node::Mapping(Span::at(marker, 1), Vec::new()), None)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.add(node, None);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Every problem found in config.yml
+pub(crate) struct Report {
+    file: String,
+    lines: Vec<String>,
+    tree: Option<Node>,
+    // Missing keys, filled in with a placeholder that must not be used
+    placeholders: Vec<ConfigPath>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl Report {
+    fn new(file: &str, text: &str) -> Report {
+        let mut builder = TreeBuilder::default();
+        // Without positions the problems are still reported
+        let tree = Parser::new(text.chars()).load(&mut builder, false).ok().and(builder.root);
+        Report { file: file.to_string(), lines: text.lines().map(str::to_string).collect(), tree, placeholders: Vec::new(), diagnostics: Vec::new() }
+    }
+
+    fn push(&mut self, severity: Severity, span: Option<Span>, message: String) {
+        let location = span.map(|span| (self.file.clone(), span, self.lines.get(span.line - 1).cloned().unwrap_or_default()));
+        self.diagnostics.push(Diagnostic { severity, message, location });
+    }
+
+    fn node(&self, path: &ConfigPath) -> Option<&Node> {
+        path.0.iter().try_fold(self.tree.as_ref()?, |node, segment| node.child(segment).map(|(_, child)| child))
+    }
+
+    fn key_span(&self, path: &ConfigPath) -> Option<Span> {
+        let (last, parent) = path.0.split_last()?;
+        self.node(&ConfigPath(parent.to_vec()))?.child(last).and_then(|(span, _)| span)
+    }
+
+    // Value of the key of `entry` that the message names first, or `entry` itself
+    fn mentioned_span(&self, entry: &ConfigPath, message: &str) -> Option<Span> {
+        let node = self.node(entry)?;
+        let entries = match node {
+            Node::Mapping(_, entries) => entries,
+            _ => return Some(node.span()),
+        };
+        let message = message.to_lowercase();
+        entries.iter()
+            .filter_map(|(key, _, value)| {
+                let short = key.strip_prefix(DEFAULT_PREFIX).unwrap_or(key);
+                [key.as_str(), short].iter().filter_map(|name| find_word(&message, name)).min().map(|position| (position, value.span()))
+            })
+            .min_by_key(|(position, _)| *position)
+            .map(|(_, span)| span)
+            .or_else(|| Some(node.span()))
+    }
+
+    // At the value of `path`
+    pub fn error_at(&mut self, path: &ConfigPath, message: impl Into<String>) {
+        let span = self.node(path).map(Node::span);
+        self.push(Severity::Error, span, message.into());
+    }
+
+    // At the key of `entry` named in the message, or at `entry`
+    pub fn error_in(&mut self, entry: &ConfigPath, message: impl Into<String>) {
+        let message = message.into();
+        let span = self.mentioned_span(entry, &message);
+        self.push(Severity::Error, span, message);
+    }
+
+    pub fn warning_in(&mut self, entry: &ConfigPath, message: impl Into<String>) {
+        let message = message.into();
+        let span = self.mentioned_span(entry, &message);
+        self.push(Severity::Warning, span, message);
+    }
+
+    fn warning_at_key(&mut self, path: &ConfigPath, message: String) {
+        let span = self.key_span(path);
+        self.push(Severity::Warning, span, message);
+    }
+
+    fn syntax_error(&mut self, error: &serde_yaml::Error) {
+        let mut message = error.to_string();
+        let span = error.location().map(|location| {
+            // The position is shown below the message
+            message = message.replacen(&format!(" at line {} column {}", location.line(), location.column()), "", 1);
+            Span { line: location.line(), column: location.column(), width: 1 }
+        });
+        if let Some(span) = span {
+            if self.lines.get(span.line - 1).map(|line| line.contains('\t')).unwrap_or(false) {
+                message.push_str(" (indent with spaces, YAML does not allow tabs)");
+            }
+        }
+        self.push(Severity::Error, span, format!("YAML syntax error: {}", message));
+    }
+
+    // Whether `entry` and everything in it has all the keys it needs, or a key is not a placeholder
+    pub fn is_complete(&self, entry: &ConfigPath) -> bool {
+        !self.placeholders.iter().any(|path| path.0.starts_with(&entry.0))
+    }
+
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_by_key(|diagnostic| diagnostic.location.as_ref().map(|(_, span, _)| (span.line, span.column)));
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Warning)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+}
+
+// The errors, for the error returned when the file can't be used
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.errors().count();
+        write!(f, "{} has {} error{}:", self.file, count, if count == 1 { "" } else { "s" })?;
+        for error in self.errors() {
+            write!(f, "\n\n{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+// Position of `word` in `text` where it is not part of a longer name
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).map(|(position, _)| position).find(|&position| {
+        !text[..position].ends_with(is_name) && !text[position + word.len()..].starts_with(is_name)
+    })
+}
+
+// The known key closest to a misspelled one
+fn suggestion(key: &str, known: &[&str]) -> Option<String> {
+    let prefixed = format!("{}{}", DEFAULT_PREFIX, key);
+    let unprefixed = key.strip_prefix(DEFAULT_PREFIX).unwrap_or(key);
+    if let Some(exact) = known.iter().find(|name| **name == prefixed || **name == unprefixed) {
+        return Some(exact.to_string());
+    }
+    known.iter()
+        .map(|name| (strsim::damerau_levenshtein(key, name), name))
+        .filter(|(distance, name)| *distance <= (name.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name.to_string())
+}
+
+// serde messages without the Rust type names
+fn value_error(key: &str, error: serde_yaml::Error) -> String {
+    let message = error.to_string();
+    let message = message.rsplit(": ").next().filter(|_| message.starts_with(key)).map(str::to_string).unwrap_or(message);
+    if message.contains("untagged enum TextList") {
+        return format!("{} must be a name or a list of names", key);
+    }
+    format!("Invalid {}: {}", key, message.replace("expected f32", "expected a number").replace("expected f64", "expected a number"))
+}
+
+// Unknown and missing keys, and the value of every known key on its own. `required` keys are
+// filled in with a placeholder when another key is checked. `nested` keys hold entries that are
+// checked separately. Wrong values are removed and missing keys filled in, so the rest of the entry
+// can still be checked.
+fn check_mapping<T: DeserializeOwned>(report: &mut Report, path: &ConfigPath, mapping: &mut Mapping, known: &[&str], required: &[(&str, Value)], nested: &[&str]) {
+    let mut wrong = Vec::new();
+    for (key, value) in mapping.iter() {
+        let name = match key.as_str() {
+            Some(name) => name,
+            None => {
+                report.error_at(path, format!("Keys in {} must be names", path));
+                wrong.push(key.clone());
+                continue;
+            }
+        };
+        if !known.contains(&name) {
+            let message = match suggestion(name, known) {
+                Some(suggested) => format!("Unknown key '{}' in {}, did you mean '{}'?", name, path, suggested),
+                None => format!("Unknown key '{}' in {} is ignored", name, path),
+            };
+            report.warning_at_key(&path.key(name), message);
+            continue;
+        }
+        if nested.contains(&name) {
+            continue;
+        }
+        let mut single = Mapping::new();
+        for (required_key, placeholder) in required.iter().filter(|(required_key, _)| *required_key != name) {
+            single.insert(Value::from(*required_key), placeholder.clone());
+        }
+        single.insert(key.clone(), value.clone());
+        if let Err(e) = serde_yaml::from_value::<T>(Value::Mapping(single)) {
+            report.error_at(&path.key(name), value_error(name, e));
+            wrong.push(key.clone());
+        }
+    }
+    for key in wrong.iter() {
+        mapping.remove(key);
+    }
+    for (required_key, placeholder) in required.iter() {
+        if !mapping.contains_key(*required_key) {
+            report.error_at(path, format!("{} needs {}", path, required_key));
+            report.placeholders.push(path.key(required_key));
+            mapping.insert(Value::from(*required_key), placeholder.clone());
+        }
+    }
+}
+
+// Entries of a list, each a mapping
+fn entries<'a>(report: &mut Report, path: &ConfigPath, value: &'a mut Value, what: &str) -> Vec<(ConfigPath, &'a mut Mapping)> {
+    let items = match value {
+        Value::Sequence(items) => items,
+        _ => {
+            report.error_at(path, format!("{} must be a list of {}", path, what));
+            return Vec::new();
+        }
+    };
+    let mut mappings = Vec::new();
+    for (i, item) in items.iter_mut().enumerate() {
+        match item {
+            Value::Mapping(mapping) => mappings.push((path.index(i), mapping)),
+            _ => report.error_at(&path.index(i), format!("{} must be a {} with its settings", path.index(i), what.trim_end_matches('s'))),
+        }
+    }
+    mappings
+}
+
+fn text(value: &str) -> Value {
+    Value::from(value)
+}
+
+fn check_setup(report: &mut Report, path: &ConfigPath, value: &mut Value) {
+    let mapping = match value {
+        Value::Mapping(mapping) => mapping,
+        _ => return report.error_at(path, "setup must be a mapping of settings"),
+    };
+    let defaults = Settings::fields().iter().map(|field| format!("{}{}", DEFAULT_PREFIX, field)).collect::<Vec<_>>();
+    let known = SetupOptions::FIELDS.iter().copied().chain(defaults.iter().map(String::as_str)).collect::<Vec<_>>();
+    check_mapping::<Setup>(report, path, mapping, &known, &[], &[]);
+}
+
+fn check_patterns(report: &mut Report, entry: &ConfigPath, mapping: &mut Mapping) {
+    if let Some(value) = mapping.get_mut("patterns") {
+        for (path, pattern) in entries(report, &entry.key("patterns"), value, "patterns") {
+            check_mapping::<PatternEntry>(report, &path, pattern, PatternEntry::FIELDS, &[("parameter", text("p")), ("pattern", text("p"))], &[]);
+        }
+    }
+}
+
+fn check_device(report: &mut Report, path: &ConfigPath, mapping: &mut Mapping) {
+    let known = [DeviceEntry::FIELDS, &DeviceOptions::fields()].concat();
+    check_mapping::<DeviceEntry>(report, path, mapping, &known, &[("ip", text("0.0.0.0")), ("proximity_parameter", text("p"))], &["patterns"]);
+    check_patterns(report, path, mapping);
+}
+
+fn check_group(report: &mut Report, path: &ConfigPath, mapping: &mut Mapping) {
+    let known = [GroupEntry::FIELDS, &DeviceOptions::fields()].concat();
+    let required = [("name", text("g")), ("proximity_parameter", text("p")), ("members", Value::Sequence(Vec::new()))];
+    check_mapping::<GroupEntry>(report, path, mapping, &known, &required, &["patterns", "members", "layout"]);
+    check_patterns(report, path, mapping);
+    if let Some(value) = mapping.get_mut("members") {
+        for (member_path, member) in entries(report, &path.key("members"), value, "members") {
+            check_mapping::<MemberEntry>(report, &member_path, member, MemberEntry::FIELDS, &[("ip", text("0.0.0.0"))], &[]);
+        }
+    }
+    match mapping.get_mut("layout") {
+        Some(Value::Mapping(layout)) => check_mapping::<LayoutEntry>(report, &path.key("layout"), layout, LayoutEntry::FIELDS, &[], &[]),
+        Some(_) => report.error_at(&path.key("layout"), "layout must be a mapping of settings"),
+        None => {}
+    }
+}
+
+// Checks the text of config.yml. Its model has the wrong values left out, so `config.rs` can check
+// the rest of the settings, and is only missing if the file can't be read at all.
+pub(crate) fn check(text: &str, file: &str) -> (Option<ConfigFile>, Report) {
+    let mut report = Report::new(file, text);
+    let mut value: Value = match serde_yaml::from_str(text) {
+        Ok(value) => value,
+        Err(e) => {
+            report.syntax_error(&e);
+            return (None, report);
+        }
+    };
+
+    let root = ConfigPath::default();
+    let mapping = match &mut value {
+        Value::Mapping(mapping) => mapping,
+        _ => {
+            report.push(Severity::Error, None, "config.yml needs a setup and a devices section".to_string());
+            return (None, report);
+        }
+    };
+    check_mapping::<Value>(&mut report, &root, mapping, ConfigFile::FIELDS, &[], ConfigFile::FIELDS);
+    match mapping.get_mut("setup") {
+        Some(setup) => check_setup(&mut report, &root.key("setup"), setup),
+        None => report.push(Severity::Error, None, "config.yml has no setup section".to_string()),
+    }
+    match mapping.get_mut("devices") {
+        Some(devices) => {
+            for (path, device) in entries(&mut report, &root.key("devices"), devices, "devices") {
+                check_device(&mut report, &path, device);
+            }
+        }
+        None => report.push(Severity::Error, None, "config.yml has no devices section".to_string()),
+    }
+    if let Some(groups) = mapping.get_mut("groups") {
+        for (path, group) in entries(&mut report, &root.key("groups"), groups, "groups") {
+            check_group(&mut report, &path, group);
+        }
+    }
+
+    report.sort();
+    match serde_yaml::from_value(value) {
+        Ok(config) => (Some(config), report),
+        // Already reported, e.g. a list that isn't a list
+        Err(_) if report.has_errors() => (None, report),
+        Err(e) => {
+            report.push(Severity::Error, None, e.to_string());
+            (None, report)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(text: &str) -> Vec<(Severity, String, Option<usize>)> {
+        let (_, report) = check(text, "config.yml");
+        report.diagnostics.iter().map(|diagnostic| (diagnostic.severity, diagnostic.message.clone(), diagnostic.location.as_ref().map(|(_, span, _)| span.line))).collect()
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_line() {
+        let found = messages("setup:
+  port_rx: 9001
+  default_max_sped: 20
+devices:
+  - ip: 192.168.1.69
+    proximity_parameter: a
+    max_sped: 100
+  - ip: 192.168.1.70
+    max_speed: fast
+    min_speed: [1]
+");
+        assert_eq!(found, vec![
+            (Severity::Warning, "Unknown key 'default_max_sped' in setup, did you mean 'default_max_speed'?".to_string(), Some(3)),
+            (Severity::Warning, "Unknown key 'max_sped' in devices[0], did you mean 'max_speed'?".to_string(), Some(7)),
+            (Severity::Error, "devices[1] needs proximity_parameter".to_string(), Some(8)),
+            (Severity::Error, "Invalid max_speed: invalid type: string \"fast\", expected a number".to_string(), Some(9)),
+            (Severity::Error, "Invalid min_speed: invalid type: sequence, expected a number".to_string(), Some(10)),
+        ]);
+    }
+
+    #[test]
+    fn device_settings_in_setup_suggest_the_prefix() {
+        let found = messages("setup:\n  max_speed: 20\ndevices: []\n");
+        assert_eq!(found, vec![(Severity::Warning, "Unknown key 'max_speed' in setup, did you mean 'default_max_speed'?".to_string(), Some(2))]);
+    }
+
+    #[test]
+    fn groups_members_and_patterns_are_checked() {
+        let found = messages("setup: {}
+devices: []
+groups:
+  - name: head
+    proximity_parameter: a
+    members:
+      - ip: 192.168.1.69
+        gian: 0.5
+      - gain: 1
+    patterns:
+      - parameter: boop
+");
+        assert_eq!(found, vec![
+            (Severity::Warning, "Unknown key 'gian' in groups[0].members[0], did you mean 'gain'?".to_string(), Some(8)),
+            (Severity::Error, "groups[0].members[1] needs ip".to_string(), Some(9)),
+            (Severity::Error, "groups[0].patterns[0] needs pattern".to_string(), Some(11)),
+        ]);
+    }
+
+    #[test]
+    fn wrong_values_are_left_out_of_the_model() {
+        let (config, report) = check("setup: {}\ndevices:\n  - ip: 192.168.1.69\n    proximity_parameter: a\n    max_speed: fast\n  - min_speed: 10\n", "config.yml");
+        let config = config.expect("the rest of the file can still be checked");
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.devices[0].options.settings.speed.max_speed, None);
+        assert_eq!(config.devices[1].options.settings.speed.min_speed, Some(10.0));
+        assert!(report.is_complete(&ConfigPath::default().key("devices").index(0)));
+        assert!(!report.is_complete(&ConfigPath::default().key("devices").index(1)));
+        assert!(!report.is_complete(&ConfigPath::default().key("devices")));
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_problem() {
+        let (config, report) = check("setup:\n  port_rx: 9001\n devices:\n  - ip: 1\n", "config.yml");
+        assert!(config.is_none());
+        assert_eq!(report.diagnostics.len(), 1);
+        assert!(report.diagnostics[0].message.starts_with("YAML syntax error"));
+        assert!(report.diagnostics[0].location.is_some());
+    }
+
+    #[test]
+    fn errors_from_settings_point_at_the_key_they_name() {
+        let text = "setup: {}\ndevices:\n  - ip: 192.168.1.69\n    proximity_parameter: a\n    inner_proximity: 0.2\n    outer_proximity: 0.5\n";
+        let (config, mut report) = check(text, "config.yml");
+        assert!(config.is_some());
+        let device = ConfigPath::default().key("devices").index(0);
+        report.error_in(&device, "inner_proximity must be greater than outer_proximity");
+        report.error_in(&device, "Something else");
+        assert_eq!(report.diagnostics[0].to_string(), "inner_proximity must be greater than outer_proximity
+  --> config.yml:5:22
+  |
+5 |     inner_proximity: 0.2
+  |                      ^^^");
+        assert_eq!(report.diagnostics[1].location.as_ref().map(|(_, span, _)| (span.line, span.column)), Some((3, 5)));
+    }
+}
@@ -0,0 +1,528 @@
+/*
+    model.rs - Typed Model of config.yml
+
+    `config.yml` is deserialized into these types in one pass, `config.rs` then turns them into
+    `GlobalConfig` and `DeviceConfig`. Settings are optional in the file: a device falls back to the
+    `default_*` value in `setup`, and `setup` falls back to the built-in default.
+
+    **Key Features:**
+
+    1. **Built-in Defaults (`defaults`, `Settings::builtin`)**:
+       - `defaults` holds the defaults of single options, `Settings::builtin` those of the device
+         settings. The `log_*` options are the exception, they default to `LogSettings::default`
+         in `logging`.
+
+    2. **Shared Settings (`Settings`)**:
+       - The settings a device, a group and (with a `default_` prefix) `setup` can take, grouped by
+         the module they configure. `or` fills the values that are not set from a fallback.
+
+    3. **Setup Section (`Setup`)**:
+       - Splits `setup` into its own options and the `default_*` settings for devices.
+
+    4. **Scalars as Text (`Text`)**:
+       - Names and addresses can be written as text or as numbers, e.g. `port_rx: 9001`.
+
+    5. **Known Keys (`FIELDS`, `fields`)**:
+       - The keys each entry takes, used by `diagnostics` to report unknown keys. They are kept next
+         to the fields they name.
+*/
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+
+// Built-in defaults of values not set in config.yml
+pub(crate) mod defaults {
+    pub const PORT_RX: &str = "9001";
+    pub const TIMEOUT: u64 = 5;
+    pub const PATTERN_DIR: &str = "patterns";
+    // Lowest max speed a device can be limited to
+    pub const MINIMUM_MAX_SPEED: f32 = 0.05;
+    pub const MEMBER_GAIN: f32 = 1.0;
+    pub const LAYOUT_SPREAD: f32 = 1.0;
+    pub const LED_MIN_BRIGHTNESS: f32 = 0.0;
+    pub const LED_MAX_BRIGHTNESS: f32 = 100.0;
+    pub const LED_GAMMA: f32 = 2.2;
+    pub const PULSE_PERIOD_MS: u64 = 200;
+}
+
+// Prefix of device settings in `setup`
+pub(crate) const DEFAULT_PREFIX: &str = "default_";
+
+// A scalar read as text, numbers and booleans included
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Text(pub String);
+
+impl Deref for Text {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.0
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for Text {
+    fn from(text: &str) -> Text {
+        Text(text.to_string())
+    }
+}
+
+struct TextVisitor;
+
+impl<'de> Visitor<'de> for TextVisitor {
+    type Value = Text;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("text or a number")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Text, E> {
+        Ok(Text(value.to_string()))
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Text, E> {
+        Ok(Text(value.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Text, E> {
+        Ok(Text(value.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Text, E> {
+        Ok(Text(value.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Text, E> {
+        Ok(Text(value.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Text, D::Error> {
+        deserializer.deserialize_any(TextVisitor)
+    }
+}
+
+// A single value or a list of values
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum TextList {
+    One(Text),
+    Many(Vec<Text>)
+}
+
+impl TextList {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            TextList::One(text) => vec![text.0.clone()],
+            TextList::Many(texts) => texts.iter().map(|text| text.0.clone()).collect(),
+        }
+    }
+}
+
+// Declares a group of optional settings that can fall back to another group
+macro_rules! settings {
+    ($name:ident { $($field:ident: $ty:ty),* $(,)? }) => {
+        #[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+        pub(crate) struct $name {
+            $(pub $field: Option<$ty>),*
+        }
+
+        impl $name {
+            pub const FIELDS: &'static [&'static str] = &[$(stringify!($field)),*];
+
+            pub fn or(&self, fallback: &$name) -> $name {
+                $name { $($field: self.$field.clone().or_else(|| fallback.$field.clone())),* }
+            }
+        }
+    };
+}
+
+// Speeds in percent, `start_tx` as a motor value
+settings!(SpeedSettings {
+    min_speed: f32,
+    max_speed: f32,
+    start_tx: i32,
+    speed_scale: f32,
+    max_speed_parameter: Text,
+});
+
+settings!(ControlSettings {
+    control_mode: Text,
+    use_velocity_control: bool,
+});
+
+settings!(MotionSettings {
+    proximity_weight: f32,
+    velocity_weight: f32,
+    outer_proximity: f32,
+    inner_proximity: f32,
+    velocity_scalar: f32,
+    respond_to_retreat: bool,
+    velocity_smoothing: f32,
+    velocity_decay: f32,
+});
+
+settings!(CurveSettings {
+    curve: Text,
+    curve_strength: f32,
+    curve_table: Vec<Vec<f32>>,
+});
+
+settings!(FilterSettings {
+    filter: Text,
+    filter_alpha: f32,
+    filter_window: f32,
+    filter_min_cutoff: f32,
+    filter_beta: f32,
+    filter_d_cutoff: f32,
+});
+
+// Times in milliseconds
+settings!(GateSettings {
+    dead_zone: f32,
+    hysteresis: f32,
+    min_on_time: i64,
+    min_off_time: i64,
+});
+
+// Times in seconds, `max_heat` and `duty_cycle` in percent
+settings!(GovernorSettings {
+    thermal_time_constant: f32,
+    max_heat: f32,
+    max_runtime: f32,
+    rest_time: f32,
+    duty_cycle: f32,
+    duty_window: f32,
+});
+
+// Times in milliseconds, `sustain` and `max_slew_rate` in percent
+settings!(EnvelopeSettings {
+    attack: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+    max_slew_rate: f32,
+});
+
+// Settings of a device or group, and the `default_*` settings of `setup`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub(crate) struct Settings {
+    #[serde(flatten)]
+    pub speed: SpeedSettings,
+    #[serde(flatten)]
+    pub control: ControlSettings,
+    #[serde(flatten)]
+    pub motion: MotionSettings,
+    #[serde(flatten)]
+    pub curve: CurveSettings,
+    #[serde(flatten)]
+    pub filter: FilterSettings,
+    #[serde(flatten)]
+    pub gate: GateSettings,
+    #[serde(flatten)]
+    pub governor: GovernorSettings,
+    #[serde(flatten)]
+    pub envelope: EnvelopeSettings,
+    pub output_format: Option<Text>
+}
+
+impl Settings {
+    // Used for everything not set in config.yml. Only `control_mode` and `curve_strength` have no
+    // default, they follow `use_velocity_control` and the curve.
+    pub fn builtin() -> Settings {
+        Settings {
+            speed: SpeedSettings {
+                min_speed: Some(5.0),
+                max_speed: Some(25.0),
+                start_tx: Some(20),
+                speed_scale: Some(100.0),
+                max_speed_parameter: Some(Text::from("max_speed"))
+            },
+            control: ControlSettings {
+                control_mode: None,
+                use_velocity_control: Some(false)
+            },
+            motion: MotionSettings {
+                proximity_weight: Some(0.5),
+                velocity_weight: Some(0.5),
+                outer_proximity: Some(0.0),
+                inner_proximity: Some(0.7),
+                velocity_scalar: Some(20.0),
+                respond_to_retreat: Some(false),
+                velocity_smoothing: Some(0.08),
+                velocity_decay: Some(0.25)
+            },
+            curve: CurveSettings {
+                curve: Some(Text::from("linear")),
+                curve_strength: None,
+                curve_table: None
+            },
+            filter: FilterSettings {
+                filter: Some(Text::from("none")),
+                filter_alpha: Some(0.5),
+                filter_window: Some(5.0),
+                filter_min_cutoff: Some(1.0),
+                filter_beta: Some(1.0),
+                filter_d_cutoff: Some(1.0)
+            },
+            gate: GateSettings {
+                dead_zone: Some(0.0),
+                hysteresis: Some(0.0),
+                min_on_time: Some(0),
+                min_off_time: Some(0)
+            },
+            governor: GovernorSettings {
+                thermal_time_constant: Some(60.0),
+                max_heat: Some(100.0),
+                max_runtime: Some(0.0),
+                rest_time: Some(10.0),
+                duty_cycle: Some(100.0),
+                duty_window: Some(60.0)
+            },
+            envelope: EnvelopeSettings {
+                attack: Some(0.0),
+                decay: Some(0.0),
+                sustain: Some(100.0),
+                release: Some(0.0),
+                max_slew_rate: Some(0.0)
+            },
+            output_format: Some(Text::from("int8"))
+        }
+    }
+
+    // Keys of every setting
+    pub fn fields() -> Vec<&'static str> {
+        [SpeedSettings::FIELDS, ControlSettings::FIELDS, MotionSettings::FIELDS, CurveSettings::FIELDS, FilterSettings::FIELDS,
+            GateSettings::FIELDS, GovernorSettings::FIELDS, EnvelopeSettings::FIELDS, &["output_format"]].concat()
+    }
+
+    pub fn or(&self, fallback: &Settings) -> Settings {
+        Settings {
+            speed: self.speed.or(&fallback.speed),
+            control: self.control.or(&fallback.control),
+            motion: self.motion.or(&fallback.motion),
+            curve: self.curve.or(&fallback.curve),
+            filter: self.filter.or(&fallback.filter),
+            gate: self.gate.or(&fallback.gate),
+            governor: self.governor.or(&fallback.governor),
+            envelope: self.envelope.or(&fallback.envelope),
+            output_format: self.output_format.clone().or_else(|| fallback.output_format.clone())
+        }
+    }
+}
+
+// Options of `setup` that are not device defaults
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub(crate) struct SetupOptions {
+    pub port_rx: Option<Text>,
+    pub timeout: Option<u64>,
+    pub pattern_dir: Option<Text>,
+    pub log_level: Option<Text>,
+    pub console_log_level: Option<Text>,
+    pub log_filters: Option<BTreeMap<String, Text>>,
+    pub log_format: Option<Text>,
+    pub log_dir: Option<Text>,
+    pub log_rotation: Option<Text>,
+    pub log_max_size: Option<f64>,
+    pub log_keep: Option<i64>
+}
+
+impl SetupOptions {
+    pub const FIELDS: &'static [&'static str] = &[
+        "port_rx", "timeout", "pattern_dir", "log_level", "console_log_level", "log_filters", "log_format", "log_dir",
+        "log_rotation", "log_max_size", "log_keep"
+    ];
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(try_from = "Mapping")]
+pub(crate) struct Setup {
+    pub options: SetupOptions,
+    // The `default_*` settings without their prefix
+    pub defaults: Settings
+}
+
+impl TryFrom<Mapping> for Setup {
+    type Error = serde_yaml::Error;
+
+    fn try_from(mapping: Mapping) -> Result<Setup, serde_yaml::Error> {
+        let mut options = Mapping::new();
+        let mut defaults = Mapping::new();
+        for (key, value) in mapping.into_iter() {
+            match key.as_str().and_then(|name| name.strip_prefix(DEFAULT_PREFIX)) {
+                Some(name) => defaults.insert(Value::String(name.to_string()), value),
+                None => options.insert(key, value),
+            };
+        }
+        Ok(Setup {
+            options: serde_yaml::from_value(Value::Mapping(options))?,
+            defaults: serde_yaml::from_value(Value::Mapping(defaults))?
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct PatternEntry {
+    pub parameter: Text,
+    pub pattern: Text,
+    pub mode: Option<Text>
+}
+
+impl PatternEntry {
+    pub const FIELDS: &'static [&'static str] = &["parameter", "pattern", "mode"];
+}
+
+// Options shared by devices and groups
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct DeviceOptions {
+    pub proximity_parameter: TextList,
+    pub combine: Option<Text>,
+    #[serde(default)]
+    pub weights: Vec<f32>,
+    pub script: Option<Text>,
+    pub curve_parameter: Option<Text>,
+    pub hmac_key: Option<Text>,
+    pub led_source: Option<Text>,
+    pub led_color_parameter: Option<Text>,
+    pub led_min_brightness: Option<f32>,
+    pub led_max_brightness: Option<f32>,
+    pub led_gamma: Option<f32>,
+    pub led_timeout: Option<u64>,
+    #[serde(default)]
+    pub pulse_mode: bool,
+    pub stall_tx: Option<i64>,
+    pub pulse_tx: Option<i64>,
+    pub pulse_period: Option<i64>,
+    #[serde(default)]
+    pub patterns: Vec<PatternEntry>,
+    #[serde(default)]
+    pub gestures: BTreeMap<String, Text>,
+    #[serde(flatten)]
+    pub settings: Settings
+}
+
+impl DeviceOptions {
+    // Keys of the options and of the settings
+    pub fn fields() -> Vec<&'static str> {
+        [&[
+            "proximity_parameter", "combine", "weights", "script", "curve_parameter", "hmac_key", "led_source",
+            "led_color_parameter", "led_min_brightness", "led_max_brightness", "led_gamma", "led_timeout", "pulse_mode",
+            "stall_tx", "pulse_tx", "pulse_period", "patterns", "gestures"
+        ][..], &Settings::fields()].concat()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct DeviceEntry {
+    pub ip: Text,
+    #[serde(flatten)]
+    pub options: DeviceOptions
+}
+
+impl DeviceEntry {
+    pub const FIELDS: &'static [&'static str] = &["ip"];
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct MemberEntry {
+    pub ip: Text,
+    pub gain: Option<f32>,
+    pub delay_ms: Option<i64>,
+    pub hmac_key: Option<Text>,
+    pub output_format: Option<Text>,
+    pub position: Option<Vec<f32>>
+}
+
+impl MemberEntry {
+    pub const FIELDS: &'static [&'static str] = &["ip", "gain", "delay_ms", "hmac_key", "output_format", "position"];
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct LayoutEntry {
+    pub spread: Option<f32>,
+    pub position_parameters: Option<TextList>,
+    pub directions: Option<Vec<Vec<f32>>>
+}
+
+impl LayoutEntry {
+    pub const FIELDS: &'static [&'static str] = &["spread", "position_parameters", "directions"];
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct GroupEntry {
+    pub name: Text,
+    pub members: Vec<MemberEntry>,
+    pub layout: Option<LayoutEntry>,
+    #[serde(flatten)]
+    pub options: DeviceOptions
+}
+
+impl GroupEntry {
+    pub const FIELDS: &'static [&'static str] = &["name", "members", "layout"];
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct ConfigFile {
+    pub setup: Setup,
+    pub devices: Vec<DeviceEntry>,
+    #[serde(default)]
+    pub groups: Vec<GroupEntry>
+}
+
+impl ConfigFile {
+    pub const FIELDS: &'static [&'static str] = &["setup", "devices", "groups"];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+setup:
+  port_rx: 9001
+  default_max_speed: 40
+  default_use_velocity_control: True
+  log_level: debug
+devices:
+  - ip: 192.168.1.69
+    proximity_parameter: proximity_01
+    velocity_scalar: 12.5
+  - ip: 192.168.1.70
+    proximity_parameter: [left, right]
+    max_speed: 80
+";
+
+    #[test]
+    fn setup_defaults_are_split_from_options() {
+        let config: ConfigFile = serde_yaml::from_str(CONFIG).unwrap();
+        assert_eq!(config.setup.options.port_rx, Some(Text::from("9001")));
+        assert_eq!(config.setup.options.log_level, Some(Text::from("debug")));
+        assert_eq!(config.setup.defaults.speed.max_speed, Some(40.0));
+        assert_eq!(config.setup.defaults.control.use_velocity_control, Some(true));
+        assert_eq!(config.devices[1].options.proximity_parameter.to_vec(), vec!["left", "right"]);
+    }
+
+    #[test]
+    fn devices_fall_back_to_setup_then_builtin_defaults() {
+        let config: ConfigFile = serde_yaml::from_str(CONFIG).unwrap();
+        let defaults = config.setup.defaults.or(&Settings::builtin());
+        let first = config.devices[0].options.settings.or(&defaults);
+        let second = config.devices[1].options.settings.or(&defaults);
+        assert_eq!(first.motion.velocity_scalar, Some(12.5));
+        assert_eq!(first.speed.max_speed, Some(40.0));
+        assert_eq!(first.speed.min_speed, Some(5.0));
+        assert_eq!(second.speed.max_speed, Some(80.0));
+        assert_eq!(second.motion.velocity_scalar, Some(20.0));
+    }
+}
@@ -46,6 +46,7 @@ use dirs::data_local_dir;
 use serde::Deserialize;
 use reqwest::blocking::Client;
 use serde_yaml;
+use log::{info, warn};
 
 // Struct to deserialize the YAML config
 #[derive(Debug, Deserialize)]
@@ -70,7 +71,7 @@ fn run_giggletech() -> Child {
     executable_path.push("giggletech_oscq.exe");
 
     // Display a message indicating the process is being started and the directory it's being started from
-    println!(
+    info!(
         "Starting OSCQ Server from the directory: {}",
         executable_path.display()
     );
@@ -110,19 +111,19 @@ pub fn initialize_and_get_udp_port() -> i32 {
         match get_udp_port(config.httpPort) {
             Ok(0) => {
                 // If UDP port is 0, send the start command
-                println!("UDP port is 0, sending start command...");
+                info!("UDP port is 0, sending start command...");
                 if let Err(e) = start_server(config.httpPort) {
-                    eprintln!("Failed to start server: {}", e);
+                    warn!("Failed to start server: {}", e);
                 }
             }
             Ok(port_value) => {
                 // If we get a valid non-zero port, return it
-                println!("UDP port: {}", port_value);
+                info!("UDP port: {}", port_value);
                 return port_value;
             }
             Err(_) => {
                 // If the request fails, restart the process
-                eprintln!("Failed to retrieve UDP port, restarting giggletech process...");
+                warn!("Failed to retrieve UDP port, restarting giggletech process...");
                 let _ = process.kill(); // Kill the current process
                 process = run_giggletech(); // Restart the process
             }
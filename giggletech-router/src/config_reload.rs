@@ -0,0 +1,463 @@
+/*
+    config_reload.rs - Hot Reload of config.yml
+
+    config.yml is checked for changes every second while the router runs. A changed file is read
+    and validated like at startup and applied without a restart: devices and groups can be added,
+    removed or retuned. If the new file is invalid, the error is reported and the running
+    configuration is kept.
+
+    **Key Features:**
+
+    1. **Device Tasks (`start_device`, `stop_device`)**:
+       - Signing keys and the timeout, LED timeout and governor tasks of each device. The tasks of a
+         retuned device are restarted with its new settings.
+
+    2. **Current Devices (`set_devices`, `current_devices`)**:
+       - The devices of the running configuration, used by the dashboard and the session history.
+
+    3. **Watching (`watch_config`)**:
+       - Devices are matched by their address (`ip` or group name, `Changes`). Added and retuned
+         devices are started, then the main loop switches to the new device list between two
+         packets (`ConfigUpdate`). A retuned device drops the curve selected at runtime.
+       - Once the main loop has switched, no packet reaches the old devices any more. Only then are
+         removed devices and members dropped from a group stopped (`retire`) and the state of
+         removed devices forgotten (`forget_device`).
+       - A new `timeout` restarts the tasks of every device and new log settings are applied
+         directly. A new `port_rx` needs a restart.
+*/
+
+use async_std::channel::{self, Sender};
+use async_std::task::{self, JoinHandle};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+
+use crate::config::{self, DeviceConfig, GlobalConfig, GroupMember};
+use crate::contact_gate;
+use crate::dashboard;
+use crate::envelope;
+use crate::gestures;
+use crate::governor;
+use crate::handle_proximity_parameter;
+use crate::input_combiner;
+use crate::led_output;
+use crate::logging;
+use crate::motion_model;
+use crate::motor_output;
+use crate::osc_auth;
+use crate::osc_timeout::{self, osc_timeout};
+use crate::patterns;
+use crate::paths;
+use crate::pulse;
+use crate::response_curve;
+use crate::scripting;
+use crate::spatial;
+
+// Interval between checks of config.yml for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref DEVICES: RwLock<Vec<DeviceConfig>> = RwLock::new(Vec::new());
+    static ref DEVICE_TASKS: Mutex<HashMap<String, Vec<JoinHandle<()>>>> = Mutex::new(HashMap::new());
+    static ref WATCHED_SCRIPTS: Mutex<HashSet<Arc<String>>> = Mutex::new(HashSet::new());
+}
+
+// New device list for the main loop
+pub(crate) struct ConfigUpdate {
+    pub devices: Vec<DeviceConfig>,
+    // Addresses of the devices whose settings changed
+    pub retuned: HashSet<String>,
+    // Told once the main loop switched to the new devices
+    pub applied: Sender<()>
+}
+
+impl ConfigUpdate {
+    // Unchanged devices keep their running copy, e.g. a max speed set from the avatar
+    pub fn apply(self, running: Vec<DeviceConfig>) -> Vec<DeviceConfig> {
+        let retuned = self.retuned;
+        let devices = self.devices.into_iter().map(|device| {
+            match running.iter().find(|old| old.device_uri == device.device_uri) {
+                Some(old) if !retuned.contains(device.device_uri.as_str()) => old.clone(),
+                _ => device,
+            }
+        }).collect();
+        let _ = self.applied.try_send(());
+        devices
+    }
+}
+
+// Devices of a new configuration compared with the running ones, matched by address
+#[derive(Debug, Default)]
+struct Changes<'a> {
+    removed: Vec<&'a DeviceConfig>,
+    added: Vec<&'a DeviceConfig>,
+    // Running and new settings
+    retuned: Vec<(&'a DeviceConfig, &'a DeviceConfig)>,
+    unchanged: Vec<(&'a DeviceConfig, &'a DeviceConfig)>
+}
+
+impl<'a> Changes<'a> {
+    fn between(running: &'a [DeviceConfig], devices: &'a [DeviceConfig]) -> Changes<'a> {
+        let mut changes = Changes {
+            removed: running.iter().filter(|old| !devices.iter().any(|device| device.device_uri == old.device_uri)).collect(),
+            ..Changes::default()
+        };
+        for device in devices.iter() {
+            match running.iter().find(|old| old.device_uri == device.device_uri) {
+                None => changes.added.push(device),
+                Some(old) if old != device => changes.retuned.push((old, device)),
+                Some(old) => changes.unchanged.push((old, device)),
+            }
+        }
+        changes
+    }
+}
+
+pub(crate) fn set_devices(devices: Vec<DeviceConfig>) {
+    if let Ok(mut current) = DEVICES.write() {
+        *current = devices;
+    }
+}
+
+pub(crate) fn current_devices() -> Vec<DeviceConfig> {
+    DEVICES.read().map(|devices| devices.clone()).unwrap_or_default()
+}
+
+pub(crate) fn register_keys(device: &DeviceConfig) {
+    for target in device.targets() {
+        if let Some(key) = &target.hmac_key {
+            osc_auth::register_key(&target.ip, key);
+        }
+    }
+}
+
+fn remove_keys(targets: &[GroupMember]) {
+    for target in targets.iter().filter(|target| target.hmac_key.is_some()) {
+        osc_auth::remove_key(&target.ip);
+    }
+}
+
+// Registers the device's signing keys and starts its background tasks
+pub(crate) fn start_device(device: &DeviceConfig, timeout: u64) {
+    register_keys(device);
+
+    let mut handles = Vec::new();
+    let timeout_device = device.clone();
+    handles.push(task::spawn(async move {
+        if let Err(e) = osc_timeout(&timeout_device, timeout).await {
+            error!("Timeout error for device {}: {}", timeout_device.device_uri, e);
+        }
+    }));
+
+    if let Some(led) = &device.led {
+        let led_device = device.clone();
+        let led_timeout = led.timeout;
+        handles.push(task::spawn(async move {
+            if let Err(e) = led_output::led_timeout(&led_device, led_timeout).await {
+                error!("LED timeout error for device {}: {}", led_device.device_uri, e);
+            }
+        }));
+    }
+
+    if device.governor.enabled() {
+        handles.push(task::spawn(governor::governor_worker(device.clone())));
+    }
+
+    if let Ok(mut device_tasks) = DEVICE_TASKS.lock() {
+        device_tasks.insert(device.device_uri.to_string(), handles);
+    }
+}
+
+async fn stop_tasks(device: &DeviceConfig) {
+    let handles = match DEVICE_TASKS.lock() {
+        Ok(mut device_tasks) => device_tasks.remove(device.device_uri.as_str()).unwrap_or_default(),
+        Err(_) => return,
+    };
+    for handle in handles {
+        handle.cancel().await;
+    }
+}
+
+// Forgets the keys and governor state of targets no longer driven. A target another entry of the
+// new configuration drives, e.g. a device moved into a group, keeps them.
+fn release_targets(targets: &[GroupMember], devices: &[DeviceConfig]) {
+    let driven = |target: &GroupMember| devices.iter().any(|device| device.targets().iter().any(|other| other.ip == target.ip));
+    let released = targets.iter().filter(|target| !driven(target)).cloned().collect::<Vec<_>>();
+    remove_keys(&released);
+    for target in released.iter() {
+        governor::remove(target);
+    }
+}
+
+// Stops a device that is no longer configured and forgets its state
+async fn stop_device(device: &DeviceConfig, devices: &[DeviceConfig]) {
+    stop_tasks(device).await;
+    contact_gate::force_off(device);
+    if let Err(e) = motor_output::send_stop(device).await {
+        warn!("Failed to stop removed device {}: {}", device.device_uri, e);
+    }
+    release_targets(&device.targets(), devices);
+    forget_device(device).await;
+}
+
+// Every piece of state kept for a device, so a device added again with the same address starts fresh.
+// The session history is kept for the session summary.
+async fn forget_device(device: &DeviceConfig) {
+    handle_proximity_parameter::remove(device).await;
+    osc_timeout::remove(device);
+    input_combiner::remove(device);
+    contact_gate::remove(device);
+    gestures::remove(device);
+    envelope::remove(device);
+    patterns::remove(device);
+    motion_model::reset(device);
+    scripting::remove(device);
+    response_curve::remove(device);
+    spatial::remove(device);
+    pulse::remove(device);
+    led_output::remove(device);
+    motor_output::remove(device);
+    dashboard::remove(device);
+}
+
+// Members a retuned device no longer drives
+fn dropped_members(old: &DeviceConfig, device: &DeviceConfig) -> Vec<GroupMember> {
+    let targets = device.targets();
+    old.targets().into_iter().filter(|old_target| !targets.iter().any(|target| target.ip == old_target.ip)).collect()
+}
+
+// Starts a watcher for every script file that is not watched yet
+pub(crate) fn watch_scripts(devices: &[DeviceConfig]) {
+    if let Ok(mut watched) = WATCHED_SCRIPTS.lock() {
+        for script in devices.iter().filter_map(|device| device.script.clone()) {
+            if watched.insert(script.clone()) {
+                task::spawn(scripting::watch_script(script));
+            }
+        }
+    }
+}
+
+fn modified_time() -> Option<SystemTime> {
+    std::fs::metadata(paths::config_path()).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Starts the devices of a new configuration and returns the update for the main loop
+async fn apply(running_config: &GlobalConfig, running: &[DeviceConfig], global_config: &GlobalConfig, devices: &[DeviceConfig], changes: &Changes<'_>, applied: Sender<()>) -> ConfigUpdate {
+    if global_config.port_rx_setting != running_config.port_rx_setting {
+        warn!("port_rx changed to {}, restart the router to listen on the new port", global_config.port_rx_setting);
+    }
+    if global_config.logging != running_config.logging {
+        logging::configure(global_config.logging.clone());
+    }
+    let timeout_changed = global_config.timeout != running_config.timeout;
+
+    for device in changes.added.iter() {
+        start_device(device, global_config.timeout);
+        info!("Added {} => {}", device.parameter_label(), device.device_uri);
+    }
+    for (old, device) in changes.retuned.iter() {
+        stop_tasks(old).await;
+        // Dropped members keep their keys until they are stopped
+        let dropped = dropped_members(old, device);
+        let kept = old.targets().into_iter().filter(|target| !dropped.iter().any(|dropped| dropped.ip == target.ip)).collect::<Vec<_>>();
+        remove_keys(&kept);
+        // The new settings may set another curve than the one switched to at runtime
+        response_curve::remove(old);
+        start_device(device, global_config.timeout);
+        info!("Retuned {}", device.device_uri);
+    }
+    if timeout_changed {
+        for (old, device) in changes.unchanged.iter() {
+            stop_tasks(old).await;
+            start_device(device, global_config.timeout);
+        }
+    }
+    for device in devices.iter().filter(|device| !running.contains(device)) {
+        dashboard::set_max_speed(device);
+    }
+
+    watch_scripts(devices);
+    set_devices(devices.to_vec());
+    let retuned = changes.retuned.iter().map(|(_, device)| device.device_uri.to_string()).collect();
+    ConfigUpdate { devices: devices.to_vec(), retuned, applied }
+}
+
+// Stops what the new configuration no longer drives, after the main loop switched to it
+async fn retire(changes: &Changes<'_>, devices: &[DeviceConfig]) {
+    for device in changes.removed.iter() {
+        stop_device(device, devices).await;
+        info!("Removed {}", device.device_uri);
+    }
+    for (old, device) in changes.retuned.iter() {
+        let dropped = dropped_members(old, device);
+        if dropped.is_empty() {
+            continue;
+        }
+        // Ends the patterns and fades still sending to the old members, then stops every old member
+        if let Err(e) = motor_output::send_stop(old).await {
+            warn!("Failed to stop {}: {}", old.device_uri, e);
+        }
+        release_targets(&dropped, devices);
+        for target in dropped.iter() {
+            info!("Stopped {}, no longer a member of {}", target.ip, device.device_uri);
+        }
+    }
+    info!("Reloaded config.yml: {} added, {} removed, {} retuned", changes.added.len(), changes.removed.len(), changes.retuned.len());
+}
+
+// Checks config.yml for changes and applies every valid version
+pub(crate) async fn watch_config(mut global_config: GlobalConfig, mut devices: Vec<DeviceConfig>, updates: Sender<ConfigUpdate>) {
+    let mut modified = modified_time();
+    loop {
+        task::sleep(RELOAD_INTERVAL).await;
+        let current = modified_time();
+        if current == modified {
+            continue;
+        }
+        // Remember the new time either way so a broken file is reported once
+        modified = current;
+
+        match config::reload_config(&global_config) {
+            Ok((new_config, new_devices)) => {
+                let changes = Changes::between(&devices, &new_devices);
+                let (applied, switched) = channel::bounded(1);
+                let update = apply(&global_config, &devices, &new_config, &new_devices, &changes, applied).await;
+                if updates.send(update).await.is_err() || switched.recv().await.is_err() {
+                    return;
+                }
+                retire(&changes, &new_devices).await;
+                global_config = new_config;
+                devices = new_devices;
+            }
+            Err(e) => warn!("config.yml not applied, keeping the running configuration: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNNING: &str = "
+setup:
+  port_rx: 9001
+devices:
+  - ip: 10.0.48.1
+    proximity_parameter: head
+  - ip: 10.0.48.2
+    proximity_parameter: ear
+  - ip: 10.0.48.3
+    proximity_parameter: tail
+groups:
+  - name: back
+    proximity_parameter: back
+    members:
+      - ip: 10.0.48.10
+      - ip: 10.0.48.11
+";
+
+    const NEW: &str = "
+setup:
+  port_rx: 9001
+devices:
+  - ip: 10.0.48.1
+    proximity_parameter: head
+  - ip: 10.0.48.2
+    proximity_parameter: ear
+    max_speed: 50
+  - ip: 10.0.48.4
+    proximity_parameter: paw
+groups:
+  - name: back
+    proximity_parameter: back
+    members:
+      - ip: 10.0.48.10
+";
+
+    fn addresses(devices: &[&DeviceConfig]) -> Vec<String> {
+        devices.iter().map(|device| device.device_uri.to_string()).collect()
+    }
+
+    #[test]
+    fn devices_are_matched_by_address() {
+        let running = config::test_devices(RUNNING);
+        let devices = config::test_devices(NEW);
+        let changes = Changes::between(&running, &devices);
+        assert_eq!(addresses(&changes.removed), vec!["10.0.48.3"]);
+        assert_eq!(addresses(&changes.added), vec!["10.0.48.4"]);
+        let retuned = changes.retuned.iter().map(|(_, device)| *device).collect::<Vec<_>>();
+        assert_eq!(addresses(&retuned), vec!["10.0.48.2", "group:back"]);
+        let unchanged = changes.unchanged.iter().map(|(_, device)| *device).collect::<Vec<_>>();
+        assert_eq!(addresses(&unchanged), vec!["10.0.48.1"]);
+    }
+
+    #[test]
+    fn members_dropped_from_a_group_are_found() {
+        let running = config::test_devices(RUNNING);
+        let devices = config::test_devices(NEW);
+        let changes = Changes::between(&running, &devices);
+        let (old, group) = changes.retuned.iter().find(|(_, device)| device.device_uri.as_str() == "group:back").unwrap();
+        let dropped = dropped_members(old, group);
+        assert_eq!(dropped.iter().map(|member| member.ip.as_str()).collect::<Vec<_>>(), vec!["10.0.48.11"]);
+        // A device without members is its own target
+        assert!(dropped_members(&running[0], &devices[0]).is_empty());
+    }
+
+    #[test]
+    fn device_added_again_starts_fresh() {
+        let device = config::test_devices(
+            "setup:\n  port_rx: 9001\ndevices:\n  - ip: 10.0.48.20\n    proximity_parameter: [left, right]\n    curve: gamma\n"
+        ).remove(0);
+        task::block_on(async {
+            handle_proximity_parameter::DEVICE_LAST_VALUE.lock().await.insert(device.device_uri.to_string(), 0.6);
+        });
+        input_combiner::combine_input(&device, "left", 0.6);
+        response_curve::set_curve_index(&device, 0.0);
+        motor_output::set_muted(&device, true);
+        osc_timeout::record_signal(&device);
+
+        task::block_on(forget_device(&device));
+        assert!(task::block_on(handle_proximity_parameter::DEVICE_LAST_VALUE.lock()).get(device.device_uri.as_str()).is_none());
+        assert!(input_combiner::input_values(&device).is_empty());
+        assert_eq!(response_curve::current_curve(&device), device.curve);
+        assert!(!motor_output::is_muted(&device));
+        assert!(!osc_timeout::DEVICE_LAST_SIGNAL_TIME.lock().unwrap().contains_key(device.device_uri.as_str()));
+    }
+
+    #[test]
+    fn target_moved_into_a_group_keeps_its_key() {
+        let running = config::test_devices(
+            "setup:\n  port_rx: 9001\ndevices:\n  - ip: 10.0.48.30\n    proximity_parameter: head\n    hmac_key: 0123456789abcdef\n"
+        );
+        let devices = config::test_devices(
+            "setup:\n  port_rx: 9001\ndevices: []\ngroups:\n  - name: head\n    proximity_parameter: head\n    members:\n      - ip: 10.0.48.30\n        hmac_key: 0123456789abcdef\n"
+        );
+        register_keys(&devices[0]);
+        release_targets(&running[0].targets(), &devices);
+        assert!(osc_auth::send_lock("10.0.48.30").is_some());
+        release_targets(&running[0].targets(), &[]);
+        assert!(osc_auth::send_lock("10.0.48.30").is_none());
+    }
+
+    #[test]
+    fn unchanged_devices_keep_their_running_copy() {
+        let configured = config::test_devices(RUNNING);
+        let devices = config::test_devices(NEW);
+        let retuned = Changes::between(&configured, &devices).retuned.iter().map(|(_, device)| device.device_uri.to_string()).collect();
+        // The main loop's copies, with a max speed set from the avatar
+        let mut running = configured.clone();
+        running[0].max_speed = 0.9;
+        running[1].max_speed = 0.9;
+        let (sender, switched) = channel::bounded(1);
+        let applied = ConfigUpdate { devices: devices.clone(), retuned, applied: sender }.apply(running);
+        assert!(switched.try_recv().is_ok());
+
+        let addresses = applied.iter().map(|device| device.device_uri.as_str()).collect::<Vec<_>>();
+        assert_eq!(addresses, vec!["10.0.48.1", "10.0.48.2", "10.0.48.4", "group:back"]);
+        assert_eq!(applied[0].max_speed, 0.9);
+        assert_eq!(applied[1], devices[1]);
+        assert_eq!(applied[3].group_members.len(), 1);
+    }
+}
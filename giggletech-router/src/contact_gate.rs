@@ -0,0 +1,210 @@
+/*
+    contact_gate.rs - Start/Stop Decisions for Proximity Contacts
+
+    Contact values hovering near zero used to flap between start and stop, and every flap started a
+    `terminator` worker and a burst of stop signals. This module keeps an on/off state per device and
+    decides when a contact starts and ends, so the stop logic no longer depends on an exact `0.0`.
+
+    **Parameters:**
+
+    - `dead_zone`: Proximity at or below this value counts as no contact (default 0).
+    - `hysteresis`: A contact only starts once proximity rises above `dead_zone + hysteresis`, and
+      ends when it falls back to `dead_zone` (default 0).
+    - `min_on_time`: Shortest time (ms) a contact is held before it may stop (default 0).
+    - `min_off_time`: Shortest time (ms) the device stays stopped before a new contact may start (default 0).
+
+    **Key Features:**
+
+    1. **Gate State (`update`)**:
+       - Returns a `GateDecision` for each filtered proximity sample: `Start`, `Active`, `Stop`, `Idle`
+         (still stopped, nothing to send) or `Deferred` when a dwell time postpones a change.
+
+    2. **Deferred Changes (`recheck`)**:
+       - A deferred start or stop is re-evaluated with the latest sample once the dwell time has
+         passed, so a contact still ends when the last packet arrived during `min_on_time`.
+
+    3. **Timeouts (`force_off`)**:
+       - When a device times out it is marked as stopped, so the next contact starts fresh.
+
+    **Usage**:
+    - `handle_proximity_parameter` calls `update` for every sample and acts on the decision.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+
+use crate::config::DeviceConfig;
+
+lazy_static! {
+    static ref DEVICE_GATE: Arc<Mutex<HashMap<String, GateState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GateParams {
+    pub dead_zone: f32,
+    pub hysteresis: f32,
+    pub min_on_time: Duration,
+    pub min_off_time: Duration
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GateDecision {
+    Start,
+    Active,
+    Stop,
+    Idle,
+    // Check again after the duration, with the given generation
+    Deferred(Duration, u64)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct GateState {
+    on: bool,
+    since: Option<Instant>,
+    last_value: f32,
+    generation: u64
+}
+
+impl Default for GateState {
+    fn default() -> Self {
+        GateState { on: false, since: None, last_value: 0.0, generation: 0 }
+    }
+}
+
+impl GateState {
+    fn decide(&mut self, value: f32, now: Instant, params: &GateParams) -> GateDecision {
+        let elapsed = self.since.map(|since| now.saturating_duration_since(since));
+        if self.on {
+            if value > params.dead_zone {
+                return GateDecision::Active;
+            }
+            match elapsed {
+                Some(elapsed) if elapsed < params.min_on_time => GateDecision::Deferred(params.min_on_time - elapsed, self.generation),
+                _ => {
+                    self.on = false;
+                    self.since = Some(now);
+                    GateDecision::Stop
+                }
+            }
+        } else {
+            if value <= params.dead_zone + params.hysteresis {
+                return GateDecision::Idle;
+            }
+            match elapsed {
+                Some(elapsed) if elapsed < params.min_off_time => GateDecision::Deferred(params.min_off_time - elapsed, self.generation),
+                _ => {
+                    self.on = true;
+                    self.since = Some(now);
+                    GateDecision::Start
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn update(device: &DeviceConfig, value: f32, now: Instant) -> GateDecision {
+    match DEVICE_GATE.lock() {
+        Ok(mut device_gate) => {
+            let state = device_gate.entry(device.device_uri.to_string()).or_default();
+            state.last_value = value;
+            state.generation += 1;
+            state.decide(value, now, &device.gate)
+        }
+        Err(_) => if value > 0.0 { GateDecision::Active } else { GateDecision::Stop },
+    }
+}
+
+// Re-evaluate a deferred change, unless newer samples have arrived since. Returns the decision and the sample.
+pub(crate) fn recheck(device: &DeviceConfig, generation: u64, now: Instant) -> Option<(GateDecision, f32)> {
+    let mut device_gate = DEVICE_GATE.lock().ok()?;
+    let state = device_gate.get_mut(device.device_uri.as_str())?;
+    if state.generation != generation {
+        return None;
+    }
+    Some((state.decide(state.last_value, now, &device.gate), state.last_value))
+}
+
+// Returns whether a contact was still on
+pub(crate) fn force_off(device: &DeviceConfig) -> bool {
+    if let Ok(mut device_gate) = DEVICE_GATE.lock() {
+        if let Some(state) = device_gate.get_mut(device.device_uri.as_str()) {
+            if state.on {
+                state.on = false;
+                state.since = Some(Instant::now());
+                state.generation += 1;
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// The device was removed from the configuration
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_gate) = DEVICE_GATE.lock() {
+        device_gate.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> GateParams {
+        GateParams {
+            dead_zone: 0.05,
+            hysteresis: 0.05,
+            min_on_time: Duration::from_millis(200),
+            min_off_time: Duration::from_millis(100)
+        }
+    }
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn exact_zero_defaults_behave_like_before() {
+        let p = GateParams { dead_zone: 0.0, hysteresis: 0.0, min_on_time: Duration::ZERO, min_off_time: Duration::ZERO };
+        let start = Instant::now();
+        let mut state = GateState::default();
+        assert_eq!(state.decide(0.3, start, &p), GateDecision::Start);
+        assert_eq!(state.decide(0.2, start, &p), GateDecision::Active);
+        assert_eq!(state.decide(0.0, start, &p), GateDecision::Stop);
+        // Repeated zeros no longer trigger repeated stops
+        assert_eq!(state.decide(0.0, start, &p), GateDecision::Idle);
+    }
+
+    #[test]
+    fn hysteresis_band_needs_a_clear_start() {
+        let start = Instant::now();
+        let mut state = GateState::default();
+        assert_eq!(state.decide(0.08, start, &params()), GateDecision::Idle);
+        assert_eq!(state.decide(0.12, start, &params()), GateDecision::Start);
+        // Inside the band an active contact keeps running
+        assert_eq!(state.decide(0.08, at(start, 300), &params()), GateDecision::Active);
+        assert_eq!(state.decide(0.04, at(start, 300), &params()), GateDecision::Stop);
+    }
+
+    #[test]
+    fn min_on_time_defers_stop() {
+        let start = Instant::now();
+        let mut state = GateState::default();
+        state.decide(0.5, start, &params());
+        assert_eq!(state.decide(0.0, at(start, 50), &params()), GateDecision::Deferred(Duration::from_millis(150), 0));
+        assert_eq!(state.decide(0.0, at(start, 200), &params()), GateDecision::Stop);
+    }
+
+    #[test]
+    fn min_off_time_defers_start() {
+        let start = Instant::now();
+        let mut state = GateState::default();
+        state.decide(0.5, start, &params());
+        state.decide(0.0, at(start, 300), &params());
+        assert_eq!(state.decide(0.5, at(start, 350), &params()), GateDecision::Deferred(Duration::from_millis(50), 0));
+        assert_eq!(state.decide(0.5, at(start, 400), &params()), GateDecision::Start);
+    }
+}
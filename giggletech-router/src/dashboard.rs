@@ -0,0 +1,278 @@
+/*
+    dashboard.rs - Live Terminal Dashboard
+
+    Replaces the console stream of per-sample lines with a full-screen view of every device. The
+    dashboard is optional and started with the `monitor` command.
+
+    **Columns:**
+
+    - Input proximity and filtered velocity of the device.
+    - Output level bar of the motor value last sent, in percent of the full motor range.
+    - Online status (whether the last send succeeded) and the send success rate, from the
+      connection statistics in `giggletech_osc`. Groups combine their members.
+    - Current max-speed limit, including changes from the avatar's max speed parameter.
+
+    **Keys:**
+
+    - `Up` / `Down`: Select a device.
+    - `m`: Mute or unmute the selected device.
+    - `s`: Stop all devices, press again to resume.
+    - `q` or `Ctrl+C`: Stop all devices, save the session history and quit.
+
+    **Key Features:**
+
+    1. **Status Recording (`record_output`, `set_max_speed`)**:
+       - `motor_output` records every value sent and the main loop records max speed changes.
+
+    2. **Rendering**:
+       - The screen is redrawn every `REFRESH` on its own thread, so the router is never held up by
+         the terminal. Per-sample console lines and the periodic connection statistics are not
+         printed while the dashboard is active.
+
+    3. **Terminal Restore (`TerminalGuard`, `end_on_panic`)**:
+       - An error or a panic ends the dashboard, leaves raw mode and the alternate screen and turns
+         the console output back on, so the message can be read.
+*/
+
+use async_std::task;
+use crossterm::{cursor, event, execute, queue, style, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use std::collections::HashMap;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::error;
+
+use crate::config::DeviceConfig;
+use crate::config_reload;
+use crate::data_processing;
+use crate::giggletech_osc;
+use crate::handle_proximity_parameter::DEVICE_LAST_VALUE;
+use crate::motion_model;
+use crate::motor_output;
+use crate::session_stats;
+
+// Redraw interval
+const REFRESH: Duration = Duration::from_millis(100);
+// Width of the output level bar
+const BAR_WIDTH: usize = 20;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref DEVICE_STATUS: Mutex<HashMap<String, DeviceStatus>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct DeviceStatus {
+    output_tx: f32,
+    max_speed: Option<f32>
+}
+
+struct Row {
+    label: String,
+    device_uri: String,
+    proximity: f32,
+    velocity: f32,
+    output: f32,
+    online: Option<bool>,
+    success_rate: Option<f32>,
+    max_speed: f32,
+    muted: bool
+}
+
+pub(crate) fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+pub(crate) fn record_output(device: &DeviceConfig, headpat_tx: f32) {
+    if let Ok(mut device_status) = DEVICE_STATUS.lock() {
+        device_status.entry(device.device_uri.to_string()).or_default().output_tx = headpat_tx;
+    }
+}
+
+pub(crate) fn set_max_speed(device: &DeviceConfig) {
+    if let Ok(mut device_status) = DEVICE_STATUS.lock() {
+        device_status.entry(device.device_uri.to_string()).or_default().max_speed = Some(device.max_speed);
+    }
+}
+
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_status) = DEVICE_STATUS.lock() {
+        device_status.remove(device.device_uri.as_str());
+    }
+}
+
+fn level_bar(level: f32) -> String {
+    let filled = ((level.clamp(0.0, 1.0) * BAR_WIDTH as f32).round() as usize).min(BAR_WIDTH);
+    format!("{}{}", "#".repeat(filled), ".".repeat(BAR_WIDTH - filled))
+}
+
+async fn collect_rows(devices: &[DeviceConfig]) -> Vec<Row> {
+    let stats = giggletech_osc::get_connection_stats().await;
+    let online = giggletech_osc::get_online_status().await;
+    let last_values = DEVICE_LAST_VALUE.lock().await.clone();
+    let device_status = DEVICE_STATUS.lock().map(|device_status| device_status.clone()).unwrap_or_default();
+
+    devices.iter().map(|device| {
+        let status = device_status.get(device.device_uri.as_str()).copied().unwrap_or_default();
+        let targets = device.targets();
+        let (total, success) = targets.iter()
+            .filter_map(|target| stats.get(target.ip.as_str()))
+            .fold((0, 0), |(total, success), (count, ok, _)| (total + count, success + ok));
+        let target_online = targets.iter().map(|target| online.get(target.ip.as_str()).copied()).collect::<Option<Vec<_>>>();
+        Row {
+            label: device.parameter_label(),
+            device_uri: device.device_uri.to_string(),
+            proximity: last_values.get(device.device_uri.as_str()).copied().unwrap_or(0.0),
+            velocity: motion_model::velocity(device),
+            output: data_processing::tx_speed(status.output_tx, device),
+            online: target_online.map(|online| online.iter().all(|ok| *ok)),
+            success_rate: if total > 0 { Some(success as f32 / total as f32 * 100.0) } else { None },
+            max_speed: status.max_speed.unwrap_or(device.max_speed),
+            muted: motor_output::is_muted(device)
+        }
+    }).collect()
+}
+
+fn draw(rows: &[Row], selected: usize) -> std::io::Result<()> {
+    let mut out = stdout();
+    queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+    queue!(out, style::Print("GiggleTech OSC Router - Dashboard    [Up/Down] select  [m] mute  [s] stop all  [q] quit"))?;
+    if motor_output::is_stop_all() {
+        queue!(out, cursor::MoveTo(0, 1), style::Print("*** ALL DEVICES STOPPED - press s to resume ***"))?;
+    }
+    queue!(out, cursor::MoveTo(0, 3), style::Print(format!(
+        "  {:<18} {:<16} {:>5} {:>6}  {:<width$} {:>4}  {:<7} {:>6}  {:>4}",
+        "Input", "Device", "Prox", "Vel", "Output", "", "Status", "Sent", "Max", width = BAR_WIDTH
+    )))?;
+
+    for (i, row) in rows.iter().enumerate() {
+        let online = match row.online {
+            Some(true) => "online",
+            Some(false) => "OFFLINE",
+            None => "-",
+        };
+        let success_rate = row.success_rate.map(|rate| format!("{:.0}%", rate)).unwrap_or_else(|| "-".to_string());
+        let line = format!(
+            "{} {:<18} {:<16} {:>5.2} {:>6.2}  {} {:>3.0}%  {:<7} {:>6}  {:>3.0}% {}",
+            if i == selected { ">" } else { " " },
+            row.label, row.device_uri, row.proximity, row.velocity,
+            level_bar(row.output), row.output * 100.0,
+            online, success_rate, row.max_speed * 100.0,
+            if row.muted { "MUTED" } else { "" }
+        );
+        queue!(out, cursor::MoveTo(0, 4 + i as u16), style::Print(line))?;
+    }
+    out.flush()
+}
+
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+}
+
+fn quit(devices: &[DeviceConfig]) -> ! {
+    motor_output::set_stop_all(true);
+    task::block_on(async {
+        for device in devices.iter() {
+            let _ = motor_output::send_stop(device).await;
+        }
+        session_stats::save(devices).await;
+    });
+    restore_terminal();
+    std::process::exit(0);
+}
+
+fn toggle_mute(device: &DeviceConfig) {
+    let muted = !motor_output::is_muted(device);
+    motor_output::set_muted(device, muted);
+    if muted {
+        let device = device.clone();
+        task::spawn(async move {
+            let _ = motor_output::send_stop(&device).await;
+        });
+    }
+}
+
+fn toggle_stop_all(devices: &[DeviceConfig]) {
+    let stopped = !motor_output::is_stop_all();
+    motor_output::set_stop_all(stopped);
+    if stopped {
+        for device in devices.iter() {
+            let device = device.clone();
+            task::spawn(async move {
+                let _ = motor_output::send_stop(&device).await;
+            });
+        }
+    }
+}
+
+// Restores the terminal and the console output when the dashboard thread ends, also by a panic
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+        restore_terminal();
+    }
+}
+
+// A panic on another thread ends the dashboard, so its message is shown on a normal terminal
+pub(crate) fn end_on_panic() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        restore_terminal();
+    }
+}
+
+fn run() -> std::io::Result<()> {
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+    let mut selected = 0;
+    while is_active() {
+        // Devices can change when config.yml is reloaded
+        let devices = config_reload::current_devices();
+        selected = selected.min(devices.len().saturating_sub(1));
+        let rows = task::block_on(collect_rows(&devices));
+        draw(&rows, selected)?;
+
+        if !event::poll(REFRESH)? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => quit(&devices),
+                KeyCode::Char('q') => quit(&devices),
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => selected = (selected + 1).min(devices.len().saturating_sub(1)),
+                KeyCode::Char('m') => {
+                    if let Some(device) = devices.get(selected) {
+                        toggle_mute(device);
+                    }
+                }
+                KeyCode::Char('s') => toggle_stop_all(&devices),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// Start the dashboard on its own thread
+pub(crate) fn start() {
+    for device in config_reload::current_devices().iter() {
+        set_max_speed(device);
+    }
+    ACTIVE.store(true, Ordering::SeqCst);
+    std::thread::spawn(move || {
+        if let Err(e) = run() {
+            error!("Dashboard error: {}", e);
+        }
+    });
+}
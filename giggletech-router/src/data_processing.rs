@@ -19,18 +19,35 @@
        - Helps visualize the intensity of the motor speed.
 
     3. **Pat Processor (`process_pat`)**:
-       - Processes the proximity signal and calculates the motor transmission (Tx) value. This value 
-         is scaled by the configured device speed scale and the constant motor scaling factor.
+       - Processes the proximity signal and calculates the motor transmission (Tx) value. The signal is
+         shaped by the device's response curve, then scaled by the configured device speed scale and the
+         constant motor scaling factor.
        - Ensures the motor starts with enough power if transitioning from an idle state.
        - Logs the proximity value and motor transmission for debugging.
 
-    4. **Advanced Pat Processor (`process_pat_advanced`)**:
-       - A more advanced version of the `process_pat` function, taking into account the velocity of the 
-         proximity signal change over time (`delta_t`) to calculate a velocity-based motor transmission.
+    4. **Motor Level (`motor_level`)**:
+       - Converts a motor Tx value back into a fraction of the device's current max speed, used by
+         outputs that mirror the motor (e.g. the LED). `level_tx` is the inverse, used by haptic patterns.
+       - `speed_tx` and `tx_speed` convert between a configured speed (0.0 - 1.0) and motor Tx.
+
+    5. **Advanced Pat Processor (`process_pat_advanced`)**:
+       - A more advanced version of the `process_pat` function, driven by the filtered velocity of the
+         proximity signal from `motion_model` rather than by proximity itself.
        - Used for finer control over motor behavior based on how fast the proximity signal changes 
          (e.g., if a headpat is being applied quickly or slowly).
        - Logs proximity, velocity, and motor transmission for debugging and visualization.
 
+    6. **Hybrid Pat Processor (`process_pat_hybrid`)**:
+       - Blends the curved proximity (baseline) with the velocity intensity (boost) using the device's
+         `proximity_weight` and `velocity_weight`, and logs both components.
+
+    7. **Script Pat Processor (`process_pat_script`)**:
+       - Uses the motor speed returned by the device's script (see `scripting`), with the same scaling.
+
+    **Precision**:
+    - Motor Tx values are `f32` on the 0-255 scale and are not rounded here. The device's
+      `output_format` encodes them when they are sent (see `giggletech_osc`).
+
     **Motor Speed Scaling**:
     - The constant `MOTOR_SPEED_SCALE` (0.66) is used to scale the motor speed transmission. Going higher 
       than this value may reduce the life of the motor, as it's designed for over-voltage control.
@@ -51,9 +68,11 @@
 */
 
 
-use std::time::Duration;
-
-use crate::config::DeviceConfig;
+use log::{debug, info};
+use crate::config::{ControlMode, DeviceConfig};
+use crate::motion_model;
+use crate::response_curve;
+use crate::scripting;
 
 
 pub fn proximity_graph(proximity_signal: f32) -> String {
@@ -71,36 +90,114 @@ pub fn print_speed_limit(headpat_max_rx: f32) {
         51..=75 => "!  ",
         _ => "   ",
     };
-    println!("Speed Limit: {}% {}", headpat_max_rx_print, max_meter);
+    info!("Speed Limit: {}% {}", headpat_max_rx_print, max_meter);
 }
 
 // Pat Processor
 const MOTOR_SPEED_SCALE: f32 = 0.66; // Overvolt   Here, OEM config 0.66 going higher than this value will reduce your vibrator motor life
 
-pub fn process_pat(proximity_signal: f32, device: &DeviceConfig, prev_signal: f32) -> i32 {
+// Motor Tx as a fraction (0.0 - 1.0) of the device's current max speed
+pub fn motor_level(headpat_tx: f32, device: &DeviceConfig) -> f32 {
+    let max_tx = device.max_speed * MOTOR_SPEED_SCALE * device.speed_scale * 255.0;
+    if max_tx <= 0.0 {
+        return 0.0;
+    }
+    (headpat_tx / max_tx).clamp(0.0, 1.0)
+}
+
+// Motor Tx for a configured speed (0.0 - 1.0), ignoring the current max speed
+pub fn speed_tx(speed: f32, device: &DeviceConfig) -> f32 {
+    speed * MOTOR_SPEED_SCALE * device.speed_scale * 255.0
+}
+
+// Configured speed (0.0 - 1.0) giving a motor Tx, the inverse of `speed_tx`
+pub fn tx_speed(headpat_tx: f32, device: &DeviceConfig) -> f32 {
+    let full_tx = MOTOR_SPEED_SCALE * device.speed_scale * 255.0;
+    if full_tx <= 0.0 {
+        return 0.0;
+    }
+    headpat_tx / full_tx
+}
+
+// Motor Tx for a fraction (0.0 - 1.0) of the device's current max speed, the inverse of `motor_level`
+pub fn level_tx(level: f32, device: &DeviceConfig) -> f32 {
+    level.clamp(0.0, 1.0) * device.max_speed * MOTOR_SPEED_SCALE * device.speed_scale * 255.0
+}
+
+pub fn process_pat(proximity_signal: f32, device: &DeviceConfig, prev_signal: f32) -> f32 {
     let graph_str = proximity_graph(proximity_signal);
-    let headpat_tx = (((device.max_speed - device.min_speed) * proximity_signal + device.min_speed) * MOTOR_SPEED_SCALE * device.speed_scale * 255.0).round() as i32;
-    let headpat_tx = if prev_signal == 0.0 && proximity_signal > 0.0 && headpat_tx < device.start_tx {
-        device.start_tx
+    let shaped_signal = response_curve::current_curve(device).apply(proximity_signal);
+    let headpat_tx = ((device.max_speed - device.min_speed) * shaped_signal + device.min_speed) * MOTOR_SPEED_SCALE * device.speed_scale * 255.0;
+    let headpat_tx = if prev_signal == 0.0 && proximity_signal > 0.0 && headpat_tx < device.start_tx as f32 {
+        device.start_tx as f32
     } else {
         headpat_tx
     };
 
     let proximity_signal = format!("{:.2}", proximity_signal);
-    eprintln!("{} Prox: {:5} Motor Tx: {:3} |{:11}|", device.proximity_parameter.trim_start_matches("/avatar/parameters/") , proximity_signal, headpat_tx, graph_str);
+    debug!("{} Prox: {:5} Motor Tx: {:5.1} |{:11}|", device.parameter_label(), proximity_signal, headpat_tx, graph_str);
 
     headpat_tx
 }
 
-pub fn process_pat_advanced(proximity_signal: f32, prev_signal: f32, delta_t: Duration, device: &DeviceConfig) -> i32 {
-    let graph_str = proximity_graph(proximity_signal);
-    let mut headpat_tx: i32 = 0;
-    let mut vel: f32 = 0.0;
-    if proximity_signal > device.outer_proximity && proximity_signal < device.inner_proximity && prev_signal > 0.0 && proximity_signal > prev_signal {
-        vel = f32::max(0.0, (proximity_signal - prev_signal) / delta_t.as_secs_f32() * device.velocity_scalar);
-        headpat_tx = (((device.max_speed - device.min_speed) * vel * device.min_speed) * MOTOR_SPEED_SCALE * device.speed_scale * 255.0).round() as i32;
+// Motor Tx for a filtered velocity, see `motion_model`
+pub fn velocity_tx(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> f32 {
+    let params = motion_model::MotionParams::from_device(device);
+    let intensity = motion_model::velocity_intensity(velocity, proximity_signal, &params);
+    let speed = motion_model::motor_speed(intensity, device.min_speed, device.max_speed);
+    speed * MOTOR_SPEED_SCALE * device.speed_scale * 255.0
+}
+
+// Proximity baseline and velocity boost (0.0 - 1.0) of the hybrid mode, before weighting
+pub fn hybrid_components(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> (f32, f32) {
+    let params = motion_model::MotionParams::from_device(device);
+    let baseline = response_curve::current_curve(device).apply(proximity_signal);
+    let boost = motion_model::velocity_intensity(velocity, proximity_signal, &params);
+    (baseline, boost)
+}
+
+pub fn hybrid_tx(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> f32 {
+    let (baseline, boost) = hybrid_components(proximity_signal, velocity, device);
+    let intensity = (device.proximity_weight * baseline + device.velocity_weight * boost).clamp(0.0, 1.0);
+    let speed = motion_model::motor_speed(intensity, device.min_speed, device.max_speed);
+    speed * MOTOR_SPEED_SCALE * device.speed_scale * 255.0
+}
+
+// Motor Tx of the motion driven modes, used while the velocity decays
+pub fn motion_tx(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> f32 {
+    match device.control_mode {
+        ControlMode::Hybrid => hybrid_tx(proximity_signal, velocity, device),
+        _ => velocity_tx(proximity_signal, velocity, device),
     }
-    eprintln!("{} Prox: {:5} Vel: {:5} Motor Tx: {:3} |{:11}|", device.proximity_parameter.trim_start_matches("/avatar/parameters/") , format!("{:.2}", proximity_signal), format!("{:.2}", vel), headpat_tx, graph_str);
+}
+
+pub fn process_pat_hybrid(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> f32 {
+    let graph_str = proximity_graph(proximity_signal);
+    let (baseline, boost) = hybrid_components(proximity_signal, velocity, device);
+    let headpat_tx = hybrid_tx(proximity_signal, velocity, device);
+    debug!("{} Prox: {:5} Vel: {:5} Base: {:5} Boost: {:5} Motor Tx: {:5.1} |{:11}|", device.parameter_label(), format!("{:.2}", proximity_signal), format!("{:.2}", velocity), format!("{:.2}", baseline), format!("{:.2}", boost), headpat_tx, graph_str);
+
+    headpat_tx
+}
+
+// Motor Tx from the device's script, see `scripting`
+pub fn process_pat_script(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> f32 {
+    let graph_str = proximity_graph(proximity_signal);
+    let headpat_tx = script_tx(scripting::evaluate(device, proximity_signal, velocity), device);
+    debug!("{} Prox: {:5} Vel: {:5} Script Tx: {:5.1} |{:11}|", device.parameter_label(), format!("{:.2}", proximity_signal), format!("{:.2}", velocity), headpat_tx, graph_str);
 
-    return headpat_tx;
-}
\ No newline at end of file
+    headpat_tx
+}
+
+// Motor Tx for a speed returned by the script
+pub fn script_tx(speed: f32, device: &DeviceConfig) -> f32 {
+    speed * MOTOR_SPEED_SCALE * device.speed_scale * 255.0
+}
+
+pub fn process_pat_advanced(proximity_signal: f32, velocity: f32, device: &DeviceConfig) -> f32 {
+    let graph_str = proximity_graph(proximity_signal);
+    let headpat_tx = velocity_tx(proximity_signal, velocity, device);
+    debug!("{} Prox: {:5} Vel: {:5} Motor Tx: {:5.1} |{:11}|", device.parameter_label(), format!("{:.2}", proximity_signal), format!("{:.2}", velocity), headpat_tx, graph_str);
+
+    headpat_tx
+}
@@ -0,0 +1,389 @@
+/*
+    envelope.rs - Attack/Release Envelopes and Slew-Rate Limiting
+
+    Output used to jump to whatever `process_pat` returned and snap to 0 when a contact ended. This
+    module shapes the motor output of each device over time.
+
+    **Parameters:**
+
+    - `attack`: Time (ms) to fade in when a contact starts (default 0).
+    - `decay`: Time (ms) to fall from full output to the sustain level after the attack (default 0).
+    - `sustain`: Level (percent of the live output) held for the rest of the contact (default 100).
+    - `release`: Time (ms) to fade out after the contact ends (default 0). The fade continues after
+      the proximity parameter has dropped to zero.
+    - `max_slew_rate`: Largest change of the transmitted value, in percent of the full motor range
+      per second (default 0 = off). Applies to everything sent to the motor, including patterns.
+
+    **Key Features:**
+
+    1. **Envelope (`apply`, `start`, `release`)**:
+       - Live output is scaled by the envelope gain. `start` begins the attack from the current gain,
+         so a new contact during a release does not jump.
+
+    2. **Slew Limiting (`slew`)**:
+       - The transmitted value moves towards its target by at most the slew rate. A change after the
+         output has settled starts with one render step, however long the output was idle.
+
+    3. **Render Worker**:
+       - While an envelope phase or a slew is in progress, a worker keeps sending updated values,
+         since no new samples arrive once the contact has ended.
+       - A forced stop (`stop`) ends the envelope, the worker then sends a last `0` and exits.
+
+    **Usage**:
+    - `motor_output::send_motor` applies the envelope and `motor_output::emit` the slew limit.
+*/
+
+use async_std::task;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::DeviceConfig;
+use crate::motor_output;
+
+// Interval of the render worker
+const RENDER_INTERVAL: Duration = Duration::from_millis(20);
+
+lazy_static! {
+    static ref DEVICE_ENVELOPE: Arc<Mutex<HashMap<String, EnvelopeState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct EnvelopeParams {
+    pub attack: Duration,
+    pub decay: Duration,
+    pub sustain: f32,
+    pub release: Duration,
+    pub max_slew_rate: f32
+}
+
+impl EnvelopeParams {
+    fn has_envelope(&self) -> bool {
+        !self.attack.is_zero() || !self.decay.is_zero() || self.sustain < 1.0 || !self.release.is_zero()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release
+}
+
+#[derive(Clone, Debug)]
+struct EnvelopeState {
+    phase: Phase,
+    phase_start: Instant,
+    // Gain when the phase started
+    phase_from: f32,
+    live_tx: f32,
+    target_tx: f32,
+    output_tx: f32,
+    last_slew: Option<Instant>,
+    rendering: bool,
+    // Set by a forced stop, the render worker ends with a 0
+    stopped: bool
+}
+
+impl EnvelopeState {
+    fn new(now: Instant) -> EnvelopeState {
+        EnvelopeState {
+            phase: Phase::Idle,
+            phase_start: now,
+            phase_from: 1.0,
+            live_tx: 0.0,
+            target_tx: 0.0,
+            output_tx: 0.0,
+            last_slew: None,
+            rendering: false,
+            stopped: false
+        }
+    }
+
+    fn enter(&mut self, phase: Phase, start: Instant, from: f32) {
+        self.phase = phase;
+        self.phase_start = start;
+        self.phase_from = from;
+    }
+
+    // Move through finished phases and return the gain at `now`
+    fn gain(&mut self, now: Instant, params: &EnvelopeParams) -> f32 {
+        loop {
+            let elapsed = now.saturating_duration_since(self.phase_start);
+            let ramp = |length: Duration| if length.is_zero() { 1.0 } else { (elapsed.as_secs_f32() / length.as_secs_f32()).min(1.0) };
+            match self.phase {
+                Phase::Idle => return 1.0,
+                Phase::Attack if elapsed >= params.attack => self.enter(Phase::Decay, self.phase_start + params.attack, 1.0),
+                Phase::Attack => return self.phase_from + (1.0 - self.phase_from) * ramp(params.attack),
+                Phase::Decay if elapsed >= params.decay => self.enter(Phase::Sustain, self.phase_start + params.decay, params.sustain),
+                Phase::Decay => return 1.0 + (params.sustain - 1.0) * ramp(params.decay),
+                Phase::Sustain => return params.sustain,
+                Phase::Release if elapsed >= params.release => {
+                    self.enter(Phase::Idle, now, 1.0);
+                    self.live_tx = 0.0;
+                    return 0.0;
+                }
+                Phase::Release => return self.phase_from * (1.0 - ramp(params.release)),
+            }
+        }
+    }
+
+    fn in_transition(&self) -> bool {
+        matches!(self.phase, Phase::Attack | Phase::Decay | Phase::Release)
+    }
+
+    fn slew(&mut self, target_tx: f32, now: Instant, params: &EnvelopeParams) -> f32 {
+        let settled = self.output_tx == self.target_tx;
+        self.target_tx = target_tx;
+        let mut delta_t = self.last_slew.map(|last| now.saturating_duration_since(last).as_secs_f32()).unwrap_or(f32::MAX);
+        // Time spent settled does not count, a new change starts with one render step
+        if settled {
+            delta_t = delta_t.min(RENDER_INTERVAL.as_secs_f32());
+        }
+        self.last_slew = Some(now);
+        let max_step = params.max_slew_rate * 255.0 * delta_t;
+        let difference = target_tx - self.output_tx;
+        if difference.abs() <= max_step {
+            self.output_tx = target_tx;
+        } else {
+            self.output_tx += difference.clamp(-max_step, max_step);
+        }
+        self.output_tx
+    }
+}
+
+fn with_state<T>(device: &DeviceConfig, action: impl FnOnce(&mut EnvelopeState) -> T) -> Option<T> {
+    let mut device_envelope = DEVICE_ENVELOPE.lock().ok()?;
+    let state = device_envelope
+        .entry(device.device_uri.to_string())
+        .or_insert_with(|| EnvelopeState::new(Instant::now()));
+    Some(action(state))
+}
+
+// A contact started
+pub(crate) fn start(device: &DeviceConfig) {
+    if !device.envelope.has_envelope() {
+        return;
+    }
+    let now = Instant::now();
+    with_state(device, |state| {
+        let from = if state.phase == Phase::Release { state.gain(now, &device.envelope) } else { 0.0 };
+        state.enter(Phase::Attack, now, from);
+    });
+    ensure_render(device);
+}
+
+// A contact ended, returns true while the release is fading out the output
+pub(crate) fn release(device: &DeviceConfig) -> bool {
+    if device.envelope.release.is_zero() {
+        with_state(device, |state| {
+            state.enter(Phase::Idle, Instant::now(), 1.0);
+            state.live_tx = 0.0;
+        });
+        return false;
+    }
+    let now = Instant::now();
+    let releasing = with_state(device, |state| {
+        if state.live_tx == 0.0 || state.phase == Phase::Idle {
+            return false;
+        }
+        let from = state.gain(now, &device.envelope);
+        state.enter(Phase::Release, now, from);
+        true
+    }).unwrap_or(false);
+    if releasing {
+        ensure_render(device);
+    }
+    releasing
+}
+
+pub(crate) fn is_releasing(device: &DeviceConfig) -> bool {
+    DEVICE_ENVELOPE.lock().ok()
+        .and_then(|device_envelope| device_envelope.get(device.device_uri.as_str()).map(|state| state.phase == Phase::Release))
+        .unwrap_or(false)
+}
+
+// Scale a live value by the envelope
+pub(crate) fn apply(device: &DeviceConfig, live_tx: f32) -> f32 {
+    if !device.envelope.has_envelope() {
+        return live_tx;
+    }
+    let now = Instant::now();
+    with_state(device, |state| {
+        state.live_tx = live_tx;
+        live_tx * state.gain(now, &device.envelope)
+    }).unwrap_or(live_tx)
+}
+
+// Limit the change of the transmitted value
+pub(crate) fn slew(device: &DeviceConfig, headpat_tx: f32) -> f32 {
+    if device.envelope.max_slew_rate <= 0.0 {
+        return headpat_tx;
+    }
+    let now = Instant::now();
+    let (output_tx, pending) = match with_state(device, |state| {
+        let output_tx = state.slew(headpat_tx, now, &device.envelope);
+        (output_tx, output_tx != headpat_tx)
+    }) {
+        Some(result) => result,
+        None => return headpat_tx,
+    };
+    if pending {
+        ensure_render(device);
+    }
+    output_tx
+}
+
+// Stop signals bypass the slew limit
+pub(crate) fn reset_output(device: &DeviceConfig) {
+    if let Ok(mut device_envelope) = DEVICE_ENVELOPE.lock() {
+        if let Some(state) = device_envelope.get_mut(device.device_uri.as_str()) {
+            state.target_tx = 0.0;
+            state.output_tx = 0.0;
+        }
+    }
+}
+
+// A forced stop ends the envelope and any slew
+pub(crate) fn stop(device: &DeviceConfig) {
+    if let Ok(mut device_envelope) = DEVICE_ENVELOPE.lock() {
+        if let Some(state) = device_envelope.get_mut(device.device_uri.as_str()) {
+            state.enter(Phase::Idle, Instant::now(), 1.0);
+            state.live_tx = 0.0;
+            state.target_tx = 0.0;
+            state.output_tx = 0.0;
+            state.stopped = state.rendering;
+        }
+    }
+}
+
+// The device was removed from the configuration
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_envelope) = DEVICE_ENVELOPE.lock() {
+        device_envelope.remove(device.device_uri.as_str());
+    }
+}
+
+enum Render {
+    Envelope(f32),
+    Slew(f32),
+    Stopped
+}
+
+fn ensure_render(device: &DeviceConfig) {
+    let already_rendering = with_state(device, |state| std::mem::replace(&mut state.rendering, true)).unwrap_or(true);
+    if already_rendering {
+        return;
+    }
+
+    let device = device.clone();
+    task::spawn(async move {
+        loop {
+            task::sleep(RENDER_INTERVAL).await;
+            let now = Instant::now();
+            let next = with_state(&device, |state| {
+                if std::mem::take(&mut state.stopped) {
+                    state.rendering = false;
+                    Some(Render::Stopped)
+                } else if state.in_transition() {
+                    let gain = state.gain(now, &device.envelope);
+                    Some(Render::Envelope(state.live_tx * gain))
+                } else if state.output_tx != state.target_tx {
+                    Some(Render::Slew(state.target_tx))
+                } else {
+                    state.rendering = false;
+                    None
+                }
+            }).flatten();
+
+            let result = match next {
+                // Envelope values go through pattern mixing like live output
+                Some(Render::Envelope(headpat_tx)) => motor_output::send_enveloped(&device, headpat_tx).await,
+                Some(Render::Slew(headpat_tx)) => motor_output::emit(&device, headpat_tx).await,
+                // Covers a value the worker still had in flight when the stop was sent
+                Some(Render::Stopped) => {
+                    if let Err(e) = motor_output::emit(&device, 0.0).await {
+                        warn!("Envelope: Failed to send to {}: {}", device.device_uri, e);
+                    }
+                    return;
+                }
+                None => return,
+            };
+            if let Err(e) = result {
+                warn!("Envelope: Failed to send to {}: {}", device.device_uri, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> EnvelopeParams {
+        EnvelopeParams {
+            attack: Duration::from_millis(100),
+            decay: Duration::from_millis(100),
+            sustain: 0.5,
+            release: Duration::from_millis(200),
+            max_slew_rate: 1.0
+        }
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn attack_decay_sustain() {
+        let params = params();
+        let start = Instant::now();
+        let mut state = EnvelopeState::new(start);
+        state.enter(Phase::Attack, start, 0.0);
+        assert_eq!(state.gain(ms(start, 50), &params), 0.5);
+        assert_eq!(state.gain(ms(start, 100), &params), 1.0);
+        assert_eq!(state.gain(ms(start, 150), &params), 0.75);
+        assert_eq!(state.gain(ms(start, 1000), &params), 0.5);
+        assert_eq!(state.phase, Phase::Sustain);
+        assert!(!state.in_transition());
+    }
+
+    #[test]
+    fn release_fades_to_idle() {
+        let params = params();
+        let start = Instant::now();
+        let mut state = EnvelopeState::new(start);
+        state.live_tx = 100.0;
+        state.enter(Phase::Release, start, 0.5);
+        assert_eq!(state.gain(ms(start, 100), &params), 0.25);
+        assert_eq!(state.gain(ms(start, 200), &params), 0.0);
+        assert_eq!(state.phase, Phase::Idle);
+        assert_eq!(state.live_tx, 0.0);
+    }
+
+    #[test]
+    fn slew_limits_the_rate() {
+        let params = params();
+        let start = Instant::now();
+        let mut state = EnvelopeState::new(start);
+        // 255 per second, so 20 ms allow a step of 5.1
+        assert!((state.slew(255.0, start, &params) - 5.1).abs() < 1e-3);
+        assert!((state.slew(255.0, ms(start, 100), &params) - 30.6).abs() < 1e-3);
+        assert_eq!(state.slew(255.0, ms(start, 2000), &params), 255.0);
+    }
+
+    #[test]
+    fn settled_output_does_not_jump_after_idle() {
+        let params = params();
+        let start = Instant::now();
+        let mut state = EnvelopeState::new(start);
+        state.slew(0.0, start, &params);
+        let output_tx = state.slew(255.0, ms(start, 60_000), &params);
+        assert!((output_tx - 5.1).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,379 @@
+/*
+    gestures.rs - Gesture Recognition on the Proximity Signal
+
+    Classifies each device's proximity over a short rolling window into gestures, plays a haptic
+    response for each one and publishes it as an event other parts of the router can react to.
+
+    **Gestures (`Gesture`)**:
+
+    - `tap`: A short contact (a boop), shorter than `TAP_MAX`.
+    - `hold`: A contact that stays at a steady proximity for `HOLD_TIME`. Reported while the hand is
+      still there.
+    - `stroke`: A longer contact that rises to a peak and falls again.
+    - `pats`: Taps or strokes repeated with gaps shorter than `PAT_GAP`, reported from the third one
+      on with the number of pats so far.
+
+    **Key Features:**
+
+    1. **Rolling Window (`observe`)**:
+       - Every sample of a contact is kept in a per-device window (at most `WINDOW` long). Contacts are
+         classified when `contact_gate` ends them, holds by a timer started with the contact.
+
+    2. **Haptic Responses (`response`)**:
+       - The device's `gestures` map picks a pattern (see `patterns`) for each gesture, mixed with the
+         live output. A looping pattern plays one cycle for a tap, stroke or pats, and loops for a hold
+         until the contact ends.
+
+    3. **Events (`subscribe`)**:
+       - Each gesture is sent to every subscriber as a `GestureEvent`.
+
+    **Usage**:
+    - Enable recognition for a device with a `gestures` map, e.g. `gestures: { tap: boop, hold: purr }`.
+      Map a gesture to `none` to publish its events without a response.
+*/
+
+use async_std::channel::{self, Receiver, Sender};
+use async_std::task;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+
+use crate::config::DeviceConfig;
+use crate::contact_gate::GateDecision;
+use crate::patterns::{self, Pattern, PatternMode};
+
+// Longest contact that counts as a tap
+const TAP_MAX: Duration = Duration::from_millis(250);
+// Steady contact time before a hold is reported
+const HOLD_TIME: Duration = Duration::from_millis(1000);
+// Largest proximity range of a steady contact
+const HOLD_RANGE: f32 = 0.15;
+// Rise and fall around the peak of a stroke
+const STROKE_DEPTH: f32 = 0.1;
+// Longest gap between repeated pats
+const PAT_GAP: Duration = Duration::from_millis(700);
+// Pats needed before they are reported
+const MIN_PATS: u32 = 3;
+// Longest history kept per device
+const WINDOW: Duration = Duration::from_secs(3);
+
+lazy_static! {
+    static ref DEVICE_GESTURE_STATE: Arc<Mutex<HashMap<String, GestureState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    static ref SUBSCRIBERS: Arc<Mutex<Vec<Sender<GestureEvent>>>> =
+        Arc::new(Mutex::new(Vec::new()));
+}
+
+// Pattern played in response to each recognised gesture, `None` for events only
+pub(crate) type GestureResponses = Arc<HashMap<Gesture, Option<Arc<Pattern>>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Gesture {
+    Tap,
+    Hold,
+    Stroke,
+    Pats
+}
+
+impl Gesture {
+    pub const ALL: [Gesture; 4] = [Gesture::Tap, Gesture::Hold, Gesture::Stroke, Gesture::Pats];
+
+    pub fn parse(name: &str) -> Option<Gesture> {
+        match name.to_lowercase().as_str() {
+            "tap" | "boop" => Some(Gesture::Tap),
+            "hold" => Some(Gesture::Hold),
+            "stroke" => Some(Gesture::Stroke),
+            "pats" => Some(Gesture::Pats),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gesture::Tap => "tap",
+            Gesture::Hold => "hold",
+            Gesture::Stroke => "stroke",
+            Gesture::Pats => "pats",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct GestureEvent {
+    pub device: Arc<String>,
+    pub gesture: Gesture,
+    // Number of pats in a row, 1 for other gestures
+    pub count: u32
+}
+
+#[derive(Clone, Debug, Default)]
+struct GestureState {
+    window: VecDeque<(Instant, f32)>,
+    contact_start: Option<Instant>,
+    contact: u64,
+    held: bool,
+    last_pat_end: Option<Instant>,
+    pats: u32
+}
+
+impl GestureState {
+    fn sample(&mut self, now: Instant, value: f32) {
+        while self.window.front().map(|(time, _)| now.saturating_duration_since(*time) > WINDOW).unwrap_or(false) {
+            self.window.pop_front();
+        }
+        self.window.push_back((now, value));
+    }
+
+    // A contact started, returns its id
+    fn start(&mut self, now: Instant, value: f32) -> u64 {
+        self.contact += 1;
+        self.contact_start = Some(now);
+        self.held = false;
+        self.sample(now, value);
+        self.contact
+    }
+
+    fn contact_samples(&self) -> impl Iterator<Item = f32> + '_ {
+        let start = self.contact_start;
+        self.window.iter().filter(move |(time, _)| Some(*time) >= start).map(|(_, value)| *value)
+    }
+
+    fn is_steady(&self) -> bool {
+        let (min, max) = self.contact_samples().fold((f32::MAX, f32::MIN), |(min, max), value| (min.min(value), max.max(value)));
+        max - min <= HOLD_RANGE
+    }
+
+    // Classify the contact that just ended
+    fn classify(&mut self, now: Instant) -> Vec<(Gesture, u32)> {
+        let start = match self.contact_start.take() {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+        let duration = now.saturating_duration_since(start);
+        let samples = self.window.iter().filter(|(time, _)| *time >= start).map(|(_, value)| *value).collect::<Vec<_>>();
+        if self.held {
+            self.pats = 0;
+            return Vec::new();
+        }
+
+        let gesture = if duration < TAP_MAX {
+            Some(Gesture::Tap)
+        } else {
+            let peak = samples.iter().cloned().fold(0.0, f32::max);
+            let first = samples.first().cloned().unwrap_or(0.0);
+            let last = samples.iter().rev().find(|value| **value > 0.0).cloned().unwrap_or(0.0);
+            if peak - first >= STROKE_DEPTH && peak - last >= STROKE_DEPTH {
+                Some(Gesture::Stroke)
+            } else {
+                None
+            }
+        };
+
+        let mut gestures = Vec::new();
+        if let Some(gesture) = gesture {
+            gestures.push((gesture, 1));
+            let repeated = self.last_pat_end.map(|end| start.saturating_duration_since(end) <= PAT_GAP).unwrap_or(false);
+            self.pats = if repeated { self.pats + 1 } else { 1 };
+            self.last_pat_end = Some(now);
+            if self.pats >= MIN_PATS {
+                gestures.push((Gesture::Pats, self.pats));
+            }
+        } else {
+            self.pats = 0;
+        }
+        gestures
+    }
+}
+
+// Receive every recognised gesture
+pub(crate) fn subscribe() -> Receiver<GestureEvent> {
+    let (sender, receiver) = channel::unbounded();
+    if let Ok(mut subscribers) = SUBSCRIBERS.lock() {
+        subscribers.push(sender);
+    }
+    receiver
+}
+
+// Pattern played for a gesture, only a hold keeps a looping pattern going
+fn response(gesture: Gesture, pattern: &Arc<Pattern>) -> Arc<Pattern> {
+    if gesture == Gesture::Hold || !pattern.looping {
+        return pattern.clone();
+    }
+    Arc::new(Pattern { looping: false, repeat: 1, ..pattern.as_ref().clone() })
+}
+
+fn emit(device: &DeviceConfig, gesture: Gesture, count: u32) {
+    // Repeated pats respond once per streak
+    let respond = gesture != Gesture::Pats || count == MIN_PATS;
+    if let Some(Some(pattern)) = device.gestures.get(&gesture).filter(|_| respond) {
+        patterns::play(device, response(gesture, pattern), PatternMode::Mix);
+    }
+
+    let event = GestureEvent { device: device.device_uri.clone(), gesture, count };
+    if let Ok(mut subscribers) = SUBSCRIBERS.lock() {
+        subscribers.retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+    }
+}
+
+fn start_hold_timer(device: &DeviceConfig, contact: u64) {
+    let device = device.clone();
+    task::spawn(async move {
+        task::sleep(HOLD_TIME).await;
+        let hold = match DEVICE_GESTURE_STATE.lock() {
+            Ok(mut device_gesture_state) => match device_gesture_state.get_mut(device.device_uri.as_str()) {
+                Some(state) if state.contact == contact && state.contact_start.is_some() && state.is_steady() => {
+                    state.held = true;
+                    true
+                }
+                _ => false,
+            },
+            Err(_) => false,
+        };
+        if hold {
+            emit(&device, Gesture::Hold, 1);
+        }
+    });
+}
+
+pub(crate) fn observe(device: &DeviceConfig, decision: GateDecision, value: f32) {
+    if device.gestures.is_empty() {
+        return;
+    }
+    let now = Instant::now();
+    let (gestures, hold_contact, held) = {
+        let mut device_gesture_state = match DEVICE_GESTURE_STATE.lock() {
+            Ok(device_gesture_state) => device_gesture_state,
+            Err(_) => return,
+        };
+        let state = device_gesture_state.entry(device.device_uri.to_string()).or_default();
+        match decision {
+            GateDecision::Start => (Vec::new(), Some(state.start(now, value)), false),
+            GateDecision::Active => {
+                state.sample(now, value);
+                (Vec::new(), None, false)
+            }
+            GateDecision::Stop => {
+                let held = state.held && state.contact_start.is_some();
+                (state.classify(now), None, held)
+            }
+            GateDecision::Idle | GateDecision::Deferred(..) => return,
+        }
+    };
+
+    if let Some(contact) = hold_contact {
+        start_hold_timer(device, contact);
+    }
+    // The hold response ends with the contact
+    if held {
+        if let Some(Some(pattern)) = device.gestures.get(&Gesture::Hold) {
+            patterns::stop(device, pattern);
+        }
+    }
+    for (gesture, count) in gestures {
+        emit(device, gesture, count);
+    }
+}
+
+// Forgets the contact and tap streak of a removed device
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_gesture_state) = DEVICE_GESTURE_STATE.lock() {
+        device_gesture_state.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    // Sends the pattern frames to a loopback address
+    fn device(ip: &str) -> DeviceConfig {
+        config::test_devices(&format!(
+            "setup:\n  port_rx: 9001\ndevices:\n  - ip: {}\n    proximity_parameter: pat\n    gestures:\n      tap: purr\n      hold: purr\n",
+            ip
+        )).remove(0)
+    }
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    // Feeds one contact of evenly spaced samples starting at `offset` ms
+    fn contact(state: &mut GestureState, start: Instant, offset: u64, values: &[f32], step: u64) -> Vec<(Gesture, u32)> {
+        state.start(at(start, offset), values[0]);
+        for (i, value) in values.iter().enumerate().skip(1) {
+            state.sample(at(start, offset + i as u64 * step), *value);
+        }
+        state.classify(at(start, offset + values.len() as u64 * step))
+    }
+
+    #[test]
+    fn short_contact_is_a_tap() {
+        let start = Instant::now();
+        let mut state = GestureState::default();
+        assert_eq!(contact(&mut state, start, 0, &[0.4, 0.6], 50), vec![(Gesture::Tap, 1)]);
+    }
+
+    #[test]
+    fn rise_and_fall_is_a_stroke() {
+        let start = Instant::now();
+        let mut state = GestureState::default();
+        let values = [0.2, 0.4, 0.6, 0.8, 0.6, 0.4, 0.2];
+        assert_eq!(contact(&mut state, start, 0, &values, 100), vec![(Gesture::Stroke, 1)]);
+        // A slow approach without falling again is not a stroke
+        let values = [0.2, 0.4, 0.6, 0.8, 0.8, 0.8];
+        assert!(contact(&mut state, start, 2000, &values, 100).is_empty());
+    }
+
+    #[test]
+    fn repeated_taps_are_pats() {
+        let start = Instant::now();
+        let mut state = GestureState::default();
+        assert_eq!(contact(&mut state, start, 0, &[0.5], 100), vec![(Gesture::Tap, 1)]);
+        assert_eq!(contact(&mut state, start, 400, &[0.5], 100), vec![(Gesture::Tap, 1)]);
+        assert_eq!(contact(&mut state, start, 800, &[0.5], 100), vec![(Gesture::Tap, 1), (Gesture::Pats, 3)]);
+        // A long pause starts a new streak
+        assert_eq!(contact(&mut state, start, 3000, &[0.5], 100), vec![(Gesture::Tap, 1)]);
+    }
+
+    #[test]
+    fn held_contact_is_not_classified_again() {
+        let start = Instant::now();
+        let mut state = GestureState::default();
+        state.start(start, 0.5);
+        state.sample(at(start, 500), 0.55);
+        assert!(state.is_steady());
+        state.held = true;
+        assert!(state.classify(at(start, 1500)).is_empty());
+    }
+
+    #[test]
+    fn looping_response_plays_one_cycle() {
+        let purr = Arc::new(patterns::builtin("purr").unwrap());
+        let tap = response(Gesture::Tap, &purr);
+        assert!(!tap.looping);
+        assert_eq!(tap.intensity_at(Duration::from_secs(1)), None);
+        assert!(response(Gesture::Hold, &purr).looping);
+    }
+
+    #[test]
+    fn looping_pattern_started_by_a_tap_ends() {
+        let device = device("127.0.0.41");
+        emit(&device, Gesture::Tap, 1);
+        assert!(patterns::is_playing(&device));
+        task::block_on(task::sleep(Duration::from_millis(500)));
+        assert!(!patterns::is_playing(&device));
+    }
+
+    #[test]
+    fn hold_response_ends_with_the_contact() {
+        let device = device("127.0.0.42");
+        observe(&device, GateDecision::Start, 0.5);
+        DEVICE_GESTURE_STATE.lock().unwrap().get_mut(device.device_uri.as_str()).unwrap().held = true;
+        emit(&device, Gesture::Hold, 1);
+        assert!(patterns::is_playing(&device));
+        observe(&device, GateDecision::Stop, 0.0);
+        assert!(!patterns::is_playing(&device));
+    }
+}
@@ -13,18 +13,25 @@
     3. **Error Recovery**: Graceful handling of network errors
     4. **Resource Management**: Automatic cleanup of stale connections
     5. **Statistics**: Connection monitoring and debugging capabilities
+    6. **Signing**: Messages to devices with an `hmac_key` are signed via `osc_auth`
+    7. **Output Encoding (`OutputFormat`)**: Motor values are carried at full precision on the
+       0-255 scale and only encoded here, as an 8-bit int (0-255), a 16-bit int (0-65535) or a
+       normalized float (0.0-1.0), depending on the device's `output_format`
 
     **Usage:**
     - Use `setup_rx_socket` for receiving OSC messages
     - Use `send_data` for sending OSC messages with automatic connection management
+    - Use `send_led` / `send_led_color` to drive the status LED on the device
     - Call `start_connection_manager()` to enable automatic cleanup
 */
 
-use async_osc::{ OscSocket, Result};
+use async_osc::{prelude::*, OscMessage, OscSocket, OscType, Result};
 use std::collections::HashMap;
 use std::sync::Arc;
 use async_std::sync::RwLock;
 use std::time::{Duration, Instant};
+use log::info;
+use crate::osc_auth;
 
 // Connection manager for efficient socket handling
 pub struct ConnectionManager {
@@ -37,6 +44,7 @@ struct ConnectionInfo {
     connection_count: u32,
     success_count: u32,
     error_count: u32,
+    last_success: bool,
 }
 
 impl ConnectionManager {
@@ -54,10 +62,12 @@ impl ConnectionManager {
             connection_count: 0,
             success_count: 0,
             error_count: 0,
+            last_success: false,
         });
         
         info.last_used = Instant::now();
         info.connection_count += 1;
+        info.last_success = success;
         
         if success {
             info.success_count += 1;
@@ -79,7 +89,7 @@ impl ConnectionManager {
         let after_count = connections.len();
         
         if before_count != after_count {
-            println!("Cleaned up {} stale connections", before_count - after_count);
+            info!("Cleaned up {} stale connections", before_count - after_count);
         }
     }
 
@@ -90,6 +100,14 @@ impl ConnectionManager {
             .map(|(ip, info)| (ip.clone(), (info.connection_count, info.success_count, info.error_count)))
             .collect()
     }
+
+    // Whether the last send to each device succeeded
+    pub async fn get_online(&self) -> HashMap<String, bool> {
+        let connections = self.connections.read().await;
+        connections.iter()
+            .map(|(ip, info)| (ip.clone(), info.last_success))
+            .collect()
+    }
 }
 
 // Global connection manager instance
@@ -100,7 +118,37 @@ lazy_static::lazy_static! {
 // OSC Address Setup
 const TX_OSC_MOTOR_ADDRESS: &str = "/avatar/parameters/motor"; // legacy support
 const TX_OSC_GIGGLESPARK: &str = "/motor"; // both gigglepuck and spark use this
-//const TX_OSC_LED_ADDRESS_2: &str = "/avatar/parameters/led";
+const TX_OSC_LED_ADDRESS: &str = "/led";
+const TX_OSC_LED_ADDRESS_2: &str = "/avatar/parameters/led"; // legacy support
+const TX_OSC_LED_COLOR_ADDRESS: &str = "/led/color";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OutputFormat {
+    Int8,
+    Int16,
+    Float
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Option<OutputFormat> {
+        match format.to_lowercase().as_str() {
+            "int8" => Some(OutputFormat::Int8),
+            "int16" => Some(OutputFormat::Int16),
+            "float" => Some(OutputFormat::Float),
+            _ => None,
+        }
+    }
+
+    // Encode a motor value on the 0-255 scale
+    pub fn encode(&self, headpat_tx: f32) -> OscType {
+        let level = (headpat_tx / 255.0).clamp(0.0, 1.0);
+        match self {
+            OutputFormat::Int8 => OscType::Int((level * 255.0).round() as i32),
+            OutputFormat::Int16 => OscType::Int((level * 65535.0).round() as i32),
+            OutputFormat::Float => OscType::Float(level),
+        }
+    }
+}
 
 pub(crate) fn create_socket_address(host: &str, port: &str) -> String {
     let address_parts = vec![host, port];
@@ -121,7 +169,7 @@ pub(crate) async fn setup_tx_socket(address: std::string::String) -> Result<OscS
 
 // Start connection manager cleanup task
 pub(crate) async fn start_connection_manager() {
-    println!("Starting connection manager with automatic cleanup...");
+    info!("Starting connection manager with automatic cleanup...");
     async_std::task::spawn(async {
         loop {
             async_std::task::sleep(Duration::from_secs(60)).await; // Cleanup every minute
@@ -130,8 +178,44 @@ pub(crate) async fn start_connection_manager() {
     });
 }
 
-// Send data with proper connection management and timeouts
-pub(crate) async fn send_data(device_ip: &str, value: i32) -> Result<()> {
+// Send motor data (0-255 scale) to a device in its output format
+pub(crate) async fn send_data(device_ip: &str, headpat_tx: f32, format: OutputFormat) -> Result<()> {
+    let value = format.encode(headpat_tx);
+    send_to_device(device_ip, vec![
+        OscMessage::new(TX_OSC_MOTOR_ADDRESS, vec![value.clone()]),
+        OscMessage::new(TX_OSC_GIGGLESPARK, vec![value]),
+    ]).await
+}
+
+// Send LED brightness (0-255) to a device
+pub(crate) async fn send_led(device_ip: &str, brightness: i32) -> Result<()> {
+    send_to_device(device_ip, vec![
+        OscMessage::new(TX_OSC_LED_ADDRESS, (brightness,)),
+        OscMessage::new(TX_OSC_LED_ADDRESS_2, (brightness,)),
+    ]).await
+}
+
+// Send LED colour (0-255 per channel) to a device
+pub(crate) async fn send_led_color(device_ip: &str, rgb: (i32, i32, i32)) -> Result<()> {
+    send_to_device(device_ip, vec![
+        OscMessage::new(TX_OSC_LED_COLOR_ADDRESS, rgb),
+    ]).await
+}
+
+// Send messages with proper connection management and timeouts
+async fn send_to_device(device_ip: &str, messages: Vec<OscMessage>) -> Result<()> {
+    // Signed messages go out one send at a time, in the order of their counters
+    let send_lock = osc_auth::send_lock(device_ip);
+    let _in_order = match &send_lock {
+        Some(send_lock) => Some(send_lock.lock().await),
+        None => None,
+    };
+
+    // Sign messages for devices with a shared key
+    let messages = messages.into_iter()
+        .map(|message| osc_auth::sign_message(device_ip, message))
+        .collect::<Result<Vec<_>>>()?;
+
     let socket_address = create_socket_address(device_ip, "8888");
     
     // Create socket with connection timeout
@@ -157,8 +241,9 @@ pub(crate) async fn send_data(device_ip: &str, value: i32) -> Result<()> {
     let send_result = async_std::future::timeout(
         Duration::from_secs(1), // 1 second send timeout
         async {
-            socket.send((TX_OSC_MOTOR_ADDRESS, (value,))).await?;
-            socket.send((TX_OSC_GIGGLESPARK, (value,))).await?;
+            for message in messages {
+                socket.send(message).await?;
+            }
             Ok::<(), async_osc::Error>(())
         }
     ).await;
@@ -190,16 +275,34 @@ pub(crate) async fn get_connection_stats() -> HashMap<String, (u32, u32, u32)> {
     CONNECTION_MANAGER.get_stats().await
 }
 
+// Whether the last send to each device succeeded
+pub(crate) async fn get_online_status() -> HashMap<String, bool> {
+    CONNECTION_MANAGER.get_online().await
+}
+
 // Print connection statistics
 pub(crate) async fn print_connection_stats() {
     let stats = get_connection_stats().await;
     if !stats.is_empty() {
-        println!("\n=== Connection Statistics ===");
+        info!("=== Connection Statistics ===");
         for (device_ip, (total, success, errors)) in stats {
             let success_rate = if total > 0 { (success as f32 / total as f32) * 100.0 } else { 0.0 };
-            println!("  {}: {} total, {} success, {} errors ({:.1}% success rate)", 
+            info!("  {}: {} total, {} success, {} errors ({:.1}% success rate)", 
                 device_ip, total, success, errors, success_rate);
         }
-        println!("=============================\n");
+        info!("=============================");
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_formats_encode_the_full_scale() {
+        assert_eq!(OutputFormat::Int8.encode(127.6), OscType::Int(128));
+        assert_eq!(OutputFormat::Int16.encode(255.0), OscType::Int(65535));
+        assert_eq!(OutputFormat::Int16.encode(1.0), OscType::Int(257));
+        assert_eq!(OutputFormat::Float.encode(127.5), OscType::Float(0.5));
+        assert_eq!(OutputFormat::Int8.encode(300.0), OscType::Int(255));
+    }
+}
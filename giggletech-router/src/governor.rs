@@ -0,0 +1,339 @@
+/*
+    governor.rs - Motor Thermal and Duty-Cycle Governor
+
+    `MOTOR_SPEED_SCALE` keeps single commands within the motor's rating, but nothing stopped a motor
+    from running hot for a long session. This governor is the last gain stage of the motor output,
+    after the mapping mode, patterns and the gain of group members, and limits what is actually sent
+    to each motor.
+
+    **Parameters:**
+
+    - `thermal_time_constant`: How fast the motor heats up and cools down (seconds, default 60).
+    - `max_heat`: Heat limit in percent of the heat reached by running at full power (default 100 = off).
+      Output is derated from 80% of the limit and stops at the limit.
+    - `max_runtime`: Longest continuous run in seconds (default 0 = off). Output is derated over the
+      last 20% and the motor then rests for `rest_time` seconds (default 10).
+    - `duty_cycle`: Share of time the motor may run, in percent over `duty_window` seconds (defaults
+      100 = off, 60). Output is derated as the budget runs out.
+
+    **Key Features:**
+
+    1. **Heating Model (`GovernorState::step`)**:
+       - Every motor has its own state, a group member is governed by the group's settings.
+       - Heat follows the motor power (output squared) with the thermal time constant, integrated
+         over the output history since the last command.
+
+    2. **Derating (`govern`)**:
+       - The strictest limit scales the requested output. Limits fade in, so the motor slows down
+         before it is stopped.
+
+    3. **Event Logging**:
+       - Every time a limit starts, changes or clears, it is logged with the current heat or duty cycle.
+
+    4. **Governor Worker (`governor_worker`)**:
+       - The motor holds its last command, so a worker re-applies the limits while a device is running
+         even when no new samples arrive.
+
+    **Usage**:
+    - `motor_output::send_targets` passes the value of every motor through `govern`.
+*/
+
+use async_std::task;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use crate::config::{DeviceConfig, GroupMember};
+use crate::motor_output;
+
+// Share of a limit over which the output is derated
+const DERATE_WINDOW: f32 = 0.2;
+// Time the motor must be off before a continuous run ends
+const RUN_RESET: Duration = Duration::from_secs(1);
+// Interval of the governor worker
+const GOVERNOR_INTERVAL: Duration = Duration::from_millis(250);
+
+lazy_static! {
+    static ref DEVICE_GOVERNOR: Arc<Mutex<HashMap<String, GovernorState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GovernorParams {
+    pub thermal_time_constant: f32,
+    pub max_heat: f32,
+    pub max_runtime: Duration,
+    pub rest_time: Duration,
+    pub duty_cycle: f32,
+    pub duty_window: f32
+}
+
+impl GovernorParams {
+    pub fn enabled(&self) -> bool {
+        self.max_heat < 1.0 || !self.max_runtime.is_zero() || self.duty_cycle < 1.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Limit {
+    Heat,
+    Runtime,
+    Resting,
+    DutyCycle
+}
+
+#[derive(Clone, Debug, Default)]
+struct GovernorState {
+    heat: f32,
+    duty: f32,
+    last_time: Option<Instant>,
+    // Output currently on the motor and the output that was asked for
+    output_tx: f32,
+    requested_tx: f32,
+    run_start: Option<Instant>,
+    off_since: Option<Instant>,
+    resting_until: Option<Instant>,
+    limit: Option<(Limit, i32)>
+}
+
+fn approach(current: f32, target: f32, delta_t: f32, time_constant: f32) -> f32 {
+    if time_constant <= 0.0 {
+        return target;
+    }
+    current + (target - current) * (1.0 - (-delta_t / time_constant).exp())
+}
+
+fn derate(remaining: f32, window: f32) -> f32 {
+    if window <= 0.0 {
+        return if remaining > 0.0 { 1.0 } else { 0.0 };
+    }
+    (remaining / window).clamp(0.0, 1.0)
+}
+
+impl GovernorState {
+    // Integrate the output held on the motor since the last command
+    fn step(&mut self, now: Instant, params: &GovernorParams) {
+        let last_time = match self.last_time {
+            Some(last_time) => last_time,
+            None => {
+                self.last_time = Some(now);
+                return;
+            }
+        };
+        let delta_t = now.saturating_duration_since(last_time).as_secs_f32();
+        let level = self.output_tx / 255.0;
+        let running = self.output_tx > 0.0;
+        self.heat = approach(self.heat, level * level, delta_t, params.thermal_time_constant);
+        self.duty = approach(self.duty, if running { 1.0 } else { 0.0 }, delta_t, params.duty_window);
+
+        if running {
+            self.run_start.get_or_insert(last_time);
+            self.off_since = None;
+        } else {
+            let off_since = *self.off_since.get_or_insert(last_time);
+            if now.saturating_duration_since(off_since) >= RUN_RESET {
+                self.run_start = None;
+            }
+        }
+        self.last_time = Some(now);
+    }
+
+    // Scale for the requested output and the limit causing it
+    fn factor(&mut self, now: Instant, params: &GovernorParams) -> (f32, Option<Limit>) {
+        if let Some(resting_until) = self.resting_until {
+            if now < resting_until {
+                return (0.0, Some(Limit::Resting));
+            }
+            self.resting_until = None;
+        }
+
+        let mut limits = Vec::new();
+        if !params.max_runtime.is_zero() {
+            if let Some(run_start) = self.run_start {
+                let runtime = now.saturating_duration_since(run_start);
+                if runtime >= params.max_runtime {
+                    self.resting_until = Some(now + params.rest_time);
+                    self.run_start = None;
+                    return (0.0, Some(Limit::Resting));
+                }
+                let max_runtime = params.max_runtime.as_secs_f32();
+                limits.push((derate(max_runtime - runtime.as_secs_f32(), max_runtime * DERATE_WINDOW), Limit::Runtime));
+            }
+        }
+        if params.max_heat < 1.0 {
+            limits.push((derate(params.max_heat - self.heat, params.max_heat * DERATE_WINDOW), Limit::Heat));
+        }
+        if params.duty_cycle < 1.0 {
+            limits.push((derate(params.duty_cycle - self.duty, params.duty_cycle * DERATE_WINDOW), Limit::DutyCycle));
+        }
+
+        match limits.into_iter().min_by(|a, b| a.0.total_cmp(&b.0)) {
+            Some((factor, limit)) if factor < 1.0 => (factor, Some(limit)),
+            _ => (1.0, None),
+        }
+    }
+
+    fn govern(&mut self, requested_tx: f32, now: Instant, params: &GovernorParams) -> f32 {
+        self.step(now, params);
+        self.requested_tx = requested_tx;
+        let (factor, limit) = self.factor(now, params);
+        let output_tx = requested_tx * factor;
+        self.output_tx = output_tx;
+
+        // Only a limit on a running motor is an event
+        self.limit = match limit {
+            Some(limit) if requested_tx > 0.0 => Some((limit, (factor * 100.0).round() as i32)),
+            _ => None,
+        };
+        output_tx
+    }
+
+    fn describe(&self) -> String {
+        match self.limit {
+            Some((Limit::Heat, percent)) => format!("motor heat at {:.0}%, output limited to {}%", self.heat * 100.0, percent),
+            Some((Limit::Runtime, percent)) => format!("maximum runtime approaching, output limited to {}%", percent),
+            Some((Limit::Resting, _)) => "maximum runtime reached, resting motor".to_string(),
+            Some((Limit::DutyCycle, percent)) => format!("duty cycle at {:.0}%, output limited to {}%", self.duty * 100.0, percent),
+            None => "limit cleared".to_string(),
+        }
+    }
+}
+
+fn log_event(target: &GroupMember, message: &str) {
+    info!("Governor {}: {}", target.ip, message);
+}
+
+// Limit the value for one motor driven by the device
+pub(crate) fn govern(device: &DeviceConfig, target: &GroupMember, headpat_tx: f32) -> f32 {
+    if !device.governor.enabled() {
+        return headpat_tx;
+    }
+    let mut device_governor = match DEVICE_GOVERNOR.lock() {
+        Ok(device_governor) => device_governor,
+        Err(_) => return headpat_tx,
+    };
+    let state = device_governor.entry(target.ip.to_string()).or_default();
+    let previous = state.limit.map(|(limit, _)| limit);
+    let output_tx = state.govern(headpat_tx, Instant::now(), &device.governor);
+    if state.limit.map(|(limit, _)| limit) != previous {
+        log_event(target, &state.describe());
+    }
+    output_tx
+}
+
+// Forget the heat and runtime of a motor that is no longer driven
+pub(crate) fn remove(target: &GroupMember) {
+    if let Ok(mut device_governor) = DEVICE_GOVERNOR.lock() {
+        device_governor.remove(target.ip.as_str());
+    }
+}
+
+// Re-apply the limits to the output held on each motor
+pub(crate) async fn governor_worker(device: DeviceConfig) {
+    loop {
+        task::sleep(GOVERNOR_INTERVAL).await;
+        for target in device.targets() {
+            let update = match DEVICE_GOVERNOR.lock() {
+                Ok(mut device_governor) => match device_governor.get_mut(target.ip.as_str()) {
+                    Some(state) if state.requested_tx > 0.0 => {
+                        let previous_tx = state.output_tx;
+                        let previous_limit = state.limit.map(|(limit, _)| limit);
+                        let output_tx = state.govern(state.requested_tx, Instant::now(), &device.governor);
+                        if state.limit.map(|(limit, _)| limit) != previous_limit {
+                            log_event(&target, &state.describe());
+                        }
+                        if output_tx != previous_tx { Some(output_tx) } else { None }
+                    }
+                    _ => None,
+                },
+                Err(_) => return,
+            };
+
+            if let Some(output_tx) = update {
+                if let Err(e) = motor_output::send_target(&target, output_tx).await {
+                    warn!("Governor: Failed to send to {}: {}", target.ip, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> GovernorParams {
+        GovernorParams {
+            thermal_time_constant: 10.0,
+            max_heat: 1.0,
+            max_runtime: Duration::ZERO,
+            rest_time: Duration::from_secs(10),
+            duty_cycle: 1.0,
+            duty_window: 60.0
+        }
+    }
+
+    // Output after running at full power for `seconds`, one command per 100 ms
+    fn run(state: &mut GovernorState, start: Instant, seconds: f32, params: &GovernorParams) -> f32 {
+        let mut output_tx = 0.0;
+        for step in 0..=(seconds * 10.0) as u32 {
+            output_tx = state.govern(255.0, start + Duration::from_millis(step as u64 * 100), params);
+        }
+        output_tx
+    }
+
+    #[test]
+    fn disabled_governor_keeps_output() {
+        let params = params();
+        assert!(!params.enabled());
+        let mut state = GovernorState::default();
+        assert_eq!(run(&mut state, Instant::now(), 600.0, &params), 255.0);
+    }
+
+    #[test]
+    fn heat_follows_the_time_constant() {
+        let mut state = GovernorState::default();
+        run(&mut state, Instant::now(), 10.0, &params());
+        assert!((state.heat - (1.0 - (-1.0f32).exp())).abs() < 0.01);
+    }
+
+    #[test]
+    fn heat_limit_derates_then_stops() {
+        let params = GovernorParams { max_heat: 0.5, ..params() };
+        let start = Instant::now();
+        let mut state = GovernorState::default();
+        // Derating starts at 80% of the limit, heat 0.4 is reached after about 5 s
+        assert_eq!(run(&mut state, start, 4.0, &params), 255.0);
+        let derated = run(&mut state, start, 6.0, &params);
+        assert!(derated > 0.0 && derated < 255.0);
+        assert_eq!(state.limit.map(|(limit, _)| limit), Some(Limit::Heat));
+        // The motor never gets hotter than the limit
+        run(&mut state, start, 120.0, &params);
+        assert!(state.heat <= 0.5 + 0.01);
+    }
+
+    #[test]
+    fn duty_cycle_limits_share_of_time() {
+        let params = GovernorParams { duty_cycle: 0.5, duty_window: 10.0, ..params() };
+        let start = Instant::now();
+        let mut state = GovernorState::default();
+        assert_eq!(run(&mut state, start, 3.0, &params), 255.0);
+        run(&mut state, start, 120.0, &params);
+        assert!(state.duty <= 0.5 + 0.01);
+        assert_eq!(state.limit.map(|(limit, _)| limit), Some(Limit::DutyCycle));
+    }
+
+    #[test]
+    fn motor_rests_after_max_runtime() {
+        let params = GovernorParams { max_runtime: Duration::from_secs(5), rest_time: Duration::from_secs(2), ..params() };
+        let start = Instant::now();
+        let mut state = GovernorState::default();
+        assert_eq!(run(&mut state, start, 5.0, &params), 0.0);
+        assert_eq!(state.limit.map(|(limit, _)| limit), Some(Limit::Resting));
+        assert_eq!(state.govern(255.0, start + Duration::from_millis(6900), &params), 0.0);
+        assert_eq!(state.govern(255.0, start + Duration::from_millis(7100), &params), 255.0);
+        assert_eq!(state.limit, None);
+    }
+}
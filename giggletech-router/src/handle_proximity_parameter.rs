@@ -8,15 +8,28 @@
     **Key Features:**
 
     1. **Proximity Handling (`handle_proximity_parameter`)**:
-       - Receives proximity data (`value`) and determines if the device should stop or continue operating.
-       - If proximity is zero, it sends stop commands to the device.
-       - If proximity is non-zero, it processes the proximity data and sends motor control values to the device.
+       - Receives proximity data (`value`) and lets `contact_gate` decide if the contact starts, continues or stops.
+       - When the contact stops, it sends stop commands to the device once, not on every near-zero sample.
+       - While the contact is active, it processes the proximity data and sends motor control values to the device.
+       - Starts or stops held back by a minimum on/off time are applied once that time has passed.
+       - Contact starts and stops also drive the device's attack and release `envelope`, and every
+         gate decision is passed on to `gestures`.
 
     2. **Velocity Control**:
-       - If the device uses velocity control, the module calculates the change in proximity over time and adjusts the motor speed accordingly.
+       - If the device uses velocity control, the filtered velocity from `motion_model` drives the motor speed,
+         and the output fades out once the motion stops.
+       - In hybrid mode the velocity boost is added to a proximity baseline and fades back to the baseline.
        - Otherwise, it simply scales the motor value based on proximity.
 
-    3. **Timeout and Signal Tracking**:
+    3. **Motor Output**:
+       - Motor values are sent through `motor_output`, which fans them out to group members and keeps
+         the LED in sync with the motor.
+
+    4. **Input Filtering**:
+       - The raw proximity is smoothed with the device's input filter before any processing. The filter
+         state is kept per device in `DEVICE_FILTER_STATE`, next to `DEVICE_LAST_VALUE`.
+
+    5. **Timeout and Signal Tracking**:
        - Updates the last signal time and last proximity value for each device, ensuring proper handling of timeouts and avoiding stale data.
 
     **Usage**:
@@ -25,23 +38,42 @@
 
 use async_osc::Result;
 use async_std::sync::{Arc, Mutex};
+use async_std::task;
 use std::{
     sync::atomic::{AtomicBool},
-    time::{Instant, Duration}, collections::HashMap,
+    time::{Duration, Instant}, collections::HashMap,
 };
+use log::{info, warn};
 
 
 use crate::osc_timeout;
 use crate::terminator;
-use crate::giggletech_osc;
 use crate::data_processing;
+use crate::led_output;
+use crate::motion_model;
+use crate::input_filter::FilterState;
+use crate::motor_output;
+use crate::patterns;
+use crate::scripting;
+use crate::envelope;
+use crate::contact_gate::{self, GateDecision};
+use crate::gestures;
+use crate::session_stats;
 use lazy_static::lazy_static;
-use crate::config::DeviceConfig;
+use crate::config::{ControlMode, DeviceConfig};
 
 
 lazy_static! {
     pub static ref DEVICE_LAST_VALUE: Arc<Mutex<HashMap<String, f32>>> =
         Arc::new(Mutex::new(HashMap::new()));
+    pub(crate) static ref DEVICE_FILTER_STATE: Arc<Mutex<HashMap<String, FilterState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+// Forgets the last proximity and the filter state of a removed device
+pub(crate) async fn remove(device: &DeviceConfig) {
+    DEVICE_FILTER_STATE.lock().await.remove(device.device_uri.as_str());
+    DEVICE_LAST_VALUE.lock().await.remove(device.device_uri.as_str());
 }
 
 pub(crate) async fn handle_proximity_parameter(
@@ -49,37 +81,108 @@ pub(crate) async fn handle_proximity_parameter(
     value: f32,
     device: DeviceConfig
 ) -> Result<()> {
-    terminator::stop(running.clone()).await?;
-
     let device_ip = Arc::new(device.device_uri.clone());
 
-    // Update Last Signal Time for timeout clock 
-    let mut device_last_signal_times = osc_timeout::DEVICE_LAST_SIGNAL_TIME.lock().unwrap();
-    // let last_signal_time: Option<Instant> = device_last_signal_times.get(&device_ip.to_string()).copied();
-    let last_signal_time = device_last_signal_times.insert(device_ip.to_string(), Instant::now());
+    // Update Last Signal Time for timeout clock, the lock is released before anything awaits
+    osc_timeout::record_signal(&device);
+
+    // Smooth the raw proximity, a zero ends the contact and clears the filter
+    let mut device_filter_states = DEVICE_FILTER_STATE.lock().await;
+    let value = if value == 0.0 {
+        device_filter_states.remove(device_ip.as_str());
+        0.0
+    } else {
+        device_filter_states
+            .entry(device_ip.to_string())
+            .or_default()
+            .apply(&device.input_filter, value, Instant::now())
+    };
+    drop(device_filter_states);
+
     let mut device_last_values = DEVICE_LAST_VALUE.lock().await;
     let last_val = device_last_values.insert(device_ip.to_string(), value).unwrap_or(0.0);
+    drop(device_last_values);
 
-    if value == 0.0 {
-        println!("Stopping pats...");
-        terminator::start(running.clone(), &device_ip).await?;
+    let decision = contact_gate::update(&device, value, Instant::now());
+    apply_decision(running, decision, value, last_val, device).await
+}
 
-        for _ in 0..5 {
-            giggletech_osc::send_data(&device_ip, 0i32).await?;  
+async fn apply_decision(
+    running: Arc<AtomicBool>,
+    decision: GateDecision,
+    value: f32,
+    last_val: f32,
+    device: DeviceConfig
+) -> Result<()> {
+    gestures::observe(&device, decision, value);
+    match decision {
+        GateDecision::Stop => {
+            info!("Stopping pats...");
+            motion_model::reset(&device);
+            scripting::reset(&device);
+            let releasing = envelope::release(&device);
+            terminator::start(running.clone(), &device).await?;
+
+            for _ in 0..5 {
+                motor_output::send_live_stop(&device).await?;
+            }
+            if !patterns::is_playing(&device) && !releasing {
+                led_output::follow_motor(&device, 0.0).await?;
+            }
         }
-    } else {
-        if !device.use_velocity_control {
-            giggletech_osc::send_data(&device_ip,
-                data_processing::process_pat(value, &device, last_val)).await?;
-        } else {
-            let delta_t = match last_signal_time {
-                None => Duration::new(0, 0),
-                Some(t_prev) => Instant::now().duration_since(t_prev),
+        GateDecision::Start | GateDecision::Active => {
+            terminator::stop(running.clone()).await?;
+
+            // A new contact always gets the start kick
+            let last_val = if decision == GateDecision::Start {
+                envelope::start(&device);
+                session_stats::record_pat(&device);
+                0.0
+            } else {
+                last_val
             };
+            let headpat_tx = match device.control_mode {
+                ControlMode::Proximity => data_processing::process_pat(value, &device, last_val),
+                ControlMode::Velocity => {
+                    let velocity = motion_model::update(&device, value, Instant::now());
+                    data_processing::process_pat_advanced(value, velocity, &device)
+                }
+                ControlMode::Hybrid => {
+                    let velocity = motion_model::update(&device, value, Instant::now());
+                    data_processing::process_pat_hybrid(value, velocity, &device)
+                }
+                ControlMode::Script => {
+                    let velocity = motion_model::update(&device, value, Instant::now());
+                    data_processing::process_pat_script(value, velocity, &device)
+                }
+            };
+            motor_output::send_motor(&device, headpat_tx).await?;
+
+            // Time based scripts keep running while the hand is still
+            if device.control_mode == ControlMode::Script {
+                scripting::start_tick(&device);
+            }
 
-            giggletech_osc::send_data(&device_ip,
-                data_processing::process_pat_advanced(value, last_val, delta_t, &device)).await?;
+            // Fade out once the hand stops moving
+            if matches!(device.control_mode, ControlMode::Velocity | ControlMode::Hybrid) && headpat_tx > 0.0 {
+                motion_model::start_decay(&device);
+            }
         }
+        GateDecision::Deferred(delay, generation) => schedule_recheck(running, device, delay, generation),
+        GateDecision::Idle => {}
     }
     Ok(())
 }
+
+// Apply a start or stop held back by a dwell time, unless newer samples took over
+fn schedule_recheck(running: Arc<AtomicBool>, device: DeviceConfig, delay: Duration, generation: u64) {
+    task::spawn(async move {
+        task::sleep(delay).await;
+        if let Some((decision, value)) = contact_gate::recheck(&device, generation, Instant::now()) {
+            let device_uri = device.device_uri.clone();
+            if let Err(e) = apply_decision(running, decision, value, 0.0, device).await {
+                warn!("Failed to apply contact change for {}: {}", device_uri, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,147 @@
+/*
+    input_combiner.rs - Combining Several Proximity Parameters into One Input
+
+    Avatars often have several contacts that should feel like one (left/right of the head, multiple
+    colliders). This module lets a device listen to a list of proximity parameters and merges them
+    into a single signal before any processing happens, so `DEVICE_LAST_VALUE` and the velocity math
+    only ever see the combined value.
+
+    **Key Features:**
+
+    1. **Combine Modes (`CombineMode`)**:
+       - `max`: The strongest contact wins (default).
+       - `sum`: Contacts add up, clamped to 1.0.
+       - `weighted`: Weighted average using the device's `weights`, one per parameter (without
+         `weights` every parameter counts as 1.0). `config.rs` rejects a list of another length.
+       - `latest`: The most recently received parameter wins.
+
+    2. **Input Tracking (`combine_input`)**:
+       - Keeps the last value of every parameter per device in `DEVICE_INPUT_VALUES` and returns the
+         combined signal each time one of them changes. `input_values` returns the separate values,
+         e.g. for directional panning in `spatial`.
+
+    **Usage**:
+    - Call `combine_input` when a device's proximity parameter is received, then process the result.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::DeviceConfig;
+
+lazy_static! {
+    static ref DEVICE_INPUT_VALUES: Arc<Mutex<HashMap<String, Vec<f32>>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CombineMode {
+    Max,
+    SumClamped,
+    WeightedAverage,
+    LatestWins
+}
+
+impl CombineMode {
+    pub fn parse(mode: &str) -> Option<CombineMode> {
+        match mode.to_lowercase().as_str() {
+            "max" => Some(CombineMode::Max),
+            "sum" => Some(CombineMode::SumClamped),
+            "weighted" => Some(CombineMode::WeightedAverage),
+            "latest" => Some(CombineMode::LatestWins),
+            _ => None,
+        }
+    }
+}
+
+fn combine(values: &[f32], weights: &[f32], mode: CombineMode, latest: f32) -> f32 {
+    match mode {
+        CombineMode::Max => values.iter().cloned().fold(0.0, f32::max),
+        CombineMode::SumClamped => values.iter().sum::<f32>().min(1.0),
+        CombineMode::WeightedAverage => {
+            let (total, weight_sum) = values.iter().enumerate().fold((0.0, 0.0), |(total, weight_sum), (i, value)| {
+                let weight = weights.get(i).cloned().unwrap_or(1.0);
+                (total + value * weight, weight_sum + weight)
+            });
+            if weight_sum > 0.0 { total / weight_sum } else { 0.0 }
+        }
+        CombineMode::LatestWins => latest,
+    }
+}
+
+// Store the new parameter value and return the combined signal for the device
+pub(crate) fn combine_input(device: &DeviceConfig, address: &str, value: f32) -> f32 {
+    let parameters = &device.proximity_parameters;
+    if parameters.len() == 1 {
+        return value;
+    }
+    let index = match parameters.iter().position(|param| **param == address) {
+        Some(index) => index,
+        None => return value,
+    };
+
+    let mut device_input_values = match DEVICE_INPUT_VALUES.lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            warn!("Mutex poisoned for device {}, using raw input", device.device_uri);
+            return value;
+        }
+    };
+    let values = device_input_values
+        .entry(device.device_uri.to_string())
+        .or_insert_with(|| vec![0.0; parameters.len()]);
+    values.resize(parameters.len(), 0.0);
+    values[index] = value;
+
+    combine(values, &device.input_weights, device.combine_mode, value)
+}
+
+// Last value of each of the device's proximity parameters
+pub(crate) fn input_values(device: &DeviceConfig) -> Vec<f32> {
+    DEVICE_INPUT_VALUES.lock().ok()
+        .and_then(|device_input_values| device_input_values.get(device.device_uri.as_str()).cloned())
+        .unwrap_or_default()
+}
+
+// Forgets the last values of a removed device
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_input_values) = DEVICE_INPUT_VALUES.lock() {
+        device_input_values.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_takes_the_strongest_contact() {
+        assert_eq!(combine(&[0.2, 0.7, 0.4], &[], CombineMode::Max, 0.4), 0.7);
+    }
+
+    #[test]
+    fn sum_is_clamped_to_one() {
+        assert!((combine(&[0.2, 0.3], &[], CombineMode::SumClamped, 0.3) - 0.5).abs() < 1e-6);
+        assert_eq!(combine(&[0.6, 0.7], &[], CombineMode::SumClamped, 0.7), 1.0);
+    }
+
+    #[test]
+    fn weighted_average_uses_the_weights() {
+        assert!((combine(&[1.0, 0.0], &[3.0, 1.0], CombineMode::WeightedAverage, 0.0) - 0.75).abs() < 1e-6);
+        assert!((combine(&[1.0, 0.0], &[], CombineMode::WeightedAverage, 0.0) - 0.5).abs() < 1e-6);
+        assert_eq!(combine(&[1.0, 1.0], &[0.0, 0.0], CombineMode::WeightedAverage, 1.0), 0.0);
+    }
+
+    #[test]
+    fn latest_takes_the_last_received_value() {
+        assert_eq!(combine(&[0.9, 0.1], &[], CombineMode::LatestWins, 0.1), 0.1);
+    }
+
+    #[test]
+    fn modes_are_parsed_by_name() {
+        assert_eq!(CombineMode::parse("Weighted"), Some(CombineMode::WeightedAverage));
+        assert_eq!(CombineMode::parse("average"), None);
+    }
+}
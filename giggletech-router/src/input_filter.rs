@@ -0,0 +1,129 @@
+/*
+    input_filter.rs - Smoothing Filters for Proximity Signals
+
+    Raw VRChat contact proximity is noisy at the edges of colliders, which makes motors stutter.
+    This module smooths the proximity signal of each device before it reaches `process_pat` or
+    `process_pat_advanced`.
+
+    **Key Features:**
+
+    1. **Filter Types (`InputFilter`)**:
+       - `none`: The raw signal is used (default).
+       - `ema`: Exponential moving average. `filter_alpha` (0.0 - 1.0, default 0.5) is the weight of
+         each new sample; lower is smoother.
+       - `median`: Median of the last `filter_window` samples (default 5). Removes single-sample spikes.
+       - `one_euro`: One-Euro filter. Smooths heavily while the hand is still and follows quickly when
+         it moves. `filter_min_cutoff` (Hz, default 1.0) sets the smoothing at rest, `filter_beta`
+         (default 1.0) how fast it opens up with speed, and `filter_d_cutoff` (Hz, default 1.0) the
+         smoothing of the speed estimate.
+
+    2. **Filter State (`FilterState`)**:
+       - Each device keeps its own state, stored next to `DEVICE_LAST_VALUE`. The state is cleared when
+         the contact ends so the next contact starts fresh.
+
+    **Usage**:
+    - `FilterState::apply` returns the filtered proximity for a new raw sample.
+*/
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum InputFilter {
+    None,
+    Ema { alpha: f32 },
+    Median { window: usize },
+    OneEuro { min_cutoff: f32, beta: f32, d_cutoff: f32 }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FilterState {
+    value: Option<f32>,
+    derivative: f32,
+    last_time: Option<Instant>,
+    window: VecDeque<f32>
+}
+
+fn smoothing_factor(cutoff: f32, delta_t: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    1.0 / (1.0 + tau / delta_t)
+}
+
+impl FilterState {
+    pub fn apply(&mut self, filter: &InputFilter, value: f32, now: Instant) -> f32 {
+        let filtered = match *filter {
+            InputFilter::None => value,
+            InputFilter::Ema { alpha } => match self.value {
+                Some(previous) => previous + alpha * (value - previous),
+                None => value,
+            },
+            InputFilter::Median { window } => {
+                self.window.push_back(value);
+                while self.window.len() > window.max(1) {
+                    self.window.pop_front();
+                }
+                let mut sorted = self.window.iter().cloned().collect::<Vec<_>>();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                sorted[sorted.len() / 2]
+            }
+            InputFilter::OneEuro { min_cutoff, beta, d_cutoff } => match (self.value, self.last_time) {
+                (Some(previous), Some(last_time)) => {
+                    let delta_t = now.saturating_duration_since(last_time).as_secs_f32().max(1e-3);
+                    let derivative = (value - previous) / delta_t;
+                    self.derivative += smoothing_factor(d_cutoff, delta_t) * (derivative - self.derivative);
+                    let cutoff = min_cutoff + beta * self.derivative.abs();
+                    previous + smoothing_factor(cutoff, delta_t) * (value - previous)
+                }
+                _ => value,
+            },
+        };
+        self.value = Some(filtered);
+        self.last_time = Some(now);
+        filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn run(filter: InputFilter, values: &[f32]) -> Vec<f32> {
+        let start = Instant::now();
+        let mut state = FilterState::default();
+        values.iter().enumerate()
+            .map(|(i, value)| state.apply(&filter, *value, start + Duration::from_millis(20 * i as u64)))
+            .collect()
+    }
+
+    #[test]
+    fn none_passes_the_signal_through() {
+        assert_eq!(run(InputFilter::None, &[0.1, 0.9, 0.3]), vec![0.1, 0.9, 0.3]);
+    }
+
+    #[test]
+    fn ema_moves_part_of_the_way_to_each_sample() {
+        let filtered = run(InputFilter::Ema { alpha: 0.5 }, &[0.0, 1.0, 1.0]);
+        assert_eq!(filtered, vec![0.0, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn median_removes_single_sample_spikes() {
+        let filtered = run(InputFilter::Median { window: 3 }, &[0.2, 0.2, 1.0, 0.2, 0.3]);
+        assert_eq!(filtered[2], 0.2);
+        assert_eq!(filtered[4], 0.3);
+    }
+
+    #[test]
+    fn one_euro_smooths_at_rest_and_follows_motion() {
+        let filter = InputFilter::OneEuro { min_cutoff: 1.0, beta: 0.0, d_cutoff: 1.0 };
+        let still = run(filter, &[0.5, 0.52, 0.48, 0.5]);
+        assert!(still.iter().all(|value| (value - 0.5).abs() < 0.005), "{:?}", still);
+
+        let fast = InputFilter::OneEuro { min_cutoff: 1.0, beta: 10.0, d_cutoff: 1.0 };
+        let slow_step = run(filter, &[0.0, 1.0])[1];
+        let fast_step = run(fast, &[0.0, 1.0])[1];
+        assert!(fast_step > slow_step, "{} <= {}", fast_step, slow_step);
+    }
+}
@@ -0,0 +1,140 @@
+/*
+    led_output.rs - LED Output Channel for GiggleTech Devices
+
+    This module drives the status LED on a device so the visual feedback on the puck matches what
+    the avatar is doing. The LED can either follow an avatar parameter directly or mirror the motor
+    intensity that is currently being sent to the device.
+
+    **Key Features:**
+
+    1. **Parameter Source (`handle_led_parameter`)**:
+       - Maps any avatar parameter (0.0 - 1.0) to an LED brightness.
+       - An optional colour parameter selects the hue of the LED (`handle_led_color_parameter`).
+
+    2. **Motor Source (`follow_motor`)**:
+       - Derives the LED brightness from the motor Tx value relative to the device's current max speed,
+         so the LED brightens as the pats get stronger.
+
+    3. **Brightness Curve (`brightness_to_tx`)**:
+       - Applies a gamma curve between `led_min_brightness` and `led_max_brightness` so the perceived
+         brightness tracks the input linearly.
+
+    4. **Timeout (`led_timeout`)**:
+       - Turns the LED off if it has not been updated within the device's `led_timeout`, independent of
+         the motor timeout.
+
+    **Usage**:
+    - Configure `led_source` on a device (either `motor` or an avatar parameter name).
+    - Spawn `led_timeout` for every device that has an LED configured.
+*/
+
+use async_osc::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::{DeviceConfig, LedConfig, LedSource};
+use crate::data_processing;
+use crate::giggletech_osc;
+
+lazy_static! {
+    // Last LED update per device, and whether the LED was left on
+    static ref DEVICE_LAST_LED_TIME: Arc<Mutex<HashMap<String, (Instant, bool)>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+pub fn brightness_to_tx(level: f32, led: &LedConfig) -> i32 {
+    if level <= 0.0 {
+        return 0;
+    }
+    let level = level.min(1.0).powf(led.gamma);
+    ((led.min_brightness + (led.max_brightness - led.min_brightness) * level) * 255.0).round() as i32
+}
+
+// Convert a hue (0.0 - 1.0) into a fully saturated RGB colour
+pub fn hue_to_rgb(hue: f32) -> (i32, i32, i32) {
+    let h = (hue.clamp(0.0, 1.0) * 6.0) % 6.0;
+    let x = 1.0 - ((h % 2.0) - 1.0).abs();
+    let (r, g, b) = match h as i32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    ((r * 255.0f32).round() as i32, (g * 255.0f32).round() as i32, (b * 255.0f32).round() as i32)
+}
+
+async fn send_brightness(device: &DeviceConfig, led: &LedConfig, level: f32) -> Result<()> {
+    let brightness = brightness_to_tx(level, led);
+    if let Ok(mut last_led_times) = DEVICE_LAST_LED_TIME.lock() {
+        last_led_times.insert(device.device_uri.to_string(), (Instant::now(), brightness > 0));
+    }
+    for target in device.targets() {
+        giggletech_osc::send_led(&target.ip, brightness).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn handle_led_parameter(value: f32, device: &DeviceConfig) -> Result<()> {
+    match &device.led {
+        Some(led) => send_brightness(device, led, value).await,
+        None => Ok(()),
+    }
+}
+
+pub(crate) async fn handle_led_color_parameter(value: f32, device: &DeviceConfig) -> Result<()> {
+    let rgb = hue_to_rgb(value);
+    for target in device.targets() {
+        giggletech_osc::send_led_color(&target.ip, rgb).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn follow_motor(device: &DeviceConfig, motor_tx: f32) -> Result<()> {
+    match &device.led {
+        Some(led) if matches!(led.source, LedSource::Motor) => {
+            send_brightness(device, led, data_processing::motor_level(motor_tx, device)).await
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(crate) async fn led_timeout(device: &DeviceConfig, timeout: u64) -> anyhow::Result<()> {
+    let device_ip = device.device_uri.as_str();
+    loop {
+        async_std::task::sleep(Duration::from_secs(1)).await;
+
+        let expired = match DEVICE_LAST_LED_TIME.lock() {
+            Ok(guard) => match guard.get(device_ip) {
+                Some((last_time, true)) => last_time.elapsed() >= Duration::from_secs(timeout),
+                _ => false,
+            },
+            Err(_) => {
+                warn!("Mutex poisoned for LED on device {}, skipping timeout check", device_ip);
+                continue;
+            }
+        };
+
+        if expired {
+            for target in device.targets() {
+                if let Err(e) = giggletech_osc::send_led(&target.ip, 0).await {
+                    warn!("Timeout: Failed to turn off LED on {}: {}", target.ip, e);
+                }
+            }
+            if let Ok(mut last_led_times) = DEVICE_LAST_LED_TIME.lock() {
+                last_led_times.insert(device_ip.to_string(), (Instant::now(), false));
+            }
+        }
+    }
+}
+
+// Forgets when the LED of a removed device was last driven
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut last_led_times) = DEVICE_LAST_LED_TIME.lock() {
+        last_led_times.remove(device.device_uri.as_str());
+    }
+}
@@ -0,0 +1,336 @@
+/*
+    logging.rs - Leveled Logging to the Console and a Rotating Log File
+
+    Every module logs through the `log` macros (`error!`, `warn!`, `info!`, `debug!`, `trace!`). This
+    module is the logger behind them: it prints to the console and appends to a log file.
+
+    **Settings (`LogSettings`, from the `setup` section):**
+
+    - `log_level`: Lowest level written to the log file (`error`, `warn`, `info`, `debug`, `trace` or
+      `off`, default `info`).
+    - `console_log_level`: Lowest level printed to the console (default `debug`, which includes the
+      per-sample lines of each device).
+    - `log_filters`: Levels for single modules, e.g. `{ giggletech_osc: trace, data_processing: info }`,
+      used for both the console and the file.
+    - `log_format`: `text` (default) or `json`, one JSON object per line.
+    - `log_dir`: Folder of the log file (default: next to config.yml, see `paths`). `--log-dir` on the
+      command line takes precedence.
+    - `log_rotation`: Start a new file `daily` (default), `hourly` or `never`.
+    - `log_max_size`: Start a new file once it grows past this many MB (default 10, 0 = no limit).
+    - `log_keep`: Number of rotated files kept (default 5).
+
+    **Key Features:**
+
+    1. **Setup (`init`, `configure`)**:
+       - `init` starts logging with the default settings before the config is read, `configure`
+         switches to the configured settings.
+
+    2. **Rotation**:
+       - The current file is `giggletech_log.txt` (`giggletech_log.jsonl` for JSON). A rotated file
+         gets the time of the rotation in its name and the oldest ones beyond `log_keep` are removed.
+
+    3. **Console**:
+       - Messages are printed as they are, warnings and errors to stderr with a prefix. Nothing is
+         printed while the dashboard is shown.
+*/
+
+use chrono::{DateTime, Local};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use lazy_static::lazy_static;
+
+use crate::dashboard;
+use crate::paths;
+
+// Name of the log file without extension
+const LOG_NAME: &str = "giggletech_log";
+// Crate name, the prefix of module paths
+const CRATE_NAME: &str = "async_osc";
+
+static LOGGER: Logger = Logger;
+
+lazy_static! {
+    static ref SETTINGS: RwLock<LogSettings> = RwLock::new(LogSettings::default());
+    static ref LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Rotation {
+    Never,
+    Hourly,
+    Daily
+}
+
+impl Rotation {
+    pub fn parse(name: &str) -> Option<Rotation> {
+        match name.to_lowercase().as_str() {
+            "never" | "none" => Some(Rotation::Never),
+            "hourly" => Some(Rotation::Hourly),
+            "daily" => Some(Rotation::Daily),
+            _ => None,
+        }
+    }
+
+    // Files written in different periods go to different files
+    fn period(&self, time: DateTime<Local>) -> String {
+        match self {
+            Rotation::Never => String::new(),
+            Rotation::Hourly => time.format("%Y-%m-%d %H").to_string(),
+            Rotation::Daily => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LogSettings {
+    pub level: LevelFilter,
+    pub console_level: LevelFilter,
+    // Module name and level, e.g. ("giggletech_osc", Trace)
+    pub filters: Vec<(String, LevelFilter)>,
+    pub json: bool,
+    pub dir: PathBuf,
+    pub rotation: Rotation,
+    // Bytes, 0 = no limit
+    pub max_size: u64,
+    pub keep: usize
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: LevelFilter::Info,
+            console_level: LevelFilter::Debug,
+            filters: Vec::new(),
+            json: false,
+            dir: paths::log_dir(None),
+            rotation: Rotation::Daily,
+            max_size: 10 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+impl LogSettings {
+    fn file_name(&self) -> String {
+        format!("{}.{}", LOG_NAME, if self.json { "jsonl" } else { "txt" })
+    }
+
+    // Level of the most specific filter for a module
+    fn filter(&self, module: &str) -> Option<LevelFilter> {
+        self.filters.iter()
+            .filter(|(name, _)| module == name || module.starts_with(&format!("{}::", name)))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, level)| *level)
+    }
+
+    fn levels(&self, module: &str) -> (LevelFilter, LevelFilter) {
+        match self.filter(module) {
+            Some(level) => (level, level),
+            None => (self.level, self.console_level),
+        }
+    }
+}
+
+pub(crate) fn parse_level(name: &str) -> Option<LevelFilter> {
+    name.parse().ok()
+}
+
+struct LogFile {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    period: String
+}
+
+impl LogFile {
+    fn open(settings: &LogSettings) -> std::io::Result<LogFile> {
+        fs::create_dir_all(&settings.dir)?;
+        let path = settings.dir.join(settings.file_name());
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // An existing file belongs to the period it was last written in
+        let modified = metadata.modified().map(DateTime::<Local>::from).unwrap_or_else(|_| Local::now());
+        Ok(LogFile { file, path, size: metadata.len(), period: settings.rotation.period(modified) })
+    }
+
+    fn needs_rotation(&self, settings: &LogSettings, now: DateTime<Local>, len: u64) -> bool {
+        let too_big = settings.max_size > 0 && self.size > 0 && self.size + len > settings.max_size;
+        too_big || settings.rotation.period(now) != self.period
+    }
+}
+
+// Rename the current file and remove the oldest rotated files
+fn rotate(settings: &LogSettings, path: &Path, now: DateTime<Local>) -> std::io::Result<()> {
+    let extension = if settings.json { "jsonl" } else { "txt" };
+    let rotated = settings.dir.join(format!("{}.{}.{}", LOG_NAME, now.format("%Y-%m-%d_%H-%M-%S-%3f"), extension));
+    fs::rename(path, rotated)?;
+
+    let current = settings.file_name();
+    let mut old_files = fs::read_dir(&settings.dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with(&format!("{}.", LOG_NAME)) && name.ends_with(extension) && *name != current)
+        .collect::<Vec<_>>();
+    old_files.sort();
+    let excess = old_files.len().saturating_sub(settings.keep);
+    for name in old_files.iter().take(excess) {
+        fs::remove_file(settings.dir.join(name))?;
+    }
+    Ok(())
+}
+
+fn format_line(settings: &LogSettings, time: DateTime<Local>, level: Level, module: &str, message: &str) -> String {
+    if settings.json {
+        serde_json::json!({
+            "time": time.to_rfc3339(),
+            "level": level.as_str(),
+            "module": module,
+            "message": message,
+        }).to_string()
+    } else {
+        format!("[{}] {:<5} {}: {}", time.format("%Y-%m-%d %H:%M:%S"), level, module, message)
+    }
+}
+
+fn write_file(settings: &LogSettings, line: &str, now: DateTime<Local>) {
+    let mut log_file = match LOG_FILE.lock() {
+        Ok(log_file) => log_file,
+        Err(_) => return,
+    };
+    let len = line.len() as u64 + 1;
+    if let Some(current) = log_file.as_ref() {
+        if current.needs_rotation(settings, now, len) {
+            let path = current.path.clone();
+            *log_file = None;
+            if let Err(e) = rotate(settings, &path, now) {
+                eprintln!("Failed to rotate log file: {}", e);
+            }
+        }
+    }
+    if log_file.is_none() {
+        match LogFile::open(settings) {
+            Ok(opened) => *log_file = Some(opened),
+            Err(e) => {
+                eprintln!("Failed to open log file: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some(current) = log_file.as_mut() {
+        match writeln!(current.file, "{}", line) {
+            Ok(_) => current.size += len,
+            Err(e) => eprintln!("Failed to write to log file: {}", e),
+        }
+    }
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let settings = match SETTINGS.read() {
+            Ok(settings) => settings,
+            Err(_) => return,
+        };
+        let module = match record.target().strip_prefix(CRATE_NAME) {
+            Some("") => "main",
+            Some(path) => path.trim_start_matches("::"),
+            None => record.target(),
+        };
+        let (file_level, console_level) = settings.levels(module);
+        let level = record.level();
+        let message = record.args().to_string();
+
+        if level <= console_level && !dashboard::is_active() {
+            match level {
+                Level::Error => eprintln!("Error: {}", message),
+                Level::Warn => eprintln!("Warning: {}", message),
+                _ => println!("{}", message),
+            }
+        }
+        if level <= file_level {
+            let now = Local::now();
+            write_file(&settings, &format_line(&settings, now, level, module, &message), now);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut log_file) = LOG_FILE.lock() {
+            if let Some(current) = log_file.as_mut() {
+                let _ = current.file.flush();
+            }
+        }
+    }
+}
+
+// Start logging with the default settings
+pub(crate) fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+// Switch to new settings, the log file is reopened at its new location
+pub(crate) fn configure(settings: LogSettings) {
+    if let Ok(mut current) = SETTINGS.write() {
+        *current = settings;
+    }
+    if let Ok(mut log_file) = LOG_FILE.lock() {
+        *log_file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file() -> File {
+        OpenOptions::new().create(true).append(true).open(std::env::temp_dir().join("giggletech_log_test.txt")).unwrap()
+    }
+
+    #[test]
+    fn module_filters_use_the_most_specific_match() {
+        let settings = LogSettings {
+            filters: vec![
+                ("config".to_string(), LevelFilter::Warn),
+                ("config::oscq_giggletech".to_string(), LevelFilter::Trace),
+            ],
+            ..LogSettings::default()
+        };
+        assert_eq!(settings.levels("config"), (LevelFilter::Warn, LevelFilter::Warn));
+        assert_eq!(settings.levels("config::oscq_giggletech"), (LevelFilter::Trace, LevelFilter::Trace));
+        assert_eq!(settings.levels("config_validator"), (LevelFilter::Info, LevelFilter::Debug));
+    }
+
+    #[test]
+    fn rotation_starts_a_new_file_per_period_or_size() {
+        let settings = LogSettings { max_size: 100, ..LogSettings::default() };
+        let now = Local::now();
+        let log_file = LogFile {
+            file: test_file(),
+            path: PathBuf::new(),
+            size: 90,
+            period: settings.rotation.period(now),
+        };
+        assert!(!log_file.needs_rotation(&settings, now, 10));
+        assert!(log_file.needs_rotation(&settings, now, 11));
+        assert!(log_file.needs_rotation(&settings, now + chrono::Duration::days(1), 1));
+    }
+
+    #[test]
+    fn json_lines_carry_level_and_module() {
+        let settings = LogSettings { json: true, ..LogSettings::default() };
+        let line = format_line(&settings, Local::now(), Level::Warn, "osc_timeout", "say \"hi\"");
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["module"], "osc_timeout");
+        assert_eq!(value["message"], "say \"hi\"");
+    }
+}
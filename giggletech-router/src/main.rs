@@ -20,7 +20,8 @@
     3. **Timeout Management (`osc_timeout`)**:
        - Each device has a timeout mechanism. If no OSC signal is received within the configured timeout period, 
          the device will stop sending motor control signals.
-       - Timeouts are handled concurrently for each device using `task::spawn` to run asynchronously.
+       - Timeouts are handled concurrently for each device using `task::spawn` to run asynchronously
+         (started by `config_reload::start_device`).
 
     4. **OSC Packet Listening and Processing**:
        - The router listens for OSC packets in a loop, processing each packet as it arrives.
@@ -33,11 +34,49 @@
        - When proximity data is received, the system adjusts the motor speed for each device accordingly.
        - If the proximity signal is zero, the device is stopped via the `terminator`.
 
+    6. **LED Output**:
+       - Devices with an LED configured are driven from their LED parameters (or the motor), each with
+         its own LED timeout task.
+
+    7. **Haptic Patterns**:
+       - Pattern trigger parameters start and stop haptic patterns on their device (see `patterns`).
+
+    8. **Scripted Mappings**:
+       - Device scripts are watched and reloaded when their file changes (see `scripting`).
+
+    9. **Gestures**:
+       - Recognised gestures (see `gestures`) are logged.
+
+    10. **Spatial Panning**:
+       - Contact position and directional proximity parameters move the contact across the members
+         of a group with a `layout` (see `spatial`).
+
+    11. **Motor Calibration**:
+       - `calibrate <device ip>` runs the interactive calibration wizard instead of the router (see
+         `calibration`).
+
+    12. **Dashboard**:
+       - `monitor` runs the router with a live full-screen view of every device with mute and
+         stop-all keys (see `dashboard`).
+
+    13. **Session History**:
+       - Per-device statistics of the session are saved to the history folder, and `history`
+         summarises or exports past sessions instead of running the router (see `session_stats`).
+
+    14. **Config Hot Reload**:
+       - Changes to `config.yml` are validated and applied while running, invalid edits are
+         rejected and the running configuration is kept (see `config_reload`).
+
+    15. **Command Line**:
+       - Subcommands (`run`, `monitor`, `validate`, `devices`, `test-device`, `stop-all`, `calibrate`,
+         `history`) and the `--config` / `--log-dir` options (see `cli`), with the default file
+         locations in `paths`. Errors end the process with exit code 1.
+
     **System Tray and Minimization (Future Feature)**:
        - The system tray minimization functionality is planned for future updates, allowing the OSC router to run in the background.
 
     **Usage**:
-    - Run the application to automatically set up device communication and handle proximity/motor controls in real-time.
+    - Run the application (or `run`) to automatically set up device communication and handle proximity/motor controls in real-time.
     - The router listens on the specified OSC ports and adjusts device behavior based on incoming OSC messages.
 
     **Example Workflow**:
@@ -46,16 +85,14 @@
     3. Continuously receive and process OSC messages to control devices (e.g., motor speed for headpats).
 */
 
-use async_osc::{prelude::*, OscPacket, OscType, Result};
-use async_std::{stream::StreamExt, task::{self}, sync::Arc};
+use async_osc::{prelude::*, OscPacket, OscType};
+use async_std::{channel, stream::StreamExt, task::{self}, sync::Arc};
 use std::sync::atomic::{AtomicBool};
-use std::fs::OpenOptions;
-use std::io::{self, Write}; // For file logging and keeping the console open
-use chrono::Local; // For getting the local time
-use std::path::Path; // Added for checking file existence
-use std::time::Duration;
+use clap::Parser;
+use std::io; // For keeping the console open
+use std::time::{Duration, Instant};
+use log::{error, info, LevelFilter};
 
-use crate::osc_timeout::osc_timeout;
 mod data_processing;
 mod config;
 mod giggletech_osc;
@@ -63,62 +100,155 @@ mod terminator;
 mod osc_timeout;
 mod handle_proximity_parameter;
 mod stop_pats;
-
-// Function to log messages to a file with a timestamp
-fn log_to_file(message: &str) {
-    // Get the current local time
-    let now = Local::now();
-    let timestamp = now.format("%Y-%m-%d %H:%M:%S").to_string(); // Format the time as desired
-
-    // Open the log file in append mode, creating it if it doesn't exist
-    match OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("giggletech_log.txt") {
-        Ok(mut file) => {
-            // Write the timestamp and the log message to the file
-            if let Err(e) = writeln!(file, "[{}] {}", timestamp, message) {
-                eprintln!("Failed to write to log file: {}", e);
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to open log file: {}", e);
-        }
-    }
-}
+mod led_output;
+mod motor_output;
+mod input_combiner;
+mod osc_auth;
+mod response_curve;
+mod motion_model;
+mod input_filter;
+mod patterns;
+mod scripting;
+mod contact_gate;
+mod governor;
+mod envelope;
+mod pulse;
+mod gestures;
+mod spatial;
+mod calibration;
+mod dashboard;
+mod logging;
+mod session_stats;
+mod config_reload;
+mod paths;
+mod cli;
+
+use cli::{Cli, Command};
+use logging::LogSettings;
+
+// Resend interval while `test-device` runs the motor
+const TEST_KEEPALIVE: Duration = Duration::from_millis(250);
 
 #[async_std::main]
 async fn main() {
+    let cli = Cli::parse();
+    let command = cli.command();
+    paths::init(cli.locations());
+
+    logging::init();
+    // One-shot commands only print to the console
+    let file_level = if command.runs_router() { LogSettings::default().level } else { LevelFilter::Off };
+    logging::configure(LogSettings { level: file_level, ..LogSettings::default() });
 
-    
     // Set a catch-all panic hook to log any panic messages
     std::panic::set_hook(Box::new(|panic_info| {
-        let message = format!("Application panicked: {}", panic_info);
-        log_to_file(&message);
+        dashboard::end_on_panic();
+        error!("Application panicked: {}", panic_info);
     }));
 
-    log_to_file("Starting GiggleTech OSC Router...");
+    let result = match &command {
+        Command::Run | Command::Monitor => {
+            info!("Starting GiggleTech OSC Router...");
+            run_giggletech(command == Command::Monitor).await.map_err(|e| format!("Application encountered an error: {}", e))
+        }
+        Command::Validate => validate(),
+        Command::Devices => print_devices(),
+        Command::TestDevice { ip, level, seconds } => test_device(ip, *level, *seconds).await,
+        Command::StopAll => stop_all().await,
+        // Motor calibration wizard instead of the router
+        Command::Calibrate { ip } => calibration::run(ip).await,
+        // Summary or export of past sessions instead of the router
+        Command::History { csv, json } => session_stats::run_command(csv.as_deref(), json.as_deref()),
+    };
 
-    // Call the main logic and handle any errors
-    if let Err(e) = run_giggletech().await {
-        let error_message = format!("Application encountered an error: {}", e);
-        log_to_file(&error_message);
+    if let Err(e) = result {
+        error!("{}", e);
+        // Keep a double-clicked console window open on Windows
+        if cfg!(windows) && cli.command.is_none() {
+            println!("Press Enter to exit...");
+            let mut input = String::new();
+            let _ = io::stdin().read_line(&mut input);
+        }
+        std::process::exit(1);
     }
+}
+
+// `validate`: read config.yml without starting the router
+fn validate() -> Result<(), String> {
+    let (_, devices) = config::check_config()?;
+    println!("{} is valid ({} devices)", paths::config_path().display(), devices.len());
+    Ok(())
+}
+
+// `devices`: the device map printed at startup
+fn print_devices() -> Result<(), String> {
+    let (global_config, devices) = config::check_config()?;
+    println!("Device Maps ({})", paths::config_path().display());
+    for line in config::device_map(&devices) {
+        println!("{}", line);
+    }
+    println!(" OSC port: {}", global_config.port_rx_setting);
+    println!(" Timeout: {}s", global_config.timeout);
+    Ok(())
+}
+
+// `test-device`: ping a configured device and run its motor at `level` % for `seconds`
+async fn test_device(ip: &str, level: f32, seconds: f32) -> Result<(), String> {
+    let (_, devices) = config::check_config()?;
+    let (device, target) = devices.iter()
+        .find_map(|device| device.targets().into_iter().find(|target| *target.ip == ip).map(|target| (device, target)))
+        .ok_or_else(|| format!("No device or group member with ip {} in {}", ip, paths::config_path().display()))?;
+    config_reload::register_keys(device);
+
+    ping_device(ip).await;
+    let tx = level.clamp(0.0, 100.0) / 100.0 * 255.0;
+    println!("Running {} at {:.0}% for {:.1}s...", ip, level.clamp(0.0, 100.0), seconds);
+    let until = Instant::now() + Duration::from_secs_f32(seconds.max(0.0));
+    let result = async {
+        while Instant::now() < until {
+            giggletech_osc::send_data(ip, tx, target.output_format).await?;
+            task::sleep(TEST_KEEPALIVE.min(until.saturating_duration_since(Instant::now()))).await;
+        }
+        Ok::<(), async_osc::Error>(())
+    }.await;
+    // Never leave the motor running
+    let stopped = giggletech_osc::send_data(ip, 0.0, target.output_format).await;
+    result.and(stopped).map_err(|e| format!("Failed to send to {}: {}", ip, e))?;
+    println!("Done, motor stopped.");
+    Ok(())
+}
 
-    // Keep the console open even after a crash or an error
-    println!("Press Enter to exit...");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
+// `stop-all`: stop every configured device, e.g. from a script or after a crash
+async fn stop_all() -> Result<(), String> {
+    let (_, devices) = config::check_config()?;
+    let mut failed = 0;
+    for device in devices.iter() {
+        // Counters follow the clock, a running router's next message is still accepted
+        config_reload::register_keys(device);
+        for target in device.targets() {
+            match giggletech_osc::send_data(&target.ip, 0.0, target.output_format).await {
+                Ok(()) => println!("Stopped {}", target.ip),
+                Err(e) => {
+                    error!("Failed to stop {}: {}", target.ip, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} devices could not be stopped", failed)),
+    }
 }
 
-async fn run_giggletech() -> async_osc::Result<()> {
-    log_to_file("Loading configuration...");
+async fn run_giggletech(show_dashboard: bool) -> async_osc::Result<()> {
+    info!("Loading configuration...");
 
     // Check if config.yml exists
-    if !Path::new("config.yml").exists() {
-        let error_msg = "Configuration file (config.yml) not found.";
-        log_to_file(error_msg);
-        eprintln!("{}", error_msg);
+    let config_path = paths::config_path();
+    if !config_path.exists() {
+        let error_msg = format!("Configuration file ({}) not found.", config_path.display());
+        error!("{}", error_msg);
         return Err(async_osc::Error::Io(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             error_msg
@@ -129,8 +259,7 @@ async fn run_giggletech() -> async_osc::Result<()> {
         Ok(config) => config,
         Err(e) => {
             let error_msg = format!("Config file error: {}", e);
-            log_to_file(&error_msg);
-            eprintln!("{}", error_msg);
+            error!("{}", error_msg);
             return Err(async_osc::Error::Io(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 error_msg
@@ -139,8 +268,9 @@ async fn run_giggletech() -> async_osc::Result<()> {
     };
     
     let timeout = global_config.timeout;
+    logging::configure(global_config.logging.clone());
 
-    log_to_file("Configuration loaded successfully. Setting up sockets and timeouts.");
+    info!("Configuration loaded successfully. Setting up sockets and timeouts.");
 
     // Start connection manager
     giggletech_osc::start_connection_manager().await;
@@ -160,23 +290,53 @@ async fn run_giggletech() -> async_osc::Result<()> {
     let running = Arc::new(AtomicBool::new(false));
 
     // Rx/Tx Socket Setup
-    let mut rx_socket = giggletech_osc::setup_rx_socket(global_config.port_rx.to_string()).await?;
+    let rx_socket = giggletech_osc::setup_rx_socket(global_config.port_rx.to_string()).await?;
 
-    // Timeout management
+    // Signing keys, timeouts, LED timeouts and motor governors of each device
     for device in devices.iter() {
-        let headpat_device_ip_clone = device.device_uri.clone();
-        task::spawn(async move {
-            if let Err(e) = osc_timeout(&headpat_device_ip_clone, timeout).await {
-                let error_message = format!("Timeout error for device {}: {}", headpat_device_ip_clone, e);
-                log_to_file(&error_message);
-            }
-        });
+        config_reload::start_device(device, timeout);
     }
+    config_reload::set_devices(devices.clone());
+
+    // Script hot reload, once per script file
+    config_reload::watch_scripts(&devices);
+
+    // Config hot reload, new device lists are picked up as soon as they arrive, between packets
+    let (update_sender, config_updates) = channel::unbounded();
+    task::spawn(config_reload::watch_config(global_config.clone(), devices.clone(), update_sender));
+
+    // Gesture log, devices with gestures can be added while running
+    let gesture_events = gestures::subscribe();
+    task::spawn(async move {
+        while let Ok(event) = gesture_events.recv().await {
+            match event.gesture {
+                gestures::Gesture::Pats => info!("Gesture {}: pats x{}", event.device, event.count),
+                _ => info!("Gesture {}: {}", event.device, event.gesture.name()),
+            }
+        }
+    });
+
+    // Session history
+    task::spawn(session_stats::history_worker());
 
-    log_to_file("Listening for OSC Packets...");
+    // Live dashboard
+    if show_dashboard {
+        dashboard::start();
+    }
 
-    // Listen for OSC Packets
-    while let Some(packet) = rx_socket.next().await {
+    info!("Listening for OSC Packets...");
+
+    // Listen for OSC Packets and reloaded config.yml files
+    let mut events = rx_socket.map(Event::Packet).merge(config_updates.map(Event::Update));
+    while let Some(event) = events.next().await {
+        let packet = match event {
+            Event::Packet(packet) => packet,
+            // Switch to the reloaded config.yml, the watcher stops removed devices once this is done
+            Event::Update(update) => {
+                devices = update.apply(devices);
+                continue;
+            }
+        };
         let (packet, _peer_addr) = packet?;
 
         // Filter OSC Signals
@@ -189,14 +349,13 @@ async fn run_giggletech() -> async_osc::Result<()> {
                 if address == "/avatar/change" {
                     // Check if the first OSC value is a string
                     if let Some(OscType::String(avatar_id)) = osc_value.first() {
-                        let log_message = format!("Avatar Changed: {}", avatar_id);
-                        log_to_file(&log_message);
+                        info!("Avatar Changed: {}", avatar_id);
                     }
                     continue; // Skip the rest of the loop as this is handled
                 }
 
                 // Handle other messages
-                let value = match osc_value.first().unwrap_or(&OscType::Nil).clone().float() {
+                let value = match osc_value.first().and_then(osc_value_as_f32) {
                     Some(v) => v,
                     None => continue,
                 };
@@ -206,16 +365,44 @@ async fn run_giggletech() -> async_osc::Result<()> {
                     if address == *device.max_speed_parameter {
                         data_processing::print_speed_limit(value);
                         device.max_speed = value.max(global_config.minimum_max_speed);
-                        //let log_message = format!("Updated max speed for device: {} to {}", device.device_uri, device.max_speed);
-                        //log_to_file(&log_message);
-                    } else if address == *device.proximity_parameter {
+                        dashboard::set_max_speed(device);
+                        //debug!("Updated max speed for device: {} to {}", device.device_uri, device.max_speed);
+                    } else if device.proximity_parameters.iter().any(|param| address == **param) {
+                        // Merge with the device's other proximity parameters
+                        let combined_value = input_combiner::combine_input(device, address, value);
+                        spatial::update_from_inputs(device);
                         handle_proximity_parameter::handle_proximity_parameter(
                             running.clone(), // Terminator
-                            value,
+                            combined_value,
                             device.clone()
                         ).await?;
-                        //let log_message = format!("Processed proximity parameter for device: {}", device.device_uri);
-                        //log_to_file(&log_message);
+                        //debug!("Processed proximity parameter for device: {}", device.device_uri);
+                    }
+
+                    // Response Curve Selection
+                    if matches!(&device.curve_parameter, Some(param) if address == **param) {
+                        response_curve::set_curve_index(device, value);
+                    }
+
+                    // Haptic Patterns
+                    for trigger in device.pattern_triggers.iter() {
+                        if address == *trigger.parameter {
+                            osc_timeout::record_signal(device);
+                            patterns::handle_trigger(device, trigger, value);
+                        }
+                    }
+
+                    // Spatial Panning
+                    spatial::handle_position_parameter(device, address, value);
+
+                    // LED Output
+                    if let Some(led) = &device.led {
+                        if matches!(&led.source, config::LedSource::Parameter(param) if address == **param) {
+                            led_output::handle_led_parameter(value, device).await?;
+                        }
+                        if matches!(&led.color_parameter, Some(param) if address == **param) {
+                            led_output::handle_led_color_parameter(value, device).await?;
+                        }
                     }
                 }
             }
@@ -225,24 +412,43 @@ async fn run_giggletech() -> async_osc::Result<()> {
     Ok(())
 }
 
+// What the main loop waits for
+enum Event {
+    Packet(async_osc::Result<(OscPacket, std::net::SocketAddr)>),
+    Update(config_reload::ConfigUpdate)
+}
+
+// Accept float, int and bool parameters from VRChat
+fn osc_value_as_f32(value: &OscType) -> Option<f32> {
+    match value {
+        OscType::Float(v) => Some(*v),
+        OscType::Double(v) => Some(*v as f32),
+        OscType::Int(v) => Some(*v as f32),
+        OscType::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
 // Simple ping test function that doesn't crash
 async fn ping_device(device_ip: &str) -> bool {
-    // Use simple ping (ICMP) test
+    // Use simple ping (ICMP) test, the options differ between Windows and Unix
+    let args: &[&str] = if cfg!(windows) { &["-n", "1", "-w", "1000"] } else { &["-c", "1", "-W", "1"] };
     match async_std::process::Command::new("ping")
-        .args(&["-n", "1", "-w", "1000", device_ip])
+        .args(args)
+        .arg(device_ip)
         .output()
         .await {
         Ok(output) => {
             let success = output.status.success();
             if success {
-                println!("    ✓ Ping successful for {}", device_ip);
+                info!("    ✓ Ping successful for {}", device_ip);
             } else {
-                println!("    ✗ Ping failed for {}", device_ip);
+                info!("    ✗ Ping failed for {}", device_ip);
             }
             success
         }
         Err(e) => {
-            println!("    ✗ Ping command failed: {}", e);
+            info!("    ✗ Ping command failed: {}", e);
             false
         }
     }
@@ -250,25 +456,22 @@ async fn ping_device(device_ip: &str) -> bool {
 
 // Test all devices and log results
 async fn test_device_connectivity(devices: &[crate::config::DeviceConfig]) {
-    println!("\n=== Testing Device Connectivity ===");
-    log_to_file("Starting device connectivity test...");
+    info!("=== Testing Device Connectivity ===");
     
     for (i, device) in devices.iter().enumerate() {
-        let device_ip = &device.device_uri;
-        
-        println!("  Testing Device {}: {}", i + 1, device_ip);
-        
-        // Test the device
-        let is_reachable = ping_device(device_ip).await;
-        
-        let status = if is_reachable { "ONLINE" } else { "OFFLINE" };
-        let message = format!("Device {}: {} - {}", i + 1, device_ip, status);
-        
-        println!("  Result: {}", message);
-        log_to_file(&message);
-        println!(); // Add blank line for readability
+        // Groups are tested member by member
+        for target in device.targets() {
+            let device_ip = &target.ip;
+
+            info!("  Testing Device {}: {}", i + 1, device_ip);
+
+            // Test the device
+            let is_reachable = ping_device(device_ip).await;
+
+            let status = if is_reachable { "ONLINE" } else { "OFFLINE" };
+            info!("  Result: Device {}: {} - {}", i + 1, device_ip, status);
+        }
     }
     
-    println!("=== Connectivity Test Complete ===\n");
-    log_to_file("Device connectivity test completed.");
+    info!("=== Connectivity Test Complete ===");
 }
@@ -0,0 +1,349 @@
+/*
+    motion_model.rs - Velocity Estimation for Velocity Control Mode
+
+    In velocity mode the motor responds to how fast a hand is moving rather than how close it is.
+    This module estimates that speed from the proximity signal and turns it into a motor intensity.
+
+    **Parameters:**
+
+    - `outer_proximity`: Start of the active zone. Movement is ignored until the contact is at least
+      this close (0.0 = anywhere in the collider).
+    - `inner_proximity`: End of the active zone. Closer than this the hand is treated as resting or
+      pressing, and movement no longer drives the motor.
+    - `velocity_scalar`: Sensitivity. At the default of 20, a hand moving one full proximity unit per
+      second drives the motor at `max_speed`. Doubling it halves the speed needed.
+    - `respond_to_retreat`: Also respond when the hand moves away, not only when it approaches.
+    - `velocity_smoothing`: Time constant (seconds) of the velocity filter. Higher is smoother but slower.
+    - `velocity_decay`: Half-life (seconds) of the velocity once motion stops, so the motor fades out
+      instead of holding its last level until the next packet.
+
+    **Key Features:**
+
+    1. **Filtered Velocity (`MotionState::update`)**:
+       - The finite difference between samples uses a time step clamped to `MIN_DELTA_T`, so packets
+         arriving back to back do not produce spikes. The result is smoothed with an exponential filter.
+       - After a gap longer than `MAX_DELTA_T` the estimate restarts instead of reading the gap as motion.
+
+    2. **Intensity (`velocity_intensity`)**:
+       - Maps the filtered velocity to 0.0 - 1.0 inside the active zone.
+
+    3. **Motor Speed (`motor_speed`)**:
+       - Maps intensity between `min_speed` and `max_speed`. No motion gives no output.
+
+    4. **Decay (`start_decay`)**:
+       - While the velocity is fading, a worker keeps re-sending the decayed output until it reaches zero
+         or the velocity has faded below `MIN_VELOCITY`. In hybrid mode the output settles on the
+         proximity baseline.
+
+    **Usage**:
+    - `update` is called for every proximity sample in velocity mode and returns the filtered velocity.
+    - `reset` is called when the contact ends.
+*/
+
+use async_std::task;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::DeviceConfig;
+use crate::data_processing;
+use crate::motor_output;
+
+// Shortest time step used for the finite difference
+const MIN_DELTA_T: f32 = 0.02;
+// Longest gap between samples that still counts as continuous motion
+const MAX_DELTA_T: f32 = 0.5;
+// Velocity scalar at which one proximity unit per second reaches full intensity
+const REFERENCE_VELOCITY_SCALAR: f32 = 20.0;
+// Interval of the decay worker
+const DECAY_INTERVAL: Duration = Duration::from_millis(50);
+// Velocity below which the decay is finished
+const MIN_VELOCITY: f32 = 0.01;
+
+lazy_static! {
+    static ref DEVICE_MOTION: Arc<Mutex<HashMap<String, MotionState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MotionParams {
+    pub outer_proximity: f32,
+    pub inner_proximity: f32,
+    pub velocity_scalar: f32,
+    pub respond_to_retreat: bool,
+    pub velocity_smoothing: f32,
+    pub velocity_decay: f32
+}
+
+impl MotionParams {
+    pub fn from_device(device: &DeviceConfig) -> MotionParams {
+        MotionParams {
+            outer_proximity: device.outer_proximity,
+            inner_proximity: device.inner_proximity,
+            velocity_scalar: device.velocity_scalar,
+            respond_to_retreat: device.respond_to_retreat,
+            velocity_smoothing: device.velocity_smoothing,
+            velocity_decay: device.velocity_decay
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MotionState {
+    last_proximity: Option<f32>,
+    last_time: Option<Instant>,
+    velocity: f32,
+    decaying: bool
+}
+
+impl MotionState {
+    // Velocity after decaying since the last sample
+    pub fn velocity_at(&self, now: Instant, params: &MotionParams) -> f32 {
+        let elapsed = match self.last_time {
+            Some(last_time) => now.saturating_duration_since(last_time).as_secs_f32(),
+            None => return 0.0,
+        };
+        if params.velocity_decay <= 0.0 {
+            return self.velocity;
+        }
+        self.velocity * 0.5f32.powf(elapsed / params.velocity_decay)
+    }
+
+    // Velocity while decaying, zero once it is too small to matter
+    pub fn decayed_velocity(&self, now: Instant, params: &MotionParams) -> f32 {
+        let velocity = self.velocity_at(now, params);
+        if velocity.abs() < MIN_VELOCITY { 0.0 } else { velocity }
+    }
+
+    pub fn update(&mut self, proximity: f32, now: Instant, params: &MotionParams) -> f32 {
+        if let (Some(last_proximity), Some(last_time)) = (self.last_proximity, self.last_time) {
+            let delta_t = now.saturating_duration_since(last_time).as_secs_f32();
+            if delta_t > MAX_DELTA_T {
+                self.velocity = 0.0;
+            } else {
+                let raw_velocity = (proximity - last_proximity) / delta_t.max(MIN_DELTA_T);
+                let velocity = self.velocity_at(now, params);
+                let alpha = if params.velocity_smoothing > 0.0 {
+                    delta_t / (params.velocity_smoothing + delta_t)
+                } else {
+                    1.0
+                };
+                self.velocity = velocity + alpha * (raw_velocity - velocity);
+            }
+        }
+        self.last_proximity = Some(proximity);
+        self.last_time = Some(now);
+        self.velocity
+    }
+
+    pub fn proximity(&self) -> f32 {
+        self.last_proximity.unwrap_or(0.0)
+    }
+}
+
+// Motor intensity (0.0 - 1.0) for a filtered velocity at the given proximity
+pub fn velocity_intensity(velocity: f32, proximity: f32, params: &MotionParams) -> f32 {
+    if proximity <= params.outer_proximity || proximity >= params.inner_proximity {
+        return 0.0;
+    }
+    let speed = if params.respond_to_retreat { velocity.abs() } else { velocity.max(0.0) };
+    (speed * params.velocity_scalar / REFERENCE_VELOCITY_SCALAR).clamp(0.0, 1.0)
+}
+
+// Motor speed between min and max speed, or 0.0 with no motion
+pub fn motor_speed(intensity: f32, min_speed: f32, max_speed: f32) -> f32 {
+    if intensity <= 0.0 {
+        return 0.0;
+    }
+    min_speed + (max_speed - min_speed) * intensity.min(1.0)
+}
+
+pub(crate) fn update(device: &DeviceConfig, proximity: f32, now: Instant) -> f32 {
+    let params = MotionParams::from_device(device);
+    match DEVICE_MOTION.lock() {
+        Ok(mut device_motion) => device_motion
+            .entry(device.device_uri.to_string())
+            .or_default()
+            .update(proximity, now, &params),
+        Err(_) => 0.0,
+    }
+}
+
+// Current filtered velocity of the device
+pub(crate) fn velocity(device: &DeviceConfig) -> f32 {
+    let params = MotionParams::from_device(device);
+    DEVICE_MOTION.lock().ok()
+        .and_then(|device_motion| device_motion.get(device.device_uri.as_str()).map(|state| state.velocity_at(Instant::now(), &params)))
+        .unwrap_or(0.0)
+}
+
+pub(crate) fn reset(device: &DeviceConfig) {
+    if let Ok(mut device_motion) = DEVICE_MOTION.lock() {
+        device_motion.remove(device.device_uri.as_str());
+    }
+}
+
+// Keep sending the decaying output until it reaches zero
+pub(crate) fn start_decay(device: &DeviceConfig) {
+    if device.velocity_decay <= 0.0 {
+        return;
+    }
+    if let Ok(mut device_motion) = DEVICE_MOTION.lock() {
+        match device_motion.get_mut(device.device_uri.as_str()) {
+            Some(state) if !state.decaying => state.decaying = true,
+            _ => return,
+        }
+    }
+
+    let device = device.clone();
+    task::spawn(async move {
+        let params = MotionParams::from_device(&device);
+        loop {
+            task::sleep(DECAY_INTERVAL).await;
+            let (velocity, proximity) = match DEVICE_MOTION.lock() {
+                Ok(device_motion) => match device_motion.get(device.device_uri.as_str()) {
+                    Some(state) => (state.decayed_velocity(Instant::now(), &params), state.proximity()),
+                    // Contact ended, the stop has already been sent
+                    None => return,
+                },
+                Err(_) => return,
+            };
+
+            let headpat_tx = data_processing::motion_tx(proximity, velocity, &device);
+            if let Err(e) = motor_output::send_motor(&device, headpat_tx).await {
+                warn!("Velocity decay: Failed to send to {}: {}", device.device_uri, e);
+            }
+            // A hand resting inside the collider keeps headpat_tx above zero, the decay still ends
+            if velocity == 0.0 || headpat_tx == 0.0 {
+                break;
+            }
+        }
+        if let Ok(mut device_motion) = DEVICE_MOTION.lock() {
+            if let Some(state) = device_motion.get_mut(device.device_uri.as_str()) {
+                state.decaying = false;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> MotionParams {
+        MotionParams {
+            outer_proximity: 0.0,
+            inner_proximity: 0.9,
+            velocity_scalar: 20.0,
+            respond_to_retreat: false,
+            velocity_smoothing: 0.0,
+            velocity_decay: 0.25
+        }
+    }
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn first_sample_has_no_velocity() {
+        let mut state = MotionState::default();
+        assert_eq!(state.update(0.5, Instant::now(), &params()), 0.0);
+    }
+
+    #[test]
+    fn approaching_motion_gives_positive_velocity() {
+        let start = Instant::now();
+        let mut state = MotionState::default();
+        state.update(0.2, start, &params());
+        let velocity = state.update(0.3, at(start, 100), &params());
+        assert!((velocity - 1.0).abs() < 1e-3, "velocity was {}", velocity);
+        assert!((velocity_intensity(velocity, 0.3, &params()) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn velocity_scalar_sets_sensitivity() {
+        let p = MotionParams { velocity_scalar: 10.0, ..params() };
+        assert!((velocity_intensity(1.0, 0.5, &p) - 0.5).abs() < 1e-6);
+        assert!((velocity_intensity(0.25, 0.5, &params()) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn retreating_motion_is_ignored_by_default() {
+        assert_eq!(velocity_intensity(-1.0, 0.5, &params()), 0.0);
+    }
+
+    #[test]
+    fn retreating_motion_responds_when_enabled() {
+        let p = MotionParams { respond_to_retreat: true, ..params() };
+        assert!((velocity_intensity(-0.5, 0.5, &p) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn no_output_outside_active_zone() {
+        let p = MotionParams { outer_proximity: 0.2, inner_proximity: 0.7, ..params() };
+        assert_eq!(velocity_intensity(1.0, 0.1, &p), 0.0);
+        assert_eq!(velocity_intensity(1.0, 0.8, &p), 0.0);
+        assert!(velocity_intensity(1.0, 0.5, &p) > 0.0);
+    }
+
+    #[test]
+    fn back_to_back_samples_do_not_spike() {
+        let start = Instant::now();
+        let mut state = MotionState::default();
+        state.update(0.2, start, &params());
+        // 1ms apart would be 10 units/s with a raw finite difference
+        let velocity = state.update(0.21, at(start, 1), &params());
+        assert!(velocity <= 0.01 / MIN_DELTA_T + 1e-3, "velocity was {}", velocity);
+    }
+
+    #[test]
+    fn smoothing_filters_velocity() {
+        let p = MotionParams { velocity_smoothing: 0.1, ..params() };
+        let start = Instant::now();
+        let mut state = MotionState::default();
+        state.update(0.2, start, &p);
+        let velocity = state.update(0.3, at(start, 100), &p);
+        // Half way to the raw velocity of 1.0 with dt equal to the time constant
+        assert!((velocity - 0.5).abs() < 1e-3, "velocity was {}", velocity);
+    }
+
+    #[test]
+    fn velocity_decays_when_motion_stops() {
+        let start = Instant::now();
+        let mut state = MotionState::default();
+        state.update(0.2, start, &params());
+        let velocity = state.update(0.3, at(start, 100), &params());
+        let decayed = state.velocity_at(at(start, 350), &params());
+        assert!((decayed - velocity / 2.0).abs() < 1e-3, "decayed was {}", decayed);
+    }
+
+    #[test]
+    fn decay_ends_while_contact_stays() {
+        let start = Instant::now();
+        let mut state = MotionState::default();
+        state.update(0.2, start, &params());
+        state.update(0.3, at(start, 100), &params());
+        let interval = DECAY_INTERVAL.as_millis() as u64;
+        let steps = (1..=100).find(|step| state.decayed_velocity(at(start, 100 + step * interval), &params()) == 0.0);
+        assert!(steps.is_some(), "decay did not end within 100 intervals");
+    }
+
+    #[test]
+    fn long_gap_restarts_estimate() {
+        let start = Instant::now();
+        let mut state = MotionState::default();
+        state.update(0.2, start, &params());
+        assert_eq!(state.update(0.6, at(start, 2000), &params()), 0.0);
+    }
+
+    #[test]
+    fn motor_speed_adds_min_speed() {
+        assert_eq!(motor_speed(0.0, 0.05, 0.25), 0.0);
+        assert!((motor_speed(0.5, 0.05, 0.25) - 0.15).abs() < 1e-6);
+        assert!((motor_speed(1.0, 0.05, 0.25) - 0.25).abs() < 1e-6);
+        assert!((motor_speed(2.0, 0.05, 0.25) - 0.25).abs() < 1e-6);
+    }
+}
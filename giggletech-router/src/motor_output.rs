@@ -0,0 +1,256 @@
+/*
+    motor_output.rs - Motor Output Stage for GiggleTech Devices
+
+    This module is the last step before motor values leave the router. Every processed motor value
+    goes through `send_motor`, which delivers it to the device (or to every member of a device group)
+    and keeps outputs that mirror the motor, such as the LED, in sync.
+
+    **Key Features:**
+
+    1. **Motor Output (`send_motor`)**:
+       - Entry point for live motor values. The value is shaped by the device's `envelope`. While a
+         haptic pattern is playing it is mixed with the pattern, or held back if the pattern overrides
+         live output (see `patterns`).
+
+    2. **Emit (`emit`)**:
+       - Passes the final motor Tx value through the slew limit, then sends it to a single device, or
+         fans it out to all group members. Values below the stall point are rendered as bursts in
+         `pulse` mode.
+       - Each group member applies its own gain, times its distance gain in a `spatial` layout, and
+         members with a delay are sent to from a separate task so the other members are not held up.
+         A delayed value is dropped if the member was stopped while it waited.
+       - The `governor` is the last gain stage, it limits the value of each motor after the gains.
+
+    3. **Mute and Stop-All (`set_muted`, `set_stop_all`)**:
+       - A muted device, or every device while stop-all is on, is sent `0` instead of its output.
+         Toggled from the `dashboard`.
+
+    4. **Stop Output (`send_stop`, `send_live_stop`)**:
+       - Sends the stop signal (`0`) to every device driven by the entry, ignoring delays so all
+         motors stop at the same time. Delayed values still waiting are cancelled.
+       - `send_live_stop` ends the live output when a contact ends. It is skipped while a pattern is
+         playing or a release is fading out, which keep control of the motor.
+       - `send_stop` is the forced stop of the OSC timeout, the dashboard and removed devices. It ends
+         any pattern, release, velocity decay or script tick and always sends `0`.
+
+    **Usage**:
+    - Use `send_motor` instead of calling `giggletech_osc::send_data` directly for motor values.
+    - A failure to reach one group member is logged and does not stop the other members.
+    - Motor values stay `f32` through every stage, each target encodes them in its `output_format`.
+*/
+
+use async_osc::Result;
+use async_std::task;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::{DeviceConfig, GroupMember};
+use crate::giggletech_osc;
+use crate::led_output;
+use crate::patterns;
+use crate::governor;
+use crate::envelope;
+use crate::motion_model;
+use crate::pulse;
+use crate::scripting;
+use crate::spatial;
+use crate::dashboard;
+use crate::session_stats;
+
+static STOP_ALL: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref MUTED_DEVICES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    // Bumped by every stop of a target, delayed values queued before it are stale
+    static ref TARGET_STOP_GENERATION: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn set_muted(device: &DeviceConfig, muted: bool) {
+    if let Ok(mut muted_devices) = MUTED_DEVICES.lock() {
+        if muted {
+            muted_devices.insert(device.device_uri.to_string());
+        } else {
+            muted_devices.remove(device.device_uri.as_str());
+        }
+    }
+}
+
+pub(crate) fn is_muted(device: &DeviceConfig) -> bool {
+    MUTED_DEVICES.lock().map(|muted_devices| muted_devices.contains(device.device_uri.as_str())).unwrap_or(false)
+}
+
+pub(crate) fn set_stop_all(stopped: bool) {
+    STOP_ALL.store(stopped, Ordering::SeqCst);
+}
+
+pub(crate) fn is_stop_all() -> bool {
+    STOP_ALL.load(Ordering::SeqCst)
+}
+
+fn stop_generation(target: &GroupMember) -> u64 {
+    TARGET_STOP_GENERATION.lock().ok().and_then(|generations| generations.get(target.ip.as_str()).copied()).unwrap_or(0)
+}
+
+fn bump_stop_generation(target: &GroupMember) {
+    if let Ok(mut generations) = TARGET_STOP_GENERATION.lock() {
+        *generations.entry(target.ip.to_string()).or_insert(0) += 1;
+    }
+}
+
+// Unmutes a removed device. The stop generations of its targets are kept, a delayed value still
+// waiting must not match a counter that started over.
+pub(crate) fn remove(device: &DeviceConfig) {
+    set_muted(device, false);
+}
+
+fn member_tx(device: &DeviceConfig, headpat_tx: f32, member: &GroupMember) -> f32 {
+    let gain = member.gain * spatial::member_gain(device, member);
+    (headpat_tx * gain).clamp(0.0, 255.0)
+}
+
+pub(crate) async fn send_motor(device: &DeviceConfig, headpat_tx: f32) -> Result<()> {
+    let headpat_tx = envelope::apply(device, headpat_tx);
+    send_enveloped(device, headpat_tx).await
+}
+
+// Live output after the envelope, mixed with any playing pattern
+pub(crate) async fn send_enveloped(device: &DeviceConfig, headpat_tx: f32) -> Result<()> {
+    match patterns::mix_live(device, headpat_tx) {
+        Some(headpat_tx) => emit(device, headpat_tx).await,
+        None => Ok(()),
+    }
+}
+
+pub(crate) async fn emit(device: &DeviceConfig, headpat_tx: f32) -> Result<()> {
+    let headpat_tx = envelope::slew(device, headpat_tx);
+    send_output(device, headpat_tx).await
+}
+
+// Sends a motor value, rendered as pulses below the stall point
+pub(crate) async fn send_output(device: &DeviceConfig, headpat_tx: f32) -> Result<()> {
+    if let Some(tx) = pulse::render(device, headpat_tx) {
+        send_targets(device, tx).await?;
+    }
+    led_output::follow_motor(device, headpat_tx).await
+}
+
+// Sends a motor value to the device or group members, each governed on its own
+pub(crate) async fn send_targets(device: &DeviceConfig, headpat_tx: f32) -> Result<()> {
+    let headpat_tx = if is_stop_all() || is_muted(device) { 0.0 } else { headpat_tx };
+    session_stats::record_output(device, headpat_tx);
+    if device.group_members.is_empty() {
+        let target = &device.targets()[0];
+        let tx = governor::govern(device, target, headpat_tx);
+        dashboard::record_output(device, tx);
+        send_target(target, tx).await?;
+    } else {
+        // A group shows its level before the member gains
+        dashboard::record_output(device, headpat_tx);
+        for member in device.group_members.iter() {
+            let tx = governor::govern(device, member, member_tx(device, headpat_tx, member));
+            if member.delay.is_zero() {
+                if let Err(e) = send_target(member, tx).await {
+                    warn!("Group {}: Failed to send to {}: {}", device.device_uri, member.ip, e);
+                }
+            } else {
+                let generation = stop_generation(member);
+                task::spawn(send_delayed(device.device_uri.to_string(), member.clone(), tx, generation));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sends a member's value after its delay, unless the member was stopped in the meantime
+async fn send_delayed(group: String, member: GroupMember, headpat_tx: f32, generation: u64) -> bool {
+    task::sleep(member.delay).await;
+    if stop_generation(&member) != generation {
+        return false;
+    }
+    if let Err(e) = send_target(&member, headpat_tx).await {
+        warn!("Group {}: Failed to send to {}: {}", group, member.ip, e);
+    }
+    true
+}
+
+// Sends a governed value to one motor
+pub(crate) async fn send_target(target: &GroupMember, headpat_tx: f32) -> Result<()> {
+    giggletech_osc::send_data(&target.ip, headpat_tx, target.output_format).await
+}
+
+// A contact ended, a playing pattern or a release fading out keeps control of the motor
+pub(crate) async fn send_live_stop(device: &DeviceConfig) -> Result<()> {
+    if envelope::is_releasing(device) {
+        return Ok(());
+    }
+    // Record the live stop for the pattern to mix with
+    patterns::mix_live(device, 0.0);
+    if patterns::is_playing(device) {
+        return Ok(());
+    }
+    send_zero(device).await
+}
+
+// Forced stop, ends any pattern, release, velocity decay or script tick
+pub(crate) async fn send_stop(device: &DeviceConfig) -> Result<()> {
+    patterns::stop_all(device);
+    envelope::stop(device);
+    motion_model::reset(device);
+    scripting::reset(device);
+    send_zero(device).await
+}
+
+async fn send_zero(device: &DeviceConfig) -> Result<()> {
+    envelope::reset_output(device);
+    for target in device.targets() {
+        bump_stop_generation(&target);
+        governor::govern(device, &target, 0.0);
+    }
+    pulse::render(device, 0.0);
+    dashboard::record_output(device, 0.0);
+    session_stats::record_output(device, 0.0);
+    if device.group_members.is_empty() {
+        return giggletech_osc::send_data(&device.device_uri, 0.0, device.output_format).await;
+    }
+    for member in device.group_members.iter() {
+        if let Err(e) = giggletech_osc::send_data(&member.ip, 0.0, member.output_format).await {
+            warn!("Group {}: Failed to stop {}: {}", device.device_uri, member.ip, e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use std::time::Duration;
+
+    fn group() -> DeviceConfig {
+        config::test_devices(
+            "setup:\n  port_rx: 9001\ndevices: []\ngroups:\n  - name: wave\n    proximity_parameter: wave\n    members:\n      - ip: 127.0.0.43\n      - ip: 127.0.0.44\n        delay_ms: 200\n"
+        ).remove(0)
+    }
+
+    #[test]
+    fn delayed_send_waits_for_the_member_delay() {
+        let device = group();
+        let member = device.group_members[1].clone();
+        assert_eq!(member.delay, Duration::from_millis(200));
+        let generation = stop_generation(&member);
+        assert!(task::block_on(send_delayed(device.device_uri.to_string(), member, 100.0, generation)));
+    }
+
+    #[test]
+    fn stop_cancels_a_pending_delayed_send() {
+        let device = group();
+        let member = device.group_members[1].clone();
+        let generation = stop_generation(&member);
+        let pending = task::spawn(send_delayed(device.device_uri.to_string(), member, 100.0, generation));
+        task::block_on(send_stop(&device)).unwrap();
+        assert!(!task::block_on(pending));
+    }
+}
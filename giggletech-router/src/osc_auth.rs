@@ -0,0 +1,209 @@
+/*
+    osc_auth.rs - Signed OSC Commands for GiggleTech Devices
+
+    Devices accept commands from anyone on the LAN. This module adds an optional shared-secret mode:
+    when a device has an `hmac_key` configured, every message sent to it is signed so the firmware
+    can reject unsigned, forged or replayed packets.
+
+    **Signed Message Format:**
+
+    A signed message carries two extra arguments after the normal ones:
+
+        /motor  <value: i32 | f32>  <counter: i64>  <hmac: blob>
+
+    - `value` is the motor value in the device's `output_format`: an `i32` from 0 to 255 (`int8`) or
+      0 to 65535 (`int16`), or an `f32` from 0.0 to 1.0 (`float`). The signature covers it either way.
+    - `counter` is the current time in microseconds, or one more than the previous counter if that
+      is larger. Every process signing for the device (the router, `stop-all`, `test-device`) follows
+      the same clock, so the counters keep increasing across router restarts and when a one-shot
+      command is run next to a running router. Messages to a device are signed and sent one at a
+      time, so they leave the router in counter order.
+    - `hmac` is HMAC-SHA256 (32 bytes) over the OSC encoding of the message *without* the blob,
+      i.e. the address, the original arguments and the counter.
+
+    **Verifying (firmware / simulator side):**
+
+    1. Drop the last argument (the blob) and re-encode the remaining message.
+    2. Compute HMAC-SHA256 of the encoding with the shared key and compare it with the blob in
+       constant time. Reject the packet if it is missing or does not match.
+    3. Reject the packet if `counter` is not greater than the last accepted counter, then store it.
+
+    See `examples/signed_device_simulator/verifier.rs` for a reference verifier.
+
+    **Key Features:**
+
+    1. **Key Registry (`register_key`, `remove_key`)**:
+       - Stores the shared key and message counter for each device IP. Keys change when config.yml
+         is reloaded.
+
+    2. **Signing (`sign_message`)**:
+       - Used by `giggletech_osc` when sending, so callers of `send_data` get the signed form
+         transparently. Messages to devices without a key are left untouched. If the keys can't be
+         read the message is not sent, rather than sent unsigned.
+
+    3. **Send Order (`send_lock`)**:
+       - Member delays, pulses, patterns and stops send to a device from separate tasks. Holding the
+         device's send lock while signing and sending keeps a later counter from overtaking an earlier
+         one, which the device would reject as a replay.
+*/
+
+use async_osc::{OscMessage, OscPacket, OscType};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use async_std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::warn;
+
+type HmacSha256 = Hmac<Sha256>;
+
+struct DeviceKey {
+    key: Vec<u8>,
+    counter: AtomicU64,
+    send_lock: Arc<Mutex<()>>,
+}
+
+lazy_static! {
+    static ref DEVICE_KEYS: Arc<RwLock<HashMap<String, DeviceKey>>> =
+        Arc::new(RwLock::new(HashMap::new()));
+}
+
+fn clock_counter() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+// Follows the clock, a counter counting messages would fall behind the counters of other processes
+fn next_counter(counter: &AtomicU64) -> u64 {
+    let now = clock_counter();
+    let next = |last: u64| last.max(now.saturating_sub(1)) + 1;
+    match counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last))) {
+        Ok(last) | Err(last) => next(last),
+    }
+}
+
+pub(crate) fn register_key(device_ip: &str, key: &str) {
+    match DEVICE_KEYS.write() {
+        Ok(mut device_keys) => {
+            device_keys.insert(device_ip.to_string(), DeviceKey {
+                key: key.as_bytes().to_vec(),
+                counter: AtomicU64::new(0),
+                send_lock: Arc::new(Mutex::new(())),
+            });
+        }
+        Err(_) => warn!("Failed to register signing key for device {}", device_ip),
+    }
+}
+
+pub(crate) fn remove_key(device_ip: &str) {
+    if let Ok(mut device_keys) = DEVICE_KEYS.write() {
+        device_keys.remove(device_ip);
+    }
+}
+
+// Held while signing and sending to a device with a key, `None` for devices without one
+pub(crate) fn send_lock(device_ip: &str) -> Option<Arc<Mutex<()>>> {
+    DEVICE_KEYS.read().ok()
+        .and_then(|device_keys| device_keys.get(device_ip).map(|device_key| device_key.send_lock.clone()))
+}
+
+pub(crate) fn sign_message(device_ip: &str, mut message: OscMessage) -> async_osc::Result<OscMessage> {
+    let device_keys = match DEVICE_KEYS.read() {
+        Ok(device_keys) => device_keys,
+        Err(_) => return Err(async_osc::Error::Io(std::io::Error::other(
+            format!("Signing keys unavailable, not sending to {}", device_ip)
+        ))),
+    };
+    let device_key = match device_keys.get(device_ip) {
+        Some(device_key) => device_key,
+        None => return Ok(message),
+    };
+
+    let counter = next_counter(&device_key.counter);
+    message.args.push(OscType::Long(counter as i64));
+
+    let encoded = rosc::encoder::encode(&OscPacket::Message(message.clone()))?;
+    let mut mac = HmacSha256::new_from_slice(&device_key.key)
+        .expect("HMAC accepts keys of any length");
+    mac.update(&encoded);
+    message.args.push(OscType::Blob(mac.finalize().into_bytes().to_vec()));
+
+    Ok(message)
+}
+
+// The reference verifier of the example, so the tests check what firmware authors check against
+#[cfg(test)]
+#[path = "../examples/signed_device_simulator/verifier.rs"]
+mod verifier;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::verifier::{Rejection, Verifier};
+
+    fn motor(value: i32) -> OscMessage {
+        OscMessage { addr: "/motor".to_string(), args: vec![OscType::Int(value)] }
+    }
+
+    #[test]
+    fn signed_messages_pass_the_reference_verifier() {
+        register_key("10.0.29.1", "secret");
+        let mut verifier = Verifier::new("secret");
+        for value in [128, 0] {
+            let signed = sign_message("10.0.29.1", motor(value)).unwrap();
+            assert!(matches!(verifier.verify(&signed), Ok(args) if args == vec![OscType::Int(value)]));
+        }
+    }
+
+    #[test]
+    fn every_output_format_is_signed() {
+        register_key("10.0.29.5", "secret");
+        let mut verifier = Verifier::new("secret");
+        for value in [OscType::Int(200), OscType::Int(51400), OscType::Float(0.78)] {
+            let message = OscMessage { addr: "/motor".to_string(), args: vec![value.clone()] };
+            let signed = sign_message("10.0.29.5", message).unwrap();
+            assert!(matches!(verifier.verify(&signed), Ok(args) if args == vec![value]));
+        }
+    }
+
+    #[test]
+    fn forged_replayed_and_unsigned_messages_are_rejected() {
+        register_key("10.0.29.2", "secret");
+        let mut verifier = Verifier::new("secret");
+        let first = sign_message("10.0.29.2", motor(10)).unwrap();
+        let second = sign_message("10.0.29.2", motor(20)).unwrap();
+
+        let mut forged = second.clone();
+        forged.args[0] = OscType::Int(255);
+        assert!(matches!(verifier.verify(&forged), Err(Rejection::BadSignature)));
+        assert!(verifier.verify(&second).is_ok());
+        // Sent out of order, the earlier counter is a replay
+        assert!(matches!(verifier.verify(&first), Err(Rejection::Replayed { .. })));
+        assert!(matches!(verifier.verify(&motor(20)), Err(Rejection::Unsigned)));
+    }
+
+    #[test]
+    fn router_counter_stays_ahead_of_a_one_shot_command() {
+        let router = AtomicU64::new(0);
+        for _ in 0..1000 {
+            next_counter(&router);
+        }
+        // `stop-all` signs with its own counter
+        let stop_all = next_counter(&AtomicU64::new(0));
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(next_counter(&router) > stop_all);
+    }
+
+    #[test]
+    fn devices_without_a_key_are_not_signed() {
+        assert_eq!(sign_message("10.0.29.3", motor(5)).unwrap(), motor(5));
+        assert!(send_lock("10.0.29.3").is_none());
+        register_key("10.0.29.4", "secret");
+        assert!(send_lock("10.0.29.4").is_some());
+    }
+}
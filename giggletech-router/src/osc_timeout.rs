@@ -3,7 +3,7 @@
 
     This module is responsible for managing timeouts for devices communicating over OSC (Open Sound Control).
     It monitors how long it's been since each device has sent a signal and, if the device exceeds the specified timeout,
-    it sends a stop signal to the device.
+    it sends a stop signal to the device (or to every member of a device group).
 
     **Key Features:**
 
@@ -14,7 +14,12 @@
     2. **Timeout Loop (`osc_timeout`)**:
        - Runs an asynchronous loop that periodically checks how long it's been since a device last sent a signal.
        - If the time elapsed exceeds the specified timeout duration, the module sends a stop signal (`0`) to the device via OSC.
+       - The stop is forced, it also ends a haptic pattern that is still playing. Pattern triggers
+         count as signals (`record_signal`), so a looping pattern plays until the trigger goes off or
+         no parameter was received for the timeout.
        - Resets the last signal time to prevent repeated stops during the timeout period.
+       - Marks the contact as ended in `contact_gate`, so the next contact starts fresh. A contact
+         that was still on is counted as a timeout in `session_stats`.
 
     **Usage**:
     - The function `osc_timeout` is typically called for each device in the system, running concurrently to monitor signal activity.
@@ -28,14 +33,34 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use anyhow::Result;
 use lazy_static::lazy_static;
-use crate::giggletech_osc;
+use log::warn;
+use crate::config::DeviceConfig;
+use crate::motor_output;
+use crate::contact_gate;
+use crate::session_stats;
 
 lazy_static! {
     pub static ref DEVICE_LAST_SIGNAL_TIME: Arc<Mutex<HashMap<String, Instant>>> =
         Arc::new(Mutex::new(HashMap::new()));
 }
 
-pub async fn osc_timeout(device_ip: &str, timeout: u64) -> Result<()> {
+// Every proximity sample and pattern trigger counts as a signal, so the timeout leaves the pattern
+// a trigger started playing
+pub(crate) fn record_signal(device: &DeviceConfig) {
+    if let Ok(mut device_last_signal_times) = DEVICE_LAST_SIGNAL_TIME.lock() {
+        device_last_signal_times.insert(device.device_uri.to_string(), Instant::now());
+    }
+}
+
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_last_signal_times) = DEVICE_LAST_SIGNAL_TIME.lock() {
+        device_last_signal_times.remove(device.device_uri.as_str());
+    }
+}
+
+pub(crate) async fn osc_timeout(device: &DeviceConfig, timeout: u64) -> Result<()> {
+    let device_ip = device.device_uri.as_str();
+
     loop {
         async_std::task::sleep(Duration::from_secs(1)).await;
         
@@ -48,19 +73,22 @@ pub async fn osc_timeout(device_ip: &str, timeout: u64) -> Result<()> {
                 elapsed
             }
             Err(_) => {
-                eprintln!("Warning: Mutex poisoned for device {}, skipping timeout check", device_ip);
+                warn!("Mutex poisoned for device {}, skipping timeout check", device_ip);
                 continue;
             }
         };
         
         if elapsed_time >= Duration::from_secs(timeout) {
-            match giggletech_osc::send_data(device_ip, 0i32).await {
+            if contact_gate::force_off(device) {
+                session_stats::record_timeout(device);
+            }
+            match motor_output::send_stop(device).await {
                 Ok(_) => {
                     // Successfully sent timeout signal
                 }
                 Err(e) => {
                     // Log the error but don't panic - just continue monitoring
-                    eprintln!("Timeout: Failed to send stop signal to {}: {}", device_ip, e);
+                    warn!("Timeout: Failed to send stop signal to {}: {}", device_ip, e);
                 }
             }
             
@@ -68,7 +96,7 @@ pub async fn osc_timeout(device_ip: &str, timeout: u64) -> Result<()> {
             if let Ok(mut device_last_signal_times) = DEVICE_LAST_SIGNAL_TIME.lock() {
                 device_last_signal_times.insert(device_ip.to_string(), Instant::now());
             } else {
-                eprintln!("Warning: Failed to update signal time for device {}", device_ip);
+                warn!("Failed to update signal time for device {}", device_ip);
             }
         }
     }
@@ -0,0 +1,163 @@
+/*
+    paths.rs - Locations of config.yml, the Log Files and the Session History
+
+    The router can be started from any folder. The config file is picked once at startup and every
+    other relative path is taken relative to the folder of that file.
+
+    **Key Features:**
+
+    1. **Config File (`Locations::find`)**:
+       - `--config <file>` first, then `config.yml` in the current folder. On Linux, when neither is
+         given, `$XDG_CONFIG_HOME/giggletech/config.yml` (usually `~/.config/giggletech/config.yml`).
+
+    2. **Relative Paths (`resolve`)**:
+       - `pattern_dir`, `script` and `log_dir` in config.yml are relative to the folder of config.yml,
+         so they work the same whatever the current folder is.
+
+    3. **Logs and History (`log_dir`, `history_dir`)**:
+       - `--log-dir <folder>` takes precedence over `log_dir` in config.yml. Without either, logs and
+         the `history` folder are next to config.yml. A config from the XDG config folder keeps its
+         logs in `$XDG_STATE_HOME/giggletech` and its history in `$XDG_DATA_HOME/giggletech/history`.
+*/
+
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+
+const CONFIG_NAME: &str = "config.yml";
+// Folder name inside the XDG folders
+const APP_DIR: &str = "giggletech";
+const HISTORY_DIR: &str = "history";
+
+lazy_static! {
+    static ref LOCATIONS: RwLock<Locations> = RwLock::new(Locations::default());
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Locations {
+    pub config: PathBuf,
+    // `--log-dir`, takes precedence over `log_dir` in config.yml
+    pub log_dir: Option<PathBuf>,
+    // XDG state and data folders, only used for a config from the XDG config folder
+    pub xdg: Option<(PathBuf, PathBuf)>
+}
+
+impl Default for Locations {
+    fn default() -> Self {
+        Locations { config: PathBuf::from(CONFIG_NAME), log_dir: None, xdg: None }
+    }
+}
+
+impl Locations {
+    // Picks the config file from the command line, the current folder or the XDG config folder
+    pub fn find(config: Option<PathBuf>, log_dir: Option<PathBuf>) -> Locations {
+        let xdg_config = if cfg!(target_os = "linux") {
+            dirs::config_dir().map(|dir| dir.join(APP_DIR).join(CONFIG_NAME))
+        } else {
+            None
+        };
+        let xdg_dirs = match (dirs::state_dir(), dirs::data_dir()) {
+            (Some(state), Some(data)) => Some((state.join(APP_DIR), data.join(APP_DIR))),
+            _ => None,
+        };
+        Locations::choose(config, log_dir, Path::new(CONFIG_NAME).exists(), xdg_config, xdg_dirs)
+    }
+
+    fn choose(config: Option<PathBuf>, log_dir: Option<PathBuf>, local_exists: bool, xdg_config: Option<PathBuf>, xdg_dirs: Option<(PathBuf, PathBuf)>) -> Locations {
+        match (config, xdg_config) {
+            (Some(config), _) => Locations { config, log_dir, xdg: None },
+            (None, Some(xdg_config)) if !local_exists => Locations { config: xdg_config, log_dir, xdg: xdg_dirs },
+            _ => Locations { log_dir, ..Locations::default() },
+        }
+    }
+
+    // Folder of the config file, empty for a file in the current folder
+    fn base_dir(&self) -> &Path {
+        self.config.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.base_dir().join(path)
+    }
+
+    fn log_dir(&self, configured: Option<&str>) -> PathBuf {
+        match (&self.log_dir, configured, &self.xdg) {
+            (Some(dir), _, _) => dir.clone(),
+            (None, Some(dir), _) => self.resolve(dir),
+            (None, None, Some((state, _))) => state.clone(),
+            (None, None, None) => self.resolve("."),
+        }
+    }
+
+    fn history_dir(&self) -> PathBuf {
+        match &self.xdg {
+            Some((_, data)) => data.join(HISTORY_DIR),
+            None => self.resolve(HISTORY_DIR),
+        }
+    }
+}
+
+fn locations() -> Locations {
+    LOCATIONS.read().map(|locations| locations.clone()).unwrap_or_default()
+}
+
+// Set once at startup, before the config is read
+pub(crate) fn init(locations: Locations) {
+    if let Ok(mut current) = LOCATIONS.write() {
+        *current = locations;
+    }
+}
+
+pub(crate) fn config_path() -> PathBuf {
+    locations().config
+}
+
+// A path from config.yml, relative to the folder of config.yml
+pub(crate) fn resolve(path: &str) -> String {
+    locations().resolve(path).to_string_lossy().to_string()
+}
+
+// Folder of the log files, `configured` is `log_dir` from config.yml
+pub(crate) fn log_dir(configured: Option<&str>) -> PathBuf {
+    locations().log_dir(configured)
+}
+
+pub(crate) fn history_dir() -> PathBuf {
+    locations().history_dir()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xdg_dirs() -> Option<(PathBuf, PathBuf)> {
+        Some((PathBuf::from("/state/giggletech"), PathBuf::from("/data/giggletech")))
+    }
+
+    #[test]
+    fn config_in_the_current_folder_keeps_relative_paths() {
+        let locations = Locations::choose(None, None, true, Some(PathBuf::from("/xdg/giggletech/config.yml")), xdg_dirs());
+        assert_eq!(locations.config, PathBuf::from("config.yml"));
+        assert_eq!(locations.resolve("scripts/a.rhai"), PathBuf::from("scripts/a.rhai"));
+        assert_eq!(locations.log_dir(None), PathBuf::from("."));
+        assert_eq!(locations.history_dir(), PathBuf::from("history"));
+    }
+
+    #[test]
+    fn xdg_config_is_used_without_a_local_config() {
+        let locations = Locations::choose(None, None, false, Some(PathBuf::from("/xdg/giggletech/config.yml")), xdg_dirs());
+        assert_eq!(locations.config, PathBuf::from("/xdg/giggletech/config.yml"));
+        assert_eq!(locations.resolve("patterns"), PathBuf::from("/xdg/giggletech/patterns"));
+        assert_eq!(locations.log_dir(None), PathBuf::from("/state/giggletech"));
+        assert_eq!(locations.log_dir(Some("logs")), PathBuf::from("/xdg/giggletech/logs"));
+        assert_eq!(locations.history_dir(), PathBuf::from("/data/giggletech/history"));
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let locations = Locations::choose(Some(PathBuf::from("/etc/router/config.yml")), Some(PathBuf::from("/var/log/router")), true, None, None);
+        assert_eq!(locations.config, PathBuf::from("/etc/router/config.yml"));
+        assert_eq!(locations.log_dir(Some("logs")), PathBuf::from("/var/log/router"));
+        assert_eq!(locations.history_dir(), PathBuf::from("/etc/router/history"));
+    }
+}
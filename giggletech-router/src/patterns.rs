@@ -0,0 +1,459 @@
+/*
+    patterns.rs - Haptic Pattern Engine for GiggleTech Devices
+
+    Live proximity can only produce a steady motor level. This module plays timed haptic patterns
+    (a boop, a heartbeat, a purr) on a device when a configured OSC parameter is triggered, either
+    mixed with the live proximity output or overriding it.
+
+    **Pattern File Format (YAML):**
+
+    ```yaml
+    name: double_tap
+    repeat: 2            # Number of times to play (default 1)
+    loop: false          # Loop until the trigger is released (overrides repeat)
+    keyframes:
+      - { time: 0, intensity: 0.0 }
+      - { time: 40, intensity: 1.0, easing: ease_out }
+      - { time: 200, intensity: 0.0, easing: ease_in }
+      - { time: 350, intensity: 0.0 }
+    ```
+
+    - `time` is in milliseconds from the start of the pattern and must increase.
+    - `intensity` is 0.0 - 1.0 of the device's current max speed.
+    - `easing` shapes the transition *into* the keyframe: `linear` (default), `step`, `ease_in`,
+      `ease_out` or `ease_in_out`.
+
+    **Key Features:**
+
+    1. **Built-in Effects (`builtin`)**:
+       - `pulse`, `heartbeat`, `purr` (looping) and `boop` (one shot).
+
+    2. **Triggers (`handle_trigger`)**:
+       - A pattern starts when its trigger parameter goes from off to on (e.g. a bool going true).
+         Looping patterns stop when the parameter goes off again.
+       - A forced stop (OSC timeout, stop-all, mute, a removed device) ends any pattern (`stop_all`).
+
+    3. **Mixing (`PatternMode`)**:
+       - `mix`: The stronger of the pattern and the live proximity output is sent.
+       - `override`: The pattern replaces the live output until it ends.
+
+    **Usage**:
+    - Configure `patterns` on a device with a `parameter`, `pattern` (built-in name or file) and `mode`.
+    - `motor_output` consults `mix_live` so live output and patterns never fight over the motor.
+*/
+
+use async_std::task;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use crate::config::DeviceConfig;
+use crate::data_processing;
+use crate::motor_output;
+
+// Interval between pattern frames sent to the device
+const FRAME_INTERVAL: Duration = Duration::from_millis(30);
+
+static NEXT_PLAYBACK_ID: AtomicU64 = AtomicU64::new(1);
+
+lazy_static! {
+    static ref DEVICE_OUTPUT: Arc<Mutex<HashMap<String, DeviceOutput>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    static ref TRIGGER_STATE: Arc<Mutex<HashMap<(String, String), bool>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Easing {
+    #[default]
+    Linear,
+    Step,
+    EaseIn,
+    EaseOut,
+    EaseInOut
+}
+
+impl Easing {
+    fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::Step => if t >= 1.0 { 1.0 } else { 0.0 },
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct Keyframe {
+    pub time: u64,
+    pub intensity: f32,
+    #[serde(default)]
+    pub easing: Easing
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct Pattern {
+    pub name: String,
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    #[serde(default, rename = "loop")]
+    pub looping: bool,
+    pub keyframes: Vec<Keyframe>
+}
+
+impl Pattern {
+    fn new(name: &str, looping: bool, keyframes: &[(u64, f32, Easing)]) -> Pattern {
+        Pattern {
+            name: name.to_string(),
+            repeat: 1,
+            looping,
+            keyframes: keyframes.iter().map(|(time, intensity, easing)| Keyframe {
+                time: *time,
+                intensity: *intensity,
+                easing: *easing
+            }).collect()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.keyframes.len() < 2 {
+            return Err(format!("Pattern '{}' needs at least 2 keyframes", self.name));
+        }
+        if self.keyframes.windows(2).any(|pair| pair[1].time <= pair[0].time) {
+            return Err(format!("Keyframe times in pattern '{}' must increase", self.name));
+        }
+        if self.keyframes.iter().any(|keyframe| !(0.0..=1.0).contains(&keyframe.intensity)) {
+            return Err(format!("Keyframe intensities in pattern '{}' must be between 0 and 1", self.name));
+        }
+        Ok(())
+    }
+
+    fn duration(&self) -> u64 {
+        self.keyframes.last().map(|keyframe| keyframe.time).unwrap_or(0)
+    }
+
+    // Intensity at a point in the playback, or None once the pattern has finished
+    pub fn intensity_at(&self, elapsed: Duration) -> Option<f32> {
+        let duration = self.duration();
+        if duration == 0 {
+            return None;
+        }
+        let elapsed_ms = elapsed.as_millis() as u64;
+        if !self.looping && elapsed_ms >= duration * self.repeat as u64 {
+            return None;
+        }
+        let t = elapsed_ms % duration;
+
+        let first = &self.keyframes[0];
+        if t < first.time {
+            return Some(first.intensity);
+        }
+        for pair in self.keyframes.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            if t >= from.time && t < to.time {
+                let progress = (t - from.time) as f32 / (to.time - from.time) as f32;
+                return Some(from.intensity + (to.intensity - from.intensity) * to.easing.apply(progress));
+            }
+        }
+        self.keyframes.last().map(|keyframe| keyframe.intensity)
+    }
+}
+
+pub(crate) fn builtin(name: &str) -> Option<Pattern> {
+    use Easing::*;
+    match name {
+        "pulse" => Some(Pattern::new("pulse", true, &[
+            (0, 0.0, Linear), (150, 1.0, EaseOut), (300, 1.0, Linear), (500, 0.0, EaseIn), (800, 0.0, Linear)
+        ])),
+        "heartbeat" => Some(Pattern::new("heartbeat", true, &[
+            (0, 0.0, Linear), (80, 1.0, EaseOut), (160, 0.2, EaseIn), (240, 0.7, EaseOut), (400, 0.0, EaseIn), (1000, 0.0, Linear)
+        ])),
+        "purr" => Some(Pattern::new("purr", true, &[
+            (0, 0.35, Linear), (60, 0.6, EaseInOut), (120, 0.35, EaseInOut)
+        ])),
+        "boop" => Some(Pattern::new("boop", false, &[
+            (0, 0.0, Linear), (30, 1.0, EaseOut), (150, 0.0, EaseIn)
+        ])),
+        _ => None,
+    }
+}
+
+// Resolve a built-in pattern name or load a pattern file from the pattern directory
+pub(crate) fn load_pattern(name: &str, pattern_dir: &Path) -> Result<Pattern, String> {
+    if let Some(pattern) = builtin(name) {
+        return Ok(pattern);
+    }
+    let path = if name.ends_with(".yml") || name.ends_with(".yaml") {
+        pattern_dir.join(name)
+    } else {
+        pattern_dir.join(format!("{}.yml", name))
+    };
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Unknown pattern '{}' (failed to read {}: {})", name, path.display(), e))?;
+    let pattern: Pattern = serde_yaml::from_str(&contents)
+        .map_err(|e| format!("Invalid pattern file {}: {}", path.display(), e))?;
+    pattern.validate()?;
+    Ok(pattern)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PatternMode {
+    Mix,
+    Override
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PatternTrigger {
+    pub parameter: Arc<String>,
+    pub pattern: Arc<Pattern>,
+    pub mode: PatternMode
+}
+
+#[derive(Clone, Debug)]
+struct Playback {
+    id: u64,
+    pattern: Arc<Pattern>,
+    mode: PatternMode,
+    started: Instant,
+    pattern_tx: f32
+}
+
+#[derive(Clone, Debug, Default)]
+struct DeviceOutput {
+    // Latest live proximity output, mixed in by the player
+    live_tx: f32,
+    playback: Option<Playback>
+}
+
+enum Frame {
+    Send(f32),
+    Finished(f32),
+    Stopped
+}
+
+pub(crate) fn is_playing(device: &DeviceConfig) -> bool {
+    DEVICE_OUTPUT.lock().ok()
+        .and_then(|device_output| device_output.get(device.device_uri.as_str()).map(|output| output.playback.is_some()))
+        .unwrap_or(false)
+}
+
+// Record the live output and return what should be sent now, or None while a pattern overrides it
+pub(crate) fn mix_live(device: &DeviceConfig, headpat_tx: f32) -> Option<f32> {
+    let mut device_output = match DEVICE_OUTPUT.lock() {
+        Ok(device_output) => device_output,
+        Err(_) => return Some(headpat_tx),
+    };
+    let output = device_output.entry(device.device_uri.to_string()).or_default();
+    output.live_tx = headpat_tx;
+    match &output.playback {
+        Some(playback) if playback.mode == PatternMode::Override => None,
+        Some(playback) => Some(headpat_tx.max(playback.pattern_tx)),
+        None => Some(headpat_tx),
+    }
+}
+
+pub(crate) fn play(device: &DeviceConfig, pattern: Arc<Pattern>, mode: PatternMode) {
+    let id = NEXT_PLAYBACK_ID.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut device_output) = DEVICE_OUTPUT.lock() {
+        device_output.entry(device.device_uri.to_string()).or_default().playback = Some(Playback {
+            id,
+            pattern: pattern.clone(),
+            mode,
+            started: Instant::now(),
+            pattern_tx: 0.0
+        });
+    }
+    info!("{}: Playing pattern '{}'", device.parameter_label(), pattern.name);
+
+    let device = device.clone();
+    task::spawn(async move {
+        loop {
+            // Advance the playback, stopping if it ended or was replaced
+            let frame = match DEVICE_OUTPUT.lock() {
+                Ok(mut device_output) => match device_output.get_mut(device.device_uri.as_str()) {
+                    Some(output) => match output.playback.as_mut() {
+                        Some(playback) if playback.id == id => match playback.pattern.intensity_at(playback.started.elapsed()) {
+                            Some(intensity) => {
+                                playback.pattern_tx = data_processing::level_tx(intensity, &device);
+                                Frame::Send(match playback.mode {
+                                    PatternMode::Mix => playback.pattern_tx.max(output.live_tx),
+                                    PatternMode::Override => playback.pattern_tx,
+                                })
+                            }
+                            None => {
+                                output.playback = None;
+                                Frame::Finished(output.live_tx)
+                            }
+                        },
+                        // Stopped, the player sends the last frame so nothing it had in flight stays on the motor
+                        None => Frame::Finished(output.live_tx),
+                        _ => Frame::Stopped,
+                    },
+                    None => Frame::Finished(0.0),
+                },
+                Err(_) => Frame::Stopped,
+            };
+
+            let headpat_tx = match frame {
+                Frame::Send(headpat_tx) => headpat_tx,
+                // Hand the motor back to the live output
+                Frame::Finished(live_tx) => {
+                    if let Err(e) = motor_output::emit(&device, live_tx).await {
+                        warn!("Pattern: Failed to send to {}: {}", device.device_uri, e);
+                    }
+                    return;
+                }
+                Frame::Stopped => return,
+            };
+            if let Err(e) = motor_output::emit(&device, headpat_tx).await {
+                warn!("Pattern: Failed to send to {}: {}", device.device_uri, e);
+            }
+            task::sleep(FRAME_INTERVAL).await;
+        }
+    });
+}
+
+// Stop a pattern, the player then hands the motor back to the live output
+pub(crate) fn stop(device: &DeviceConfig, pattern: &Arc<Pattern>) {
+    if let Ok(mut device_output) = DEVICE_OUTPUT.lock() {
+        if let Some(output) = device_output.get_mut(device.device_uri.as_str()) {
+            if output.playback.as_ref().map(|playback| Arc::ptr_eq(&playback.pattern, pattern)).unwrap_or(false) {
+                output.playback = None;
+            }
+        }
+    }
+}
+
+// End any pattern and the live output, the player's last frame is 0
+pub(crate) fn stop_all(device: &DeviceConfig) {
+    if let Ok(mut device_output) = DEVICE_OUTPUT.lock() {
+        if let Some(output) = device_output.get_mut(device.device_uri.as_str()) {
+            output.live_tx = 0.0;
+            output.playback = None;
+        }
+    }
+}
+
+// The device was removed from the configuration, a playing pattern ends with a 0
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_output) = DEVICE_OUTPUT.lock() {
+        device_output.remove(device.device_uri.as_str());
+    }
+    if let Ok(mut trigger_state) = TRIGGER_STATE.lock() {
+        trigger_state.retain(|(device_uri, _), _| device_uri != device.device_uri.as_str());
+    }
+}
+
+pub(crate) fn handle_trigger(device: &DeviceConfig, trigger: &PatternTrigger, value: f32) {
+    let on = value >= 0.5;
+    let was_on = match TRIGGER_STATE.lock() {
+        Ok(mut trigger_state) => trigger_state
+            .insert((device.device_uri.to_string(), trigger.parameter.to_string()), on)
+            .unwrap_or(false),
+        Err(_) => return,
+    };
+
+    if on && !was_on {
+        play(device, trigger.pattern.clone(), trigger.mode);
+    } else if !on && was_on && trigger.pattern.looping {
+        stop(device, &trigger.pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn device(ip: &str) -> DeviceConfig {
+        config::test_devices(&format!("setup:\n  port_rx: 9001\ndevices:\n  - ip: {}\n    proximity_parameter: pat\n", ip)).remove(0)
+    }
+
+    // A playback without a player, so nothing is sent
+    fn start(device: &DeviceConfig, name: &str, mode: PatternMode) -> Arc<Pattern> {
+        let pattern = Arc::new(builtin(name).unwrap());
+        DEVICE_OUTPUT.lock().unwrap().entry(device.device_uri.to_string()).or_default().playback = Some(Playback {
+            id: NEXT_PLAYBACK_ID.fetch_add(1, Ordering::SeqCst),
+            pattern: pattern.clone(),
+            mode,
+            started: Instant::now(),
+            pattern_tx: 100.0
+        });
+        pattern
+    }
+
+    #[test]
+    fn builtins_are_valid() {
+        for name in ["pulse", "heartbeat", "purr", "boop"] {
+            assert_eq!(builtin(name).unwrap().validate(), Ok(()));
+        }
+        assert!(builtin("bogus").is_none());
+    }
+
+    #[test]
+    fn one_shot_ends_after_its_repeats() {
+        let mut boop = builtin("boop").unwrap();
+        assert_eq!(boop.intensity_at(Duration::from_millis(30)), Some(1.0));
+        assert_eq!(boop.intensity_at(Duration::from_millis(150)), None);
+        boop.repeat = 2;
+        assert_eq!(boop.intensity_at(Duration::from_millis(180)), Some(1.0));
+        assert_eq!(boop.intensity_at(Duration::from_millis(300)), None);
+    }
+
+    #[test]
+    fn looping_pattern_repeats() {
+        let purr = builtin("purr").unwrap();
+        assert_eq!(purr.intensity_at(Duration::from_secs(60)), purr.intensity_at(Duration::ZERO));
+    }
+
+    #[test]
+    fn easing_shapes_the_transition() {
+        assert_eq!(Easing::Step.apply(0.5), 0.0);
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn mix_and_override() {
+        let device = device("10.0.0.1");
+        assert_eq!(mix_live(&device, 50.0), Some(50.0));
+        start(&device, "purr", PatternMode::Mix);
+        assert_eq!(mix_live(&device, 50.0), Some(100.0));
+        assert_eq!(mix_live(&device, 150.0), Some(150.0));
+        start(&device, "purr", PatternMode::Override);
+        assert_eq!(mix_live(&device, 150.0), None);
+    }
+
+    #[test]
+    fn stop_only_ends_its_own_pattern() {
+        let device = device("10.0.0.2");
+        let purr = start(&device, "purr", PatternMode::Mix);
+        stop(&device, &Arc::new(builtin("purr").unwrap()));
+        assert!(is_playing(&device));
+        stop(&device, &purr);
+        assert!(!is_playing(&device));
+    }
+
+    #[test]
+    fn stop_all_ends_pattern_and_live_output() {
+        let device = device("10.0.0.3");
+        start(&device, "heartbeat", PatternMode::Override);
+        mix_live(&device, 80.0);
+        stop_all(&device);
+        assert!(!is_playing(&device));
+        assert_eq!(DEVICE_OUTPUT.lock().unwrap()[device.device_uri.as_str()].live_tx, 0.0);
+    }
+}
@@ -0,0 +1,216 @@
+/*
+    pulse.rs - Low-Speed Pulse Mode for GiggleTech Devices
+
+    Below a certain speed the motor stalls and light touches feel like nothing. With pulse mode on,
+    any requested value below the device's stall point is rendered as short bursts at a speed the
+    motor reliably reaches, with a duty cycle proportional to the request. The average output matches
+    the request, so the perceived intensity stays continuous down to zero.
+
+    **Parameters:**
+
+    - `pulse_mode`: Enables pulse mode (default false).
+    - `stall_tx`: Motor Tx below which the motor does not spin reliably.
+    - `pulse_tx`: Motor Tx of each burst (default `stall_tx`).
+    - `pulse_period`: Length of one on/off cycle in milliseconds (default 200). Very low requests
+      stretch the period so bursts never get shorter than `MIN_BURST`.
+
+    **Key Features:**
+
+    1. **Rendering (`render`)**:
+       - Values at or above the stall point (and zero) pass straight through. Values below it are
+         handed to a per-device pulse worker.
+
+    2. **Pulse Worker**:
+       - Sends `pulse_tx`, waits the on time, sends 0 and waits the off time, picking up the latest
+         request every cycle until the request leaves the pulse range.
+       - The request is checked again after every send, and the worker ends by sending the request
+         that ended the pulses (`0` after a stop), so a burst never outlasts a stop.
+
+    **Usage**:
+    - `motor_output::send_output` calls `render` for every governed motor value.
+*/
+
+use async_std::task;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::DeviceConfig;
+use crate::motor_output;
+
+// Shortest burst the motor can spin up in
+const MIN_BURST: Duration = Duration::from_millis(25);
+
+lazy_static! {
+    static ref DEVICE_PULSE: Arc<Mutex<HashMap<String, PulseState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PulseParams {
+    pub enabled: bool,
+    pub stall_tx: i32,
+    pub pulse_tx: i32,
+    pub period: Duration
+}
+
+impl PulseParams {
+    pub fn disabled() -> PulseParams {
+        PulseParams { enabled: false, stall_tx: 0, pulse_tx: 0, period: Duration::ZERO }
+    }
+
+    fn in_range(&self, headpat_tx: f32) -> bool {
+        self.enabled && headpat_tx > 0.0 && headpat_tx < self.stall_tx as f32
+    }
+
+    // On and off time of one cycle for a requested value
+    fn cycle(&self, headpat_tx: f32) -> (Duration, Duration) {
+        let duty = (headpat_tx / self.pulse_tx.max(1) as f32).clamp(0.0, 1.0);
+        let period = self.period.max(MIN_BURST.div_f32(duty.max(1e-3)));
+        let on_time = period.mul_f32(duty);
+        (on_time, period - on_time)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PulseState {
+    request_tx: f32,
+    pulsing: bool
+}
+
+// Returns the value to send now, or None while the pulse worker renders it
+pub(crate) fn render(device: &DeviceConfig, headpat_tx: f32) -> Option<f32> {
+    if !device.pulse.enabled {
+        return Some(headpat_tx);
+    }
+    let start_worker = {
+        let mut device_pulse = match DEVICE_PULSE.lock() {
+            Ok(device_pulse) => device_pulse,
+            Err(_) => return Some(headpat_tx),
+        };
+        let state = device_pulse.entry(device.device_uri.to_string()).or_default();
+        state.request_tx = headpat_tx;
+        if !device.pulse.in_range(headpat_tx) {
+            return Some(headpat_tx);
+        }
+        !std::mem::replace(&mut state.pulsing, true)
+    };
+
+    if start_worker {
+        let device = device.clone();
+        task::spawn(async move {
+            if let Err(e) = pulse_worker(&device).await {
+                warn!("Pulse: Failed to send to {}: {}", device.device_uri, e);
+                if let Ok(mut device_pulse) = DEVICE_PULSE.lock() {
+                    if let Some(state) = device_pulse.get_mut(device.device_uri.as_str()) {
+                        state.pulsing = false;
+                    }
+                }
+            }
+        });
+    }
+    None
+}
+
+fn current_request(device: &DeviceConfig) -> f32 {
+    DEVICE_PULSE.lock().ok()
+        .and_then(|device_pulse| device_pulse.get(device.device_uri.as_str()).map(|state| state.request_tx))
+        .unwrap_or(0.0)
+}
+
+// The request once it has left the pulse range, the worker then ends
+fn finished_request(device: &DeviceConfig) -> Option<f32> {
+    let mut device_pulse = match DEVICE_PULSE.lock() {
+        Ok(device_pulse) => device_pulse,
+        Err(_) => return Some(0.0),
+    };
+    match device_pulse.get_mut(device.device_uri.as_str()) {
+        Some(state) if device.pulse.in_range(state.request_tx) => None,
+        Some(state) => {
+            state.pulsing = false;
+            Some(state.request_tx)
+        }
+        None => Some(0.0),
+    }
+}
+
+async fn pulse_worker(device: &DeviceConfig) -> async_osc::Result<()> {
+    let request_tx = loop {
+        if let Some(request_tx) = finished_request(device) {
+            break request_tx;
+        }
+        let (on_time, off_time) = device.pulse.cycle(current_request(device));
+        motor_output::send_targets(device, device.pulse.pulse_tx as f32).await?;
+        // Checked after every send, a stop may have been sent while the burst was on its way
+        if let Some(request_tx) = finished_request(device) {
+            break request_tx;
+        }
+        task::sleep(on_time).await;
+        if let Some(request_tx) = finished_request(device) {
+            break request_tx;
+        }
+        motor_output::send_targets(device, 0.0).await?;
+        task::sleep(off_time).await;
+    };
+    // Repeat the value that ended the pulses (0 after a stop), so no burst sent after it stays on
+    motor_output::send_targets(device, request_tx).await
+}
+
+// Forgets the burst state of a removed device
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_pulse) = DEVICE_PULSE.lock() {
+        device_pulse.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn device(ip: &str) -> DeviceConfig {
+        config::test_devices(&format!(
+            "setup:\n  port_rx: 9001\ndevices:\n  - ip: {}\n    proximity_parameter: pat\n    pulse_mode: true\n    stall_tx: 60\n    pulse_tx: 80\n",
+            ip
+        )).remove(0)
+    }
+
+    #[test]
+    fn only_values_below_the_stall_point_pulse() {
+        let params = device("10.0.2.1").pulse;
+        assert!(params.in_range(30.0));
+        assert!(!params.in_range(0.0));
+        assert!(!params.in_range(60.0));
+        assert!(!PulseParams::disabled().in_range(30.0));
+    }
+
+    #[test]
+    fn duty_follows_the_request() {
+        let params = device("10.0.2.2").pulse;
+        let (on_time, off_time) = params.cycle(40.0);
+        assert_eq!(on_time + off_time, params.period);
+        assert!((on_time.as_secs_f32() - params.period.as_secs_f32() / 2.0).abs() < 1e-4);
+        // Short bursts stretch the period instead
+        let (on_time, _) = params.cycle(1.0);
+        assert!(on_time >= MIN_BURST - Duration::from_micros(1));
+    }
+
+    #[test]
+    fn render_passes_values_outside_the_range() {
+        let device = device("10.0.2.3");
+        assert_eq!(render(&device, 0.0), Some(0.0));
+        assert_eq!(render(&device, 120.0), Some(120.0));
+    }
+
+    #[test]
+    fn worker_ends_with_the_request_that_stopped_it() {
+        let device = device("10.0.2.4");
+        DEVICE_PULSE.lock().unwrap().insert(device.device_uri.to_string(), PulseState { request_tx: 30.0, pulsing: true });
+        assert_eq!(finished_request(&device), None);
+        DEVICE_PULSE.lock().unwrap().get_mut(device.device_uri.as_str()).unwrap().request_tx = 0.0;
+        assert_eq!(finished_request(&device), Some(0.0));
+        assert!(!DEVICE_PULSE.lock().unwrap()[device.device_uri.as_str()].pulsing);
+    }
+}
@@ -0,0 +1,254 @@
+/*
+    response_curve.rs - Response Curves for Proximity Mode
+
+    Different people and devices need a very different feel at the light-touch end. This module
+    shapes the proximity signal (0.0 - 1.0) before it is mapped between `min_speed` and `max_speed`.
+
+    **Key Features:**
+
+    1. **Curve Types (`CurveKind`)**:
+       - `linear`: Output follows proximity (the original behaviour).
+       - `exponential`: Stays gentle for light touches and ramps up late. `curve_strength` (default 3)
+         sets how late; values near 0 are close to linear.
+       - `gamma`: `proximity ^ curve_strength` (default 2). Below 1 boosts light touches, above 1 softens them.
+       - `s_curve`: Soft at both ends and steep in the middle. `curve_strength` (default 2) sets the steepness.
+       - `table`: User supplied `[proximity, output]` points with linear interpolation in between.
+
+    2. **Runtime Switching (`set_curve_index`)**:
+       - A device's `curve_parameter` selects the curve while running: 0 = linear, 1 = exponential,
+         2 = gamma, 3 = s_curve, 4 = table (only when a table is configured). `curve_strength` was
+         checked for the configured curve only, so another curve uses its own default strength.
+       - A reload that changes or removes the device goes back to its configured curve (`remove`).
+
+    3. **Output Range (`ResponseCurve::apply`)**:
+       - The shaped proximity is clamped to 0.0 - 1.0, so no curve or table drives the motor past
+         `max_speed`.
+
+    **Usage**:
+    - `current_curve(device).apply(proximity)` returns the shaped proximity for the device.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+use log::info;
+
+use crate::config::DeviceConfig;
+
+lazy_static! {
+    // Curves selected at runtime through a device's curve parameter
+    static ref DEVICE_CURVE_OVERRIDE: Arc<Mutex<HashMap<String, CurveKind>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CurveKind {
+    Linear,
+    Exponential,
+    Gamma,
+    SCurve,
+    Table
+}
+
+const CURVE_KINDS: [CurveKind; 5] = [CurveKind::Linear, CurveKind::Exponential, CurveKind::Gamma, CurveKind::SCurve, CurveKind::Table];
+
+impl CurveKind {
+    pub fn parse(kind: &str) -> Option<CurveKind> {
+        match kind.to_lowercase().as_str() {
+            "linear" => Some(CurveKind::Linear),
+            "exponential" => Some(CurveKind::Exponential),
+            "gamma" => Some(CurveKind::Gamma),
+            "s_curve" => Some(CurveKind::SCurve),
+            "table" => Some(CurveKind::Table),
+            _ => None,
+        }
+    }
+
+    fn default_strength(&self) -> f32 {
+        match self {
+            CurveKind::Exponential => 3.0,
+            CurveKind::Gamma | CurveKind::SCurve => 2.0,
+            CurveKind::Linear | CurveKind::Table => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ResponseCurve {
+    pub kind: CurveKind,
+    pub strength: Option<f32>,
+    pub table: Arc<Vec<(f32, f32)>>
+}
+
+impl ResponseCurve {
+    pub fn apply(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let strength = self.strength.unwrap_or_else(|| self.kind.default_strength());
+        let y = match self.kind {
+            CurveKind::Linear => x,
+            CurveKind::Exponential => {
+                if strength.abs() < 1e-3 {
+                    x
+                } else {
+                    ((strength * x).exp() - 1.0) / (strength.exp() - 1.0)
+                }
+            }
+            CurveKind::Gamma => x.powf(strength),
+            CurveKind::SCurve => {
+                let a = x.powf(strength);
+                let b = (1.0 - x).powf(strength);
+                if a + b > 0.0 { a / (a + b) } else { x }
+            }
+            CurveKind::Table => interpolate(&self.table, x),
+        };
+        if y.is_nan() { 0.0 } else { y.clamp(0.0, 1.0) }
+    }
+
+    // The curve switched to another kind, with that kind's default strength
+    fn switched_to(&self, kind: CurveKind) -> ResponseCurve {
+        if kind == self.kind {
+            return self.clone();
+        }
+        ResponseCurve { kind, strength: None, table: self.table.clone() }
+    }
+}
+
+fn interpolate(table: &[(f32, f32)], x: f32) -> f32 {
+    let (first, last) = match (table.first(), table.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return x,
+    };
+    if x <= first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+    for pair in table.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if x >= x0 && x <= x1 {
+            if x1 - x0 <= f32::EPSILON {
+                return y1;
+            }
+            return y0 + (y1 - y0) * (x - x0) / (x1 - x0);
+        }
+    }
+    last.1
+}
+
+pub(crate) fn current_curve(device: &DeviceConfig) -> ResponseCurve {
+    let override_kind = DEVICE_CURVE_OVERRIDE.lock().ok()
+        .and_then(|overrides| overrides.get(device.device_uri.as_str()).cloned());
+    match override_kind {
+        Some(kind) => device.curve.switched_to(kind),
+        None => device.curve.clone(),
+    }
+}
+
+pub(crate) fn set_curve_index(device: &DeviceConfig, index: f32) {
+    let kind = match CURVE_KINDS.get(index.round().max(0.0) as usize) {
+        Some(CurveKind::Table) if device.curve.table.is_empty() => {
+            info!("{}: No curve table configured, keeping current curve", device.parameter_label());
+            return;
+        }
+        Some(kind) => *kind,
+        None => {
+            info!("{}: Unknown curve index {}", device.parameter_label(), index);
+            return;
+        }
+    };
+    if let Ok(mut overrides) = DEVICE_CURVE_OVERRIDE.lock() {
+        overrides.insert(device.device_uri.to_string(), kind);
+    }
+    info!("{}: Response curve set to {:?}", device.parameter_label(), kind);
+}
+
+// Forgets the curve selected at runtime
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut overrides) = DEVICE_CURVE_OVERRIDE.lock() {
+        overrides.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(kind: CurveKind, strength: Option<f32>) -> ResponseCurve {
+        ResponseCurve { kind, strength, table: Arc::new(Vec::new()) }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn every_curve_keeps_the_end_points() {
+        for kind in [CurveKind::Linear, CurveKind::Exponential, CurveKind::Gamma, CurveKind::SCurve] {
+            let curve = curve(kind, None);
+            assert!(close(curve.apply(0.0), 0.0), "{:?} at 0", kind);
+            assert!(close(curve.apply(1.0), 1.0), "{:?} at 1", kind);
+        }
+    }
+
+    #[test]
+    fn linear_follows_proximity() {
+        assert!(close(curve(CurveKind::Linear, None).apply(0.3), 0.3));
+    }
+
+    #[test]
+    fn exponential_stays_gentle_for_light_touches() {
+        let y = curve(CurveKind::Exponential, None).apply(0.5);
+        assert!(close(y, ((1.5f32).exp() - 1.0) / ((3.0f32).exp() - 1.0)));
+        assert!(y < 0.5);
+        assert!(close(curve(CurveKind::Exponential, Some(0.0)).apply(0.4), 0.4));
+    }
+
+    #[test]
+    fn gamma_raises_proximity_to_the_strength() {
+        assert!(close(curve(CurveKind::Gamma, None).apply(0.5), 0.25));
+        assert!(close(curve(CurveKind::Gamma, Some(0.5)).apply(0.25), 0.5));
+    }
+
+    #[test]
+    fn s_curve_is_symmetric_around_the_middle() {
+        let curve = curve(CurveKind::SCurve, None);
+        assert!(close(curve.apply(0.5), 0.5));
+        assert!(curve.apply(0.2) < 0.2);
+        assert!(close(curve.apply(0.2) + curve.apply(0.8), 1.0));
+    }
+
+    #[test]
+    fn table_interpolates_between_points() {
+        let table = ResponseCurve { kind: CurveKind::Table, strength: None, table: Arc::new(vec![(0.0, 0.0), (0.5, 0.8), (1.0, 1.0)]) };
+        assert!(close(table.apply(0.25), 0.4));
+        assert!(close(table.apply(0.75), 0.9));
+    }
+
+    #[test]
+    fn switching_curves_uses_the_default_strength() {
+        let exponential = curve(CurveKind::Exponential, Some(-2.0));
+        assert_eq!(exponential.switched_to(CurveKind::Gamma).strength, None);
+        assert!(close(exponential.switched_to(CurveKind::Gamma).apply(0.5), 0.25));
+        assert_eq!(exponential.switched_to(CurveKind::Exponential), exponential);
+    }
+
+    #[test]
+    fn removed_override_restores_the_configured_curve() {
+        let device = crate::config::test_devices(
+            "setup:\n  port_rx: 9001\ndevices:\n  - ip: 10.0.30.1\n    proximity_parameter: pat\n    curve: gamma\n"
+        ).remove(0);
+        set_curve_index(&device, 1.0);
+        assert_eq!(current_curve(&device).kind, CurveKind::Exponential);
+        remove(&device);
+        assert_eq!(current_curve(&device).kind, CurveKind::Gamma);
+    }
+
+    #[test]
+    fn output_is_clamped_to_the_unit_range() {
+        let table = ResponseCurve { kind: CurveKind::Table, strength: None, table: Arc::new(vec![(0.0, -0.5), (1.0, 1.5)]) };
+        assert_eq!(table.apply(0.0), 0.0);
+        assert_eq!(table.apply(1.0), 1.0);
+        assert!(close(table.apply(0.5), 0.5));
+    }
+}
@@ -0,0 +1,343 @@
+/*
+    scripting.rs - Scripted Motor Mappings for GiggleTech Devices
+
+    New mapping ideas no longer need a change to `data_processing.rs`. A device with a `script`
+    runs a small Rhai script for every proximity sample (and on a tick while the hand is still), and the
+    value it returns drives the motor.
+
+    **Script Inputs:**
+
+    - `proximity`: Filtered proximity (0.0 - 1.0).
+    - `velocity`: Filtered velocity from `motion_model` (proximity units per second, positive when approaching).
+    - `time`: Seconds since the router started.
+    - `contact_time`: Seconds since the current contact started.
+    - `min_speed` / `max_speed`: The device's speed limits (0.0 - 1.0), `max_speed` follows the max speed parameter.
+    - `last_output`: The speed the script returned last time.
+    - `state`: A map kept between calls for the script's own use, e.g. `state.peak = proximity;`.
+
+    The script returns the motor speed (0.0 - 1.0, same scale as `min_speed` and `max_speed`). The
+    result is clamped to `max_speed`, so a script can never exceed the device's speed limit.
+
+    ```rhai
+    // Pulse faster the closer the hand gets
+    let rate = 2.0 + proximity * 8.0;
+    let wave = (contact_time * rate * 6.283).sin() * 0.5 + 0.5;
+    min_speed + (max_speed - min_speed) * proximity * wave
+    ```
+
+    **Key Features:**
+
+    1. **Tick (`start_tick`)**:
+       - The script also runs when no sample arrived for 50 ms during a contact, with the last
+         proximity, so scripts using `time` or `contact_time` keep changing while the hand is still.
+
+    2. **Sandbox (`new_engine`)**:
+       - Scripts have no file, network or module access, and are limited in the number of operations,
+         call depth, and string, array and map sizes. A script that hits a limit or fails returns 0.
+
+    3. **Hot Reload (`watch_script`)**:
+       - Script files are checked for changes every second and recompiled. If the new version does
+         not compile, the previous version keeps running.
+
+    **Usage**:
+    - `load_script` compiles a script when the configuration is loaded.
+    - `evaluate` runs the device's script for a sample and returns the motor speed.
+    - `reset` is called when the contact ends and ends the tick.
+*/
+
+use async_std::task;
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+
+use crate::config::DeviceConfig;
+use crate::data_processing;
+use crate::motion_model;
+use crate::motor_output;
+
+// Interval between checks of script files for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+// Longest time without a script run during a contact
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+lazy_static! {
+    static ref ENGINE: Engine = new_engine();
+    static ref START_TIME: Instant = Instant::now();
+    static ref SCRIPTS: Arc<Mutex<HashMap<String, LoadedScript>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    static ref DEVICE_SCRIPT_STATE: Arc<Mutex<HashMap<String, ScriptState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+struct LoadedScript {
+    ast: Arc<AST>,
+    modified: Option<SystemTime>
+}
+
+#[derive(Clone, Debug, Default)]
+struct ScriptState {
+    state: Map,
+    contact_start: Option<Instant>,
+    last_output: f32,
+    // Last proximity and run, for the tick
+    proximity: f32,
+    last_run: Option<Instant>,
+    ticking: bool
+}
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(50_000);
+    engine.set_max_call_levels(16);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1024);
+    engine.set_max_array_size(256);
+    engine.set_max_map_size(256);
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.on_print(|text| info!("Script: {}", text));
+    engine.on_debug(|text, _, _| debug!("Script: {}", text));
+    engine
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn compile(path: &str) -> Result<AST, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read script {}: {}", path, e))?;
+    ENGINE.compile(source).map_err(|e| format!("Error in script {}: {}", path, e))
+}
+
+pub(crate) fn load_script(path: &str) -> Result<(), String> {
+    let ast = compile(path)?;
+    if let Ok(mut scripts) = SCRIPTS.lock() {
+        scripts.insert(path.to_string(), LoadedScript { ast: Arc::new(ast), modified: modified_time(path) });
+    }
+    Ok(())
+}
+
+// Recompile the script whenever its file changes
+pub(crate) async fn watch_script(path: Arc<String>) {
+    loop {
+        task::sleep(RELOAD_INTERVAL).await;
+        let modified = modified_time(&path);
+        let changed = match SCRIPTS.lock() {
+            Ok(scripts) => scripts.get(path.as_str()).map(|script| script.modified != modified).unwrap_or(false),
+            Err(_) => return,
+        };
+        if !changed {
+            continue;
+        }
+
+        // Remember the new time either way so a broken file is reported once
+        let ast = compile(&path);
+        if let Ok(mut scripts) = SCRIPTS.lock() {
+            if let Some(script) = scripts.get_mut(path.as_str()) {
+                script.modified = modified;
+                match ast {
+                    Ok(ast) => {
+                        script.ast = Arc::new(ast);
+                        info!("Reloaded script {}", path);
+                    }
+                    Err(e) => warn!("{}, keeping the previous version", e),
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn evaluate(device: &DeviceConfig, proximity: f32, velocity: f32) -> f32 {
+    run(device, proximity, velocity, Instant::now(), true).unwrap_or(0.0)
+}
+
+// Runs the script, a tick (`sample` false) only runs during a contact
+fn run(device: &DeviceConfig, proximity: f32, velocity: f32, now: Instant, sample: bool) -> Option<f32> {
+    let path = match &device.script {
+        Some(path) => path,
+        None => return Some(0.0),
+    };
+    let ast = match SCRIPTS.lock() {
+        Ok(scripts) => match scripts.get(path.as_str()) {
+            Some(script) => script.ast.clone(),
+            None => return Some(0.0),
+        },
+        Err(_) => return Some(0.0),
+    };
+
+    let mut script_state = DEVICE_SCRIPT_STATE.lock().ok()
+        .and_then(|states| states.get(device.device_uri.as_str()).cloned())
+        .unwrap_or_default();
+    if !sample && script_state.contact_start.is_none() {
+        return None;
+    }
+    let contact_start = *script_state.contact_start.get_or_insert(now);
+
+    let mut scope = Scope::new();
+    scope.push_constant("proximity", proximity as f64);
+    scope.push_constant("velocity", velocity as f64);
+    scope.push_constant("time", now.saturating_duration_since(*START_TIME).as_secs_f64());
+    scope.push_constant("contact_time", now.saturating_duration_since(contact_start).as_secs_f64());
+    scope.push_constant("min_speed", device.min_speed as f64);
+    scope.push_constant("max_speed", device.max_speed as f64);
+    scope.push_constant("last_output", script_state.last_output as f64);
+    scope.push("state", script_state.state.clone());
+
+    let speed = match ENGINE.eval_ast_with_scope::<Dynamic>(&mut scope, &ast) {
+        Ok(result) => match result.as_float().or_else(|_| result.as_int().map(|x| x as f64)) {
+            Ok(speed) => (speed as f32).clamp(0.0, device.max_speed),
+            Err(type_name) => {
+                warn!("Script {} returned {} instead of a number", path, type_name);
+                0.0
+            }
+        },
+        Err(e) => {
+            warn!("Script {} failed: {}", path, e);
+            0.0
+        }
+    };
+    let speed = if speed.is_finite() { speed } else { 0.0 };
+
+    if let Ok(mut states) = DEVICE_SCRIPT_STATE.lock() {
+        let current = states.entry(device.device_uri.to_string()).or_default();
+        // The contact ended while a tick was running
+        if !sample && current.contact_start.is_none() {
+            return None;
+        }
+        current.state = scope.get_value::<Map>("state").unwrap_or_default();
+        current.contact_start = Some(contact_start);
+        current.last_output = speed;
+        current.proximity = proximity;
+        current.last_run = Some(now);
+    }
+    Some(speed)
+}
+
+// Keep running the script while the contact lasts, so time based scripts go on while the hand is still
+pub(crate) fn start_tick(device: &DeviceConfig) {
+    if let Ok(mut states) = DEVICE_SCRIPT_STATE.lock() {
+        match states.get_mut(device.device_uri.as_str()) {
+            Some(script_state) if script_state.contact_start.is_some() && !script_state.ticking => script_state.ticking = true,
+            _ => return,
+        }
+    }
+
+    let device = device.clone();
+    task::spawn(async move {
+        loop {
+            task::sleep(TICK_INTERVAL).await;
+            let now = Instant::now();
+            let proximity = match DEVICE_SCRIPT_STATE.lock() {
+                Ok(mut states) => match states.get_mut(device.device_uri.as_str()) {
+                    Some(script_state) if script_state.contact_start.is_some() => tick_due(script_state, now).then_some(script_state.proximity),
+                    Some(script_state) => {
+                        script_state.ticking = false;
+                        return;
+                    }
+                    None => return,
+                },
+                Err(_) => return,
+            };
+            let speed = match proximity.and_then(|proximity| run(&device, proximity, motion_model::velocity(&device), now, false)) {
+                Some(speed) => speed,
+                None => continue,
+            };
+
+            let headpat_tx = data_processing::script_tx(speed, &device);
+            if let Err(e) = motor_output::send_motor(&device, headpat_tx).await {
+                warn!("Script tick: Failed to send to {}: {}", device.device_uri, e);
+            }
+            // The contact ended while sending, its stop may have gone out first
+            if !in_contact(&device) {
+                if let Err(e) = motor_output::send_live_stop(&device).await {
+                    warn!("Script tick: Failed to stop {}: {}", device.device_uri, e);
+                }
+            }
+        }
+    });
+}
+
+fn tick_due(script_state: &ScriptState, now: Instant) -> bool {
+    script_state.last_run.map(|last_run| now.saturating_duration_since(last_run) >= TICK_INTERVAL).unwrap_or(true)
+}
+
+fn in_contact(device: &DeviceConfig) -> bool {
+    DEVICE_SCRIPT_STATE.lock().ok()
+        .and_then(|states| states.get(device.device_uri.as_str()).map(|script_state| script_state.contact_start.is_some()))
+        .unwrap_or(false)
+}
+
+// Contact ended, the next sample starts a new contact
+pub(crate) fn reset(device: &DeviceConfig) {
+    if let Ok(mut states) = DEVICE_SCRIPT_STATE.lock() {
+        if let Some(script_state) = states.get_mut(device.device_uri.as_str()) {
+            script_state.contact_start = None;
+            script_state.last_output = 0.0;
+            script_state.last_run = None;
+        }
+    }
+}
+
+// The device was removed, its script variables start over if it comes back
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut states) = DEVICE_SCRIPT_STATE.lock() {
+        states.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn device(ip: &str, source: &str) -> DeviceConfig {
+        let path = std::env::temp_dir().join(format!("giggletech_script_{}.rhai", ip));
+        std::fs::write(&path, source).unwrap();
+        config::test_devices(&format!(
+            "setup:\n  port_rx: 9001\ndevices:\n  - ip: {}\n    proximity_parameter: pat\n    script: {}\n",
+            ip, path.display()
+        )).remove(0)
+    }
+
+    #[test]
+    fn output_is_limited_to_max_speed() {
+        let device = device("10.0.1.1", "proximity * 10.0");
+        assert!((evaluate(&device, 0.01, 0.0) - 0.1).abs() < 1e-6);
+        assert_eq!(evaluate(&device, 1.0, 0.0), device.max_speed);
+    }
+
+    #[test]
+    fn failing_script_returns_zero() {
+        assert_eq!(evaluate(&device("10.0.1.2", "\"fast\""), 1.0, 0.0), 0.0);
+        assert_eq!(evaluate(&device("10.0.1.3", "1.0 / unknown"), 1.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn state_is_kept_between_samples() {
+        let device = device("10.0.1.4", "state.count = (state.count ?? 0) + 1; state.count / 10.0");
+        assert_eq!(evaluate(&device, 0.5, 0.0), 0.1);
+        assert_eq!(evaluate(&device, 0.5, 0.0), 0.2);
+    }
+
+    #[test]
+    fn tick_runs_with_the_last_proximity_until_the_contact_ends() {
+        let device = device("10.0.1.5", "(proximity + contact_time) / 10.0");
+        let start = Instant::now();
+        assert_eq!(run(&device, 0.25, 0.0, start, false), None);
+        assert_eq!(run(&device, 0.25, 0.0, start, true), Some(0.025));
+
+        let later = start + Duration::from_millis(200);
+        let script_state = DEVICE_SCRIPT_STATE.lock().unwrap()[device.device_uri.as_str()].clone();
+        assert!(tick_due(&script_state, later));
+        let speed = run(&device, script_state.proximity, 0.0, later, false).unwrap();
+        assert!((speed - 0.045).abs() < 1e-5);
+
+        reset(&device);
+        assert!(!in_contact(&device));
+        assert_eq!(run(&device, 0.25, 0.0, later, false), None);
+    }
+}
@@ -0,0 +1,334 @@
+/*
+    session_stats.rs - Session Analytics and History
+
+    Keeps per-device statistics for the running session, saves them to a history folder and
+    summarises or exports past sessions.
+
+    **Statistics (`DeviceStats`):**
+
+    - `run_time`: Seconds the motor was running (output above 0).
+    - `pats`: Number of contacts.
+    - `peak_intensity` / `average_intensity`: Highest and time-weighted average motor output while
+      running, in % of the full motor range.
+    - `timeouts`: Contacts ended by the OSC timeout instead of by the avatar.
+    - `sends` / `send_failures`: From the connection counters in `giggletech_osc`, combined over the
+      members of a group.
+
+    **Key Features:**
+
+    1. **Recording (`record_output`, `record_pat`, `record_timeout`)**:
+       - Called from `motor_output`, `handle_proximity_parameter` and `osc_timeout`.
+
+    2. **History (`history_worker`, `save`)**:
+       - The session is written to `history/session-<start time>.json` every `SAVE_INTERVAL` and when
+         the router quits, so a closed console loses at most one interval. The history folder is
+         next to config.yml (see `paths`).
+
+    3. **Summary and Export (`run_command`)**:
+       - `history` prints every saved session and the totals per device.
+       - `history --csv <file>` / `history --json <file>` export all sessions, one CSV row per device
+         and session, or one JSON list of sessions.
+*/
+
+use async_std::task;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::config::DeviceConfig;
+use crate::config_reload;
+use crate::data_processing;
+use crate::giggletech_osc;
+use crate::paths;
+
+// Time between saves of the running session
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref SESSION_START: DateTime<Local> = Local::now();
+    static ref DEVICE_SESSION: Mutex<HashMap<String, DeviceSession>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, Debug, Default)]
+struct DeviceSession {
+    pats: u32,
+    timeouts: u32,
+    // Output level (0.0 - 1.0) since `last_time`
+    level: f32,
+    last_time: Option<Instant>,
+    run_time: f64,
+    // Sum of level × seconds while running
+    intensity_time: f64,
+    peak: f32
+}
+
+impl DeviceSession {
+    fn output(&mut self, level: f32, now: Instant) {
+        if let Some(last_time) = self.last_time {
+            if self.level > 0.0 {
+                let elapsed = now.saturating_duration_since(last_time).as_secs_f64();
+                self.run_time += elapsed;
+                self.intensity_time += self.level as f64 * elapsed;
+            }
+        }
+        self.level = level;
+        self.last_time = Some(now);
+        self.peak = self.peak.max(level);
+    }
+
+    fn average(&self) -> f32 {
+        if self.run_time > 0.0 { (self.intensity_time / self.run_time) as f32 } else { 0.0 }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DeviceStats {
+    pub device: String,
+    pub label: String,
+    pub run_time: f64,
+    pub pats: u32,
+    pub peak_intensity: f32,
+    pub average_intensity: f32,
+    pub timeouts: u32,
+    pub sends: u32,
+    pub send_failures: u32
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SessionRecord {
+    pub start: String,
+    pub end: String,
+    pub devices: Vec<DeviceStats>
+}
+
+fn update(device: &DeviceConfig, update: impl FnOnce(&mut DeviceSession)) {
+    if let Ok(mut device_session) = DEVICE_SESSION.lock() {
+        update(device_session.entry(device.device_uri.to_string()).or_default());
+    }
+}
+
+pub(crate) fn record_output(device: &DeviceConfig, headpat_tx: f32) {
+    let level = data_processing::tx_speed(headpat_tx, device).clamp(0.0, 1.0);
+    update(device, |session| session.output(level, Instant::now()));
+}
+
+pub(crate) fn record_pat(device: &DeviceConfig) {
+    update(device, |session| session.pats += 1);
+}
+
+pub(crate) fn record_timeout(device: &DeviceConfig) {
+    update(device, |session| session.timeouts += 1);
+}
+
+// Statistics of the running session so far
+async fn snapshot(devices: &[DeviceConfig]) -> SessionRecord {
+    let stats = giggletech_osc::get_connection_stats().await;
+    let now = Instant::now();
+    let device_session = DEVICE_SESSION.lock().map(|device_session| device_session.clone()).unwrap_or_default();
+
+    let devices = devices.iter().map(|device| {
+        // Count the time up to now for a motor that is still running
+        let mut session = device_session.get(device.device_uri.as_str()).cloned().unwrap_or_default();
+        session.output(session.level, now);
+        let (sends, send_failures) = device.targets().iter()
+            .filter_map(|target| stats.get(target.ip.as_str()))
+            .fold((0, 0), |(sends, failures), (count, _, errors)| (sends + count, failures + errors));
+        DeviceStats {
+            device: device.device_uri.to_string(),
+            label: device.parameter_label(),
+            run_time: session.run_time,
+            pats: session.pats,
+            peak_intensity: session.peak * 100.0,
+            average_intensity: session.average() * 100.0,
+            timeouts: session.timeouts,
+            sends,
+            send_failures,
+        }
+    }).collect();
+
+    SessionRecord {
+        start: SESSION_START.to_rfc3339_opts(SecondsFormat::Secs, false),
+        end: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+        devices
+    }
+}
+
+// Write the running session to the history folder
+pub(crate) async fn save(devices: &[DeviceConfig]) {
+    let record = snapshot(devices).await;
+    let dir = paths::history_dir();
+    let path = dir.join(format!("session-{}.json", SESSION_START.format("%Y-%m-%d_%H-%M-%S")));
+    let result = fs::create_dir_all(&dir)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::to_string_pretty(&record).map_err(|e| e.to_string()))
+        .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        warn!("Failed to save session history to {}: {}", path.display(), e);
+    }
+}
+
+pub(crate) async fn history_worker() {
+    // Starts the session clock
+    lazy_static::initialize(&SESSION_START);
+    loop {
+        task::sleep(SAVE_INTERVAL).await;
+        save(&config_reload::current_devices()).await;
+    }
+}
+
+fn load_history(dir: &Path) -> Result<Vec<SessionRecord>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map(|extension| extension == "json").unwrap_or(false))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut sessions = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        match serde_json::from_str(&text) {
+            Ok(session) => sessions.push(session),
+            Err(e) => warn!("Skipping {}: {}", path.display(), e),
+        }
+    }
+    Ok(sessions)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(sessions: &[SessionRecord]) -> String {
+    let mut csv = String::from("session_start,session_end,device,label,run_time_s,pats,peak_intensity,average_intensity,timeouts,sends,send_failures\n");
+    for session in sessions {
+        for stats in session.devices.iter() {
+            csv.push_str(&format!(
+                "{},{},{},{},{:.1},{},{:.1},{:.1},{},{},{}\n",
+                session.start, session.end, csv_field(&stats.device), csv_field(&stats.label), stats.run_time,
+                stats.pats, stats.peak_intensity, stats.average_intensity, stats.timeouts, stats.sends, stats.send_failures
+            ));
+        }
+    }
+    csv
+}
+
+fn session_length(session: &SessionRecord) -> String {
+    match (DateTime::parse_from_rfc3339(&session.start), DateTime::parse_from_rfc3339(&session.end)) {
+        (Ok(start), Ok(end)) => {
+            let minutes = (end - start).num_minutes();
+            format!("{}h {:02}m", minutes / 60, minutes % 60)
+        }
+        _ => "-".to_string(),
+    }
+}
+
+fn print_summary(sessions: &[SessionRecord]) {
+    if sessions.is_empty() {
+        println!("No sessions in {} yet.", paths::history_dir().display());
+        return;
+    }
+    let mut totals: Vec<DeviceStats> = Vec::new();
+    for session in sessions {
+        let start = DateTime::parse_from_rfc3339(&session.start)
+            .map(|start| start.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| session.start.clone());
+        println!("\nSession {} ({})", start, session_length(session));
+        for stats in session.devices.iter() {
+            println!(
+                "  {:<18} {:<16} Run: {:>6.0}s  Pats: {:>4}  Peak: {:>3.0}%  Avg: {:>3.0}%  Timeouts: {:>3}  Failed sends: {}/{}",
+                stats.label, stats.device, stats.run_time, stats.pats, stats.peak_intensity, stats.average_intensity,
+                stats.timeouts, stats.send_failures, stats.sends
+            );
+            match totals.iter_mut().find(|total| total.device == stats.device && total.label == stats.label) {
+                Some(total) => {
+                    // Averages are weighted by run time
+                    let run_time = total.run_time + stats.run_time;
+                    if run_time > 0.0 {
+                        total.average_intensity = ((total.average_intensity as f64 * total.run_time + stats.average_intensity as f64 * stats.run_time) / run_time) as f32;
+                    }
+                    total.run_time = run_time;
+                    total.pats += stats.pats;
+                    total.peak_intensity = total.peak_intensity.max(stats.peak_intensity);
+                    total.timeouts += stats.timeouts;
+                    total.sends += stats.sends;
+                    total.send_failures += stats.send_failures;
+                }
+                None => totals.push(stats.clone()),
+            }
+        }
+    }
+    println!("\nTotals over {} sessions", sessions.len());
+    for total in totals {
+        println!(
+            "  {:<18} {:<16} Run: {:>6.0}s  Pats: {:>4}  Peak: {:>3.0}%  Avg: {:>3.0}%  Timeouts: {:>3}  Failed sends: {}/{}",
+            total.label, total.device, total.run_time, total.pats, total.peak_intensity, total.average_intensity,
+            total.timeouts, total.send_failures, total.sends
+        );
+    }
+}
+
+// `history [--csv <file>] [--json <file>]`
+pub(crate) fn run_command(csv: Option<&Path>, json: Option<&Path>) -> Result<(), String> {
+    let sessions = load_history(&paths::history_dir())?;
+    if csv.is_none() && json.is_none() {
+        print_summary(&sessions);
+    }
+    let export = |path: &Path, contents: String| {
+        fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        println!("Exported {} sessions to {}", sessions.len(), path.display());
+        Ok::<(), String>(())
+    };
+    if let Some(path) = csv {
+        export(path, to_csv(&sessions))?;
+    }
+    if let Some(path) = json {
+        export(path, serde_json::to_string_pretty(&sessions).map_err(|e| e.to_string())?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_time_and_average_follow_the_output() {
+        let start = Instant::now();
+        let mut session = DeviceSession::default();
+        session.output(0.5, start);
+        session.output(1.0, start + Duration::from_secs(2));
+        session.output(0.0, start + Duration::from_secs(4));
+        // Idle time does not count
+        session.output(0.0, start + Duration::from_secs(10));
+        assert!((session.run_time - 4.0).abs() < 1e-6);
+        assert!((session.average() - 0.75).abs() < 1e-6);
+        assert_eq!(session.peak, 1.0);
+    }
+
+    #[test]
+    fn csv_has_one_row_per_device_and_session() {
+        let session = SessionRecord {
+            start: "2024-01-01T20:00:00+00:00".to_string(),
+            end: "2024-01-01T21:00:00+00:00".to_string(),
+            devices: vec![DeviceStats { device: "192.168.1.69".to_string(), label: "head, left".to_string(), pats: 3, ..DeviceStats::default() }],
+        };
+        let csv = to_csv(&[session.clone(), session]);
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], "2024-01-01T20:00:00+00:00,2024-01-01T21:00:00+00:00,192.168.1.69,\"head, left\",0.0,3,0.0,0.0,0,0,0");
+    }
+}
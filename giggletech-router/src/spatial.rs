@@ -0,0 +1,139 @@
+/*
+    spatial.rs - Spatial Panning Across Group Members
+
+    A group sends the same signal to every member. With a `layout`, each member has a position and
+    the group keeps track of where the contact is. Every member is then driven with a gain that falls
+    off with its distance to the contact, so a pat moving from left to right travels across the pucks.
+
+    **Parameters (group `layout` map):**
+
+    - `spread`: Distance from the contact at which a member's gain reaches 0 (default 1.0, in the
+      units of the member positions).
+    - `position_parameters`: One or two avatar parameters (x, then y) giving the contact position.
+    - `directions`: One position per `proximity_parameter` of the group. The contact position is the
+      average of these positions weighted by each parameter's proximity.
+
+    Members take a `position: [x, y]` (or `[x]` for a left-right row).
+
+    **Key Features:**
+
+    1. **Contact Position (`handle_position_parameter`, `update_from_inputs`)**:
+       - Position parameters set the contact position directly. Directional proximity parameters
+         move it towards the strongest contact. With no contact the last position is kept, so fades
+         and releases stay where the contact ended.
+
+    2. **Distance Gains (`member_gain`)**:
+       - Linear fall-off: `1 - distance / spread`, clamped to 0..1. Until a position is known every
+         member gets full gain.
+
+    **Usage**:
+    - `motor_output::send_targets` multiplies each member's gain with its `member_gain`.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+
+use crate::config::{DeviceConfig, GroupMember};
+use crate::input_combiner;
+
+lazy_static! {
+    static ref DEVICE_PAN_POSITION: Arc<Mutex<HashMap<String, [f32; 2]>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PanSource {
+    // Avatar parameters for x and optionally y
+    Position(Arc<String>, Option<Arc<String>>),
+    // One position per proximity parameter
+    Directions(Arc<Vec<[f32; 2]>>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PanLayout {
+    pub spread: f32,
+    pub source: PanSource
+}
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+}
+
+fn gain(position: [f32; 2], member: [f32; 2], spread: f32) -> f32 {
+    (1.0 - distance(position, member) / spread).clamp(0.0, 1.0)
+}
+
+// Proximity-weighted average of the directions, None without contact
+fn centroid(values: &[f32], directions: &[[f32; 2]]) -> Option<[f32; 2]> {
+    let (x, y, total) = values.iter().zip(directions.iter()).fold((0.0, 0.0, 0.0), |(x, y, total), (value, direction)| {
+        let weight = value.max(0.0);
+        (x + direction[0] * weight, y + direction[1] * weight, total + weight)
+    });
+    if total > 0.0 { Some([x / total, y / total]) } else { None }
+}
+
+fn set_position(device: &DeviceConfig, update: impl FnOnce(&mut [f32; 2])) {
+    if let Ok(mut device_pan_position) = DEVICE_PAN_POSITION.lock() {
+        update(device_pan_position.entry(device.device_uri.to_string()).or_insert([0.0, 0.0]));
+    }
+}
+
+// A contact position parameter was received
+pub(crate) fn handle_position_parameter(device: &DeviceConfig, address: &str, value: f32) {
+    if let Some(PanLayout { source: PanSource::Position(x_param, y_param), .. }) = &device.layout {
+        if address == **x_param {
+            set_position(device, |position| position[0] = value);
+        } else if matches!(y_param, Some(param) if address == **param) {
+            set_position(device, |position| position[1] = value);
+        }
+    }
+}
+
+// Move the contact position after a directional proximity parameter changed
+pub(crate) fn update_from_inputs(device: &DeviceConfig) {
+    if let Some(PanLayout { source: PanSource::Directions(directions), .. }) = &device.layout {
+        if let Some(centroid) = centroid(&input_combiner::input_values(device), directions) {
+            set_position(device, |position| *position = centroid);
+        }
+    }
+}
+
+// Gain of a group member for the current contact position
+pub(crate) fn member_gain(device: &DeviceConfig, member: &GroupMember) -> f32 {
+    let (layout, member_position) = match (&device.layout, member.position) {
+        (Some(layout), Some(member_position)) => (layout, member_position),
+        _ => return 1.0,
+    };
+    DEVICE_PAN_POSITION.lock().ok()
+        .and_then(|device_pan_position| device_pan_position.get(device.device_uri.as_str()).copied())
+        .map(|position| gain(position, member_position, layout.spread))
+        .unwrap_or(1.0)
+}
+
+// Forgets the contact position of a removed group
+pub(crate) fn remove(device: &DeviceConfig) {
+    if let Ok(mut device_pan_position) = DEVICE_PAN_POSITION.lock() {
+        device_pan_position.remove(device.device_uri.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_falls_off_with_distance() {
+        assert_eq!(gain([0.0, 0.0], [0.0, 0.0], 1.0), 1.0);
+        assert!((gain([0.5, 0.0], [0.0, 0.0], 1.0) - 0.5).abs() < 1e-6);
+        assert_eq!(gain([2.0, 0.0], [0.0, 0.0], 1.0), 0.0);
+    }
+
+    #[test]
+    fn centroid_follows_the_strongest_contact() {
+        let directions = [[-1.0, 0.0], [1.0, 0.0]];
+        assert_eq!(centroid(&[1.0, 0.0], &directions), Some([-1.0, 0.0]));
+        assert_eq!(centroid(&[0.5, 0.5], &directions), Some([0.0, 0.0]));
+        assert_eq!(centroid(&[0.0, 0.0], &directions), None);
+    }
+}
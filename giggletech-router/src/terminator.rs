@@ -7,7 +7,9 @@
     **Key Features:**
 
     1. **Start Worker (`start`)**:
-       - Spawns a worker task that continuously sends a stop signal (`0`) to a device every second.
+       - Spawns a worker task that continuously sends a stop signal (`0`) to a device (or all group members) every second.
+       - The stop ends the live output only (`motor_output::send_live_stop`), a pattern triggered while nobody
+         is touching keeps playing.
        - Ensures the worker is not started if it’s already running by checking the `AtomicBool`.
 
     2. **Stop Worker (`stop`)**:
@@ -31,26 +33,27 @@ use async_osc::{Result};
 use async_std::{task::{self},sync::Arc,};
 use std::{ time::{Duration, }};
 use std::sync::atomic::{AtomicBool, Ordering};
-use crate::giggletech_osc;
+use crate::config::DeviceConfig;
+use crate::motor_output;
 
 
- pub(crate) async fn start(running: Arc<AtomicBool>, device_ip: &Arc<String>) -> Result<()> {
+ pub(crate) async fn start(running: Arc<AtomicBool>, device: &DeviceConfig) -> Result<()> {
     if running.load(Ordering::SeqCst) {
         //return Err("Worker is already running".into());
     }
     let worker_running = running.clone();
-    let worker_device_ip = device_ip.clone();
+    let worker_device = device.clone();
     task::spawn(async move {
-        worker(worker_running, worker_device_ip).await.unwrap();
+        worker(worker_running, worker_device).await.unwrap();
     });
     running.store(true, Ordering::SeqCst);
     Ok(())
 }
 
-async fn worker(running: Arc<AtomicBool>, device_ip: Arc<String>) -> Result<()> {
+async fn worker(running: Arc<AtomicBool>, device: DeviceConfig) -> Result<()> {
     while running.load(Ordering::Relaxed) {
         //println!("Worker is running");
-        giggletech_osc::send_data(&device_ip, 0i32).await?;
+        motor_output::send_live_stop(&device).await?;
         task::sleep(Duration::from_secs(1)).await;
     }
     //println!("Worker stopped");